@@ -1,3 +1,6 @@
+pub mod portfolios;
+pub mod signals;
+pub mod stats;
 pub mod utilities;
 
 // #[cfg(test)]
@@ -0,0 +1,230 @@
+use super::sorts::{Breakpoints, Rebalance, SortConfig};
+use anyhow::{anyhow, Result};
+
+/// How stocks are weighted within a portfolio.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Weighting {
+    /// Weights proportional to the lagged market equity.
+    #[default]
+    Value,
+    /// Equal weights.
+    Equal,
+}
+
+/// Filter applied to the universe before sorting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Screen {
+    /// Drop stocks whose absolute price is below the given level (e.g. 5 dollars).
+    MinPrice(f64),
+    /// Drop stocks below the NYSE 20th percentile of market equity.
+    ExcludeMicroCaps,
+}
+
+/// Settings of an anomaly test. Build it with [`AnomalyConfig::builder`]; the defaults
+/// follow the academic convention:
+///
+/// * decile portfolios, value-weighted, with NYSE breakpoints,
+/// * portfolios held from the month after the signal,
+/// * monthly rebalancing with a one-month holding period,
+/// * no minimum number of stocks per portfolio,
+/// * no transaction costs and no screens,
+/// * returns aggregated as simple returns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyConfig {
+    /// Number of portfolios to form each month.
+    pub n_portfolios: usize,
+    /// Weighting of the stocks within each portfolio.
+    pub weighting: Weighting,
+    /// Compute the breakpoints from NYSE stocks only.
+    pub nyse_breakpoints: bool,
+    /// Percentiles at which the portfolios are cut.
+    pub breakpoints: Breakpoints,
+    /// Months between the signal and the first holding month, see
+    /// [`SortConfig::formation_lag`].
+    pub formation_lag: usize,
+    /// How often portfolios are re-formed.
+    pub rebalance: Rebalance,
+    /// Number of months each formation is held.
+    pub holding_period: usize,
+    /// Minimum number of stocks for a portfolio return to be reported, NaN otherwise.
+    pub min_stocks: usize,
+    /// One-way transaction cost in basis points, applied to the turnover.
+    pub cost_bps: f64,
+    /// Universe filters applied before sorting.
+    pub screens: Vec<Screen>,
+    /// Aggregate returns over time as log returns, `ln(1 + r)`, and convert the results
+    /// back to simple returns for reporting.
+    pub log_returns: bool,
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        AnomalyConfig {
+            n_portfolios: 10,
+            weighting: Weighting::Value,
+            nyse_breakpoints: true,
+            breakpoints: Breakpoints::Equal,
+            formation_lag: 1,
+            rebalance: Rebalance::Monthly,
+            holding_period: 1,
+            min_stocks: 0,
+            cost_bps: 0.0,
+            screens: Vec::new(),
+            log_returns: false,
+        }
+    }
+}
+
+impl AnomalyConfig {
+    /// Starts a builder from the default settings.
+    pub fn builder() -> AnomalyConfigBuilder {
+        AnomalyConfigBuilder::default()
+    }
+
+    /// Settings of the underlying univariate sort.
+    pub fn sort_config(&self) -> SortConfig {
+        SortConfig {
+            n_portfolios: self.n_portfolios,
+            nyse_breakpoints: self.nyse_breakpoints,
+            breakpoints: self.breakpoints.clone(),
+            formation_lag: self.formation_lag,
+            ..Default::default()
+        }
+    }
+}
+
+/// Builder of an [`AnomalyConfig`].
+#[derive(Debug, Clone, Default)]
+pub struct AnomalyConfigBuilder {
+    config: AnomalyConfig,
+}
+
+impl AnomalyConfigBuilder {
+    pub fn n_portfolios(mut self, n_portfolios: usize) -> Self {
+        self.config.n_portfolios = n_portfolios;
+        self
+    }
+
+    pub fn weighting(mut self, weighting: Weighting) -> Self {
+        self.config.weighting = weighting;
+        self
+    }
+
+    pub fn nyse_breakpoints(mut self, nyse_breakpoints: bool) -> Self {
+        self.config.nyse_breakpoints = nyse_breakpoints;
+        self
+    }
+
+    pub fn breakpoints(mut self, breakpoints: Breakpoints) -> Self {
+        self.config.breakpoints = breakpoints;
+        self
+    }
+
+    pub fn formation_lag(mut self, formation_lag: usize) -> Self {
+        self.config.formation_lag = formation_lag;
+        self
+    }
+
+    pub fn rebalance(mut self, rebalance: Rebalance) -> Self {
+        self.config.rebalance = rebalance;
+        self
+    }
+
+    pub fn holding_period(mut self, holding_period: usize) -> Self {
+        self.config.holding_period = holding_period;
+        self
+    }
+
+    pub fn min_stocks(mut self, min_stocks: usize) -> Self {
+        self.config.min_stocks = min_stocks;
+        self
+    }
+
+    pub fn cost_bps(mut self, cost_bps: f64) -> Self {
+        self.config.cost_bps = cost_bps;
+        self
+    }
+
+    pub fn log_returns(mut self, log_returns: bool) -> Self {
+        self.config.log_returns = log_returns;
+        self
+    }
+
+    /// Adds a screen to the ones already set.
+    pub fn screen(mut self, screen: Screen) -> Self {
+        self.config.screens.push(screen);
+        self
+    }
+
+    /// Validates the settings and returns the configuration.
+    pub fn build(self) -> Result<AnomalyConfig> {
+        let config = self.config;
+        if config.n_portfolios < 2 {
+            return Err(anyhow!("At least two portfolios are required"));
+        }
+        config.breakpoints.percentiles(config.n_portfolios)?;
+        if config.formation_lag == 0 {
+            return Err(anyhow!("The formation lag must be at least one month"));
+        }
+        if config.holding_period == 0 {
+            return Err(anyhow!("The holding period must be at least one month"));
+        }
+        if !(config.cost_bps.is_finite() && config.cost_bps >= 0.0) {
+            return Err(anyhow!(
+                "Transaction costs must be non-negative, got {} bps",
+                config.cost_bps
+            ));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_defaults_and_overrides() {
+        let default = AnomalyConfig::builder().build().unwrap();
+        assert_eq!(default, AnomalyConfig::default());
+        assert_eq!(default.n_portfolios, 10);
+        assert_eq!(default.weighting, Weighting::Value);
+        assert!(default.nyse_breakpoints);
+        assert_eq!(default.rebalance, Rebalance::Monthly);
+        assert_eq!(default.holding_period, 1);
+        assert!(default.screens.is_empty());
+        assert_eq!(default.formation_lag, 1);
+        assert!(!default.log_returns);
+
+        let config = AnomalyConfig::builder()
+            .n_portfolios(5)
+            .weighting(Weighting::Equal)
+            .cost_bps(20.0)
+            .breakpoints(Breakpoints::Custom(vec![0.3, 0.2, 0.2, 0.2, 0.1]))
+            .formation_lag(2)
+            .log_returns(true)
+            .screen(Screen::MinPrice(5.0))
+            .screen(Screen::ExcludeMicroCaps)
+            .build()
+            .unwrap();
+        assert_eq!(config.n_portfolios, 5);
+        let sort_config = config.sort_config();
+        assert_eq!(sort_config.n_portfolios, 5);
+        assert_eq!(sort_config.breakpoints, config.breakpoints);
+        assert_eq!(sort_config.formation_lag, 2);
+        assert!(config.log_returns);
+        assert_eq!(
+            config.screens,
+            vec![Screen::MinPrice(5.0), Screen::ExcludeMicroCaps]
+        );
+        assert!(config.nyse_breakpoints);
+
+        assert!(AnomalyConfig::builder().n_portfolios(1).build().is_err());
+        assert!(AnomalyConfig::builder().holding_period(0).build().is_err());
+        assert!(AnomalyConfig::builder().formation_lag(0).build().is_err());
+        assert!(AnomalyConfig::builder()
+            .breakpoints(Breakpoints::Custom(vec![0.5, 0.5]))
+            .build()
+            .is_err());
+    }
+}
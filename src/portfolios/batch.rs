@@ -0,0 +1,201 @@
+use super::returns::PortfolioResult;
+use super::sorts::{univariate_sort, SortConfig};
+use crate::utilities::crsp_matrices::CrspMatrices;
+use anyhow::{anyhow, Result};
+use ndarray::{s, Array2, Axis};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// Sorts stocks on a signal and forms the resulting portfolios.
+///
+/// The portfolios are value-weighted when `crsp.me` is provided and equal-weighted
+/// otherwise. Permnos pruned by the sort are left unassigned, so the assignments keep
+/// the columns of `crsp`. With a `config.formation_lag` above 1, the assignments are
+/// delayed by `formation_lag - 1` months, and so are the ME weights taken at formation.
+///
+/// # Arguments
+///
+/// * `signal` - Sorting variable (nMonths x nStocks), aligned with `crsp`.
+/// * `crsp` - CRSP matrices.
+/// * `config` - Sort configuration.
+pub fn sort_portfolios(
+    signal: &Array2<f64>,
+    crsp: &CrspMatrices,
+    config: &SortConfig,
+) -> Result<PortfolioResult> {
+    crsp.validate()?;
+    if config.formation_lag == 0 {
+        return Err(anyhow!(
+            "The formation lag must be at least one month to avoid look-ahead"
+        ));
+    }
+    let sort = univariate_sort(signal, crsp.exchcd.as_ref(), &crsp.permno, config)?;
+    let mut assignments = Array2::zeros(signal.dim());
+    for (k, &j) in sort.columns.iter().enumerate() {
+        assignments
+            .column_mut(j)
+            .assign(&sort.assignments.index_axis(Axis(1), k));
+    }
+    // Returns on row t come from the assignments of row t - 1, so a lag of L holds the
+    // signal of row t from the assignments of row t + L - 1
+    let delay = (config.formation_lag - 1).min(assignments.nrows());
+    if delay > 0 {
+        let mut delayed = Array2::zeros(assignments.dim());
+        let n_months = assignments.nrows();
+        delayed
+            .slice_mut(s![delay.., ..])
+            .assign(&assignments.slice(s![..n_months - delay, ..]));
+        assignments = delayed;
+    }
+    PortfolioResult::new(
+        assignments,
+        &crsp.dates,
+        &crsp.ret,
+        crsp.me.as_ref(),
+        config.n_portfolios,
+    )
+}
+
+/// Runs [`sort_portfolios`] for a batch of signals in parallel.
+///
+/// # Arguments
+///
+/// * `signals` - Signals keyed by name, each aligned with `crsp`.
+/// * `crsp` - CRSP matrices shared by all the sorts.
+/// * `config` - Sort configuration shared by all the sorts.
+///
+/// # Returns
+///
+/// * `Result<HashMap<String, PortfolioResult>>` - The portfolios of each signal, or the
+///   first error encountered.
+pub fn batch_sort(
+    signals: &HashMap<String, Array2<f64>>,
+    crsp: &CrspMatrices,
+    config: &SortConfig,
+) -> Result<HashMap<String, PortfolioResult>> {
+    signals
+        .par_iter()
+        .map(|(name, signal)| Ok((name.clone(), sort_portfolios(signal, crsp, config)?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signals::momentum::make_momentum;
+
+    #[test]
+    fn test_batch_sort_matches_individual_runs() {
+        let (n_months, n_stocks) = (12, 8);
+        let crsp = CrspMatrices {
+            dates: Array2::from_shape_fn((n_months, 1), |(t, _)| 200001 + t as i32),
+            permno: Array2::from_shape_fn((n_stocks, 1), |(j, _)| 10001 + j as i32),
+            ret: Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+                0.01 * ((t + 2 * j) % 5) as f64 - 0.02
+            }),
+            me: Some(Array2::from_shape_fn((n_months, n_stocks), |(_, j)| {
+                (j + 1) as f64
+            })),
+            exchcd: None,
+        };
+        let config = SortConfig {
+            n_portfolios: 2,
+            nyse_breakpoints: false,
+            ..Default::default()
+        };
+        let signals = HashMap::from([
+            (
+                "size".to_string(),
+                Array2::from_shape_fn((n_months, n_stocks), |(_, j)| j as f64),
+            ),
+            (
+                "reversal".to_string(),
+                Array2::from_shape_fn((n_months, n_stocks), |(t, j)| ((t * 3 + j) % 7) as f64),
+            ),
+        ]);
+
+        let results = batch_sort(&signals, &crsp, &config).unwrap();
+
+        assert_eq!(results.len(), 2);
+        for (name, signal) in &signals {
+            let individual = sort_portfolios(signal, &crsp, &config).unwrap();
+            let batch = &results[name];
+            assert_eq!(batch.assignments, individual.assignments);
+            assert!(batch
+                .returns
+                .iter()
+                .zip(individual.returns.iter())
+                .all(|(a, b)| a == b || (a.is_nan() && b.is_nan())));
+        }
+    }
+
+    #[test]
+    fn test_formation_lag_delays_holding() {
+        let (n_months, n_stocks) = (6, 4);
+        let crsp = CrspMatrices {
+            dates: Array2::from_shape_fn((n_months, 1), |(t, _)| 200001 + t as i32),
+            permno: Array2::from_shape_fn((n_stocks, 1), |(j, _)| 10001 + j as i32),
+            ret: Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+                0.01 * (t * n_stocks + j) as f64
+            }),
+            me: None,
+            exchcd: None,
+        };
+        // Only the signal of month 1 (row 0) is observed, stocks 2 and 3 in the top half
+        let mut signal = Array2::from_elem((n_months, n_stocks), f64::NAN);
+        signal
+            .row_mut(0)
+            .assign(&ndarray::array![1.0, 2.0, 3.0, 4.0]);
+        let config = SortConfig {
+            n_portfolios: 2,
+            nyse_breakpoints: false,
+            formation_lag: 2,
+            ..Default::default()
+        };
+
+        let result = sort_portfolios(&signal, &crsp, &config).unwrap();
+
+        assert_eq!(result.warmup_months, 2);
+        // The month-3 return of the top portfolio is the mean over stocks 2 and 3
+        let top = (crsp.ret[[2, 2]] + crsp.ret[[2, 3]]) / 2.0;
+        assert!((result.returns[[2, 1]] - top).abs() < 1e-12);
+        assert!(result.returns[[3, 1]].is_nan());
+
+        let config = SortConfig {
+            formation_lag: 0,
+            ..config
+        };
+        assert!(sort_portfolios(&signal, &crsp, &config).is_err());
+    }
+
+    #[test]
+    fn test_momentum_sort_warmup_matches_lookback() {
+        let (n_months, n_stocks) = (30, 10);
+        let crsp = CrspMatrices {
+            dates: Array2::from_shape_fn((n_months, 1), |(t, _)| {
+                crate::utilities::matrix_ops::add_months(200001, t as i32)
+            }),
+            permno: Array2::from_shape_fn((n_stocks, 1), |(j, _)| 10001 + j as i32),
+            ret: Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+                0.01 * ((t * 3 + j * 7) % 9) as f64 - 0.04
+            }),
+            me: None,
+            exchcd: None,
+        };
+        let config = SortConfig {
+            n_portfolios: 2,
+            nyse_breakpoints: false,
+            ..Default::default()
+        };
+        let momentum = make_momentum(&crsp.ret, 11, 1);
+
+        let result = sort_portfolios(&momentum, &crsp, &config).unwrap();
+
+        // The first signal is formed at the end of month 12 (row 11) and earns returns
+        // from row 12, the 12-month lookback of R12-1
+        assert_eq!(result.warmup_months, 12);
+        assert_eq!(result.live_returns().nrows(), n_months - 12);
+        assert!(result.live_returns().iter().all(|r| r.is_finite()));
+        assert_eq!(result.live_dates()[[0, 0]], 200101);
+    }
+}
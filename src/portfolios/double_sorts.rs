@@ -0,0 +1,88 @@
+use super::returns::{long_short_spread, portfolio_returns};
+use super::sorts::{assign_portfolios, compute_breakpoints};
+use ndarray::{Array1, Array2, Zip};
+
+/// Number of signal portfolios formed within each size tercile.
+pub const SIGNAL_PORTFOLIOS_PER_TERCILE: usize = 5;
+
+/// Long-short spread of an anomaly computed separately within small, mid and large caps.
+///
+/// Each month stocks are sorted into size terciles on `me` using NYSE breakpoints, then
+/// within each tercile into [`SIGNAL_PORTFOLIOS_PER_TERCILE`] portfolios on the signal,
+/// again with NYSE breakpoints computed over the tercile. The portfolios are
+/// value-weighted by ME at formation, and the spread is the return of the highest signal
+/// portfolio minus the lowest. Comparing the three spreads shows whether the effect is
+/// concentrated in small caps.
+///
+/// # Arguments
+///
+/// * `signal` - Sorting variable (nMonths x nStocks).
+/// * `ret` - Returns (nMonths x nStocks).
+/// * `me` - Market equity at formation (nMonths x nStocks).
+/// * `exchcd` - Exchange codes (nMonths x nStocks).
+///
+/// # Returns
+///
+/// * `[Array1<f64>; 3]` - Monthly spreads of the small, mid and large terciles. Row t is
+///   the return over month t of the portfolios formed at the end of month t-1.
+pub fn spread_by_size_tercile(
+    signal: &Array2<f64>,
+    ret: &Array2<f64>,
+    me: &Array2<f64>,
+    exchcd: &Array2<i16>,
+) -> [Array1<f64>; 3] {
+    assert!(
+        ret.dim() == signal.dim() && me.dim() == signal.dim() && exchcd.dim() == signal.dim(),
+        "Signal, returns, ME and exchcd must have the same dimensions"
+    );
+    let size = me.mapv(|x| if x > 0.0 { x } else { f64::NAN });
+    let size_breakpoints = compute_breakpoints(&size, Some(exchcd), &[1.0 / 3.0, 2.0 / 3.0]);
+    let terciles = assign_portfolios(&size, &size_breakpoints);
+
+    let n = SIGNAL_PORTFOLIOS_PER_TERCILE;
+    let percentiles: Vec<f64> = (1..n).map(|i| i as f64 / n as f64).collect();
+    [1, 2, 3].map(|tercile| {
+        let mut within = signal.clone();
+        Zip::from(&mut within).and(&terciles).for_each(|x, &g| {
+            if g != tercile {
+                *x = f64::NAN;
+            }
+        });
+        let breakpoints = compute_breakpoints(&within, Some(exchcd), &percentiles);
+        let assignments = assign_portfolios(&within, &breakpoints);
+        let returns = portfolio_returns(&assignments, ret, Some(me), n);
+        long_short_spread(&returns)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::time_series::nan_mean;
+
+    #[test]
+    fn test_spread_by_size_tercile_small_cap_effect() {
+        let (n_months, n_stocks) = (36, 60);
+        let me = Array2::from_shape_fn((n_months, n_stocks), |(_, j)| (j + 1) as f64);
+        let exchcd = Array2::from_elem((n_months, n_stocks), 1i16);
+        let signal = Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+            ((t * 7 + j * 13) % 17) as f64
+        });
+        // Noise shared by all stocks, plus a signal premium for the 20 smallest stocks
+        let ret = Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+            let noise = 0.01 * ((t * 5 + j * 3) % 7) as f64 - 0.03;
+            if t > 0 && j < 20 {
+                noise + 0.002 * signal[[t - 1, j]]
+            } else {
+                noise
+            }
+        });
+
+        let [small, mid, large] = spread_by_size_tercile(&signal, &ret, &me, &exchcd);
+
+        assert!(small[0].is_nan());
+        let (small, mid, large) = (nan_mean(&small), nan_mean(&mid), nan_mean(&large));
+        assert!(small > 0.01);
+        assert!(small > mid.abs() && small > large.abs());
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{anyhow, Context, Result};
+use ndarray::Array2;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Missing value code used in the Fama-French data library files.
+const FF_MISSING: f64 = -99.99;
+
+/// Builds the Fama-French style column labels for `n_portfolios` sorted portfolios.
+///
+/// Deciles are labelled `Lo 10, Dec 2, ..., Dec 9, Hi 10`, quintiles
+/// `Lo 20, Qnt 2, ..., Hi 20`, terciles `Lo 30, Med 40, Hi 30` and any other
+/// number of portfolios `Lo, P2, ..., Hi`.
+pub fn ff_column_labels(n_portfolios: usize) -> Vec<String> {
+    match n_portfolios {
+        3 => vec![
+            "Lo 30".to_string(),
+            "Med 40".to_string(),
+            "Hi 30".to_string(),
+        ],
+        5 | 10 => {
+            let (pct, mid) = if n_portfolios == 5 {
+                (20, "Qnt")
+            } else {
+                (10, "Dec")
+            };
+            (1..=n_portfolios)
+                .map(|i| match i {
+                    1 => format!("Lo {}", pct),
+                    i if i == n_portfolios => format!("Hi {}", pct),
+                    i => format!("{} {}", mid, i),
+                })
+                .collect()
+        }
+        _ => (1..=n_portfolios)
+            .map(|i| match i {
+                1 => "Lo".to_string(),
+                i if i == n_portfolios => "Hi".to_string(),
+                i => format!("P{}", i),
+            })
+            .collect(),
+    }
+}
+
+/// Exports portfolio returns in the CSV layout of the Fama-French data library.
+///
+/// The first column holds the `YYYYMM` date and has an empty header, followed by one
+/// column per portfolio and a final `Lo-Hi` spread column, the return of the lowest
+/// portfolio minus that of the highest. Returns are written in percent with two decimals
+/// and missing values are coded as `-99.99`, as in the published files.
+///
+/// # Arguments
+///
+/// * `port_returns` - Portfolio returns in decimals (nMonths x nPortfolios).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1).
+/// * `path` - Path of the CSV file to create.
+pub fn export_ff_style_csv(
+    port_returns: &Array2<f64>,
+    dates: &Array2<i32>,
+    path: &Path,
+) -> Result<()> {
+    if port_returns.nrows() != dates.len() {
+        return Err(anyhow!(
+            "Portfolio returns have {} rows but there are {} dates",
+            port_returns.nrows(),
+            dates.len()
+        ));
+    }
+    let n_portfolios = port_returns.ncols();
+    if n_portfolios < 2 {
+        return Err(anyhow!(
+            "At least two portfolios are required for a Lo-Hi column"
+        ));
+    }
+
+    let file =
+        File::create(path).with_context(|| format!("Failed to create CSV file: {:?}", path))?;
+    let mut writer = BufWriter::new(file);
+
+    let mut header = ff_column_labels(n_portfolios);
+    header.push("Lo-Hi".to_string());
+    writeln!(writer, ",{}", header.join(","))?;
+
+    for (row, date) in port_returns.outer_iter().zip(dates.iter()) {
+        let spread = row[0] - row[n_portfolios - 1];
+        let values: Vec<String> = row
+            .iter()
+            .chain(std::iter::once(&spread))
+            .map(|&r| {
+                let pct = if r.is_finite() { 100.0 * r } else { FF_MISSING };
+                format!("{:.2}", pct)
+            })
+            .collect();
+        writeln!(writer, "{},{}", date, values.join(","))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+    use std::io::Read;
+
+    #[test]
+    fn test_ff_column_labels() {
+        let deciles = ff_column_labels(10);
+        assert_eq!(deciles.first().unwrap(), "Lo 10");
+        assert_eq!(deciles[1], "Dec 2");
+        assert_eq!(deciles.last().unwrap(), "Hi 10");
+        assert_eq!(ff_column_labels(5)[2], "Qnt 3");
+        assert_eq!(ff_column_labels(3), vec!["Lo 30", "Med 40", "Hi 30"]);
+    }
+
+    #[test]
+    fn test_export_ff_style_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("portfolios.csv");
+        let port_returns = array![[0.01, 0.02, 0.03], [f64::NAN, -0.015, 0.005]];
+        let dates = array![[192607], [192608]];
+
+        export_ff_style_csv(&port_returns, &dates, &path).unwrap();
+
+        let mut csv = String::new();
+        File::open(&path).unwrap().read_to_string(&mut csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], ",Lo 30,Med 40,Hi 30,Lo-Hi");
+        assert_eq!(lines[1], "192607,1.00,2.00,3.00,-2.00");
+        assert_eq!(lines[2], "192608,-99.99,-1.50,0.50,-99.99");
+    }
+}
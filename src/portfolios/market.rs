@@ -0,0 +1,147 @@
+use super::weights::lag_me;
+use crate::stats::cross_section::value_weighted_average;
+use ndarray::{Array1, Array2, Axis};
+
+/// Value-weighted market return: each month's returns weighted by the previous month's
+/// market equity (see [`lag_me`]). The first month has no lagged weights and is NaN.
+///
+/// # Arguments
+///
+/// * `ret` - Returns (nMonths x nStocks).
+/// * `me` - Market equity (nMonths x nStocks), unlagged.
+pub fn make_market_return(ret: &Array2<f64>, me: &Array2<f64>) -> Array1<f64> {
+    assert_eq!(
+        ret.dim(),
+        me.dim(),
+        "Returns and ME must have the same dimensions"
+    );
+    value_weighted_average(ret, &lag_me(me))
+}
+
+/// Equal-weighted market return: the simple cross-sectional mean of all valid returns
+/// each month. It tilts towards small caps relative to [`make_market_return`]. Months
+/// without any valid return are NaN.
+pub fn make_equal_weighted_market(ret: &Array2<f64>) -> Array1<f64> {
+    ret.axis_iter(Axis(0))
+        .map(|row| {
+            let (sum, n) = row
+                .iter()
+                .filter(|r| r.is_finite())
+                .fold((0.0, 0usize), |(sum, n), r| (sum + r, n + 1));
+            if n == 0 {
+                f64::NAN
+            } else {
+                sum / n as f64
+            }
+        })
+        .collect()
+}
+
+/// Comparison of a constructed market return with CRSP's value-weighted index.
+#[derive(Debug, Clone)]
+pub struct ReconReport {
+    /// Per-period difference, computed minus CRSP (NaN when either is missing).
+    pub differences: Array1<f64>,
+    /// Number of periods where both series are available.
+    pub n_months: usize,
+    /// Correlation of the two series over those periods.
+    pub correlation: f64,
+    /// Mean absolute difference over those periods.
+    pub mean_abs_diff: f64,
+    /// Largest absolute difference over those periods.
+    pub max_abs_diff: f64,
+}
+
+/// Reconciles a constructed value-weighted market return with CRSP's `vwretd` from the
+/// MSI file. A low correlation or a large mean absolute difference points to a problem
+/// with the universe or the filters used to build the market return.
+///
+/// # Arguments
+///
+/// * `computed_mkt` - Market return built from the sample, e.g. [`make_market_return`].
+/// * `crsp_vwretd` - CRSP value-weighted return including distributions, same periods.
+pub fn reconcile_vwretd(computed_mkt: &Array1<f64>, crsp_vwretd: &Array1<f64>) -> ReconReport {
+    assert_eq!(
+        computed_mkt.len(),
+        crsp_vwretd.len(),
+        "Both market returns must cover the same periods"
+    );
+    let differences = computed_mkt - crsp_vwretd;
+    let both: Vec<usize> = (0..differences.len())
+        .filter(|&t| differences[t].is_finite())
+        .collect();
+    let n = both.len();
+    if n == 0 {
+        return ReconReport {
+            differences,
+            n_months: 0,
+            correlation: f64::NAN,
+            mean_abs_diff: f64::NAN,
+            max_abs_diff: f64::NAN,
+        };
+    }
+
+    let mean = |x: &Array1<f64>| both.iter().map(|&t| x[t]).sum::<f64>() / n as f64;
+    let (mean_a, mean_b) = (mean(computed_mkt), mean(crsp_vwretd));
+    let (mut cov, mut var_a, mut var_b) = (0.0, 0.0, 0.0);
+    for &t in &both {
+        let (a, b) = (computed_mkt[t] - mean_a, crsp_vwretd[t] - mean_b);
+        cov += a * b;
+        var_a += a * a;
+        var_b += b * b;
+    }
+    let abs_diffs = both.iter().map(|&t| differences[t].abs());
+    ReconReport {
+        n_months: n,
+        correlation: cov / (var_a * var_b).sqrt(),
+        mean_abs_diff: abs_diffs.clone().sum::<f64>() / n as f64,
+        max_abs_diff: abs_diffs.fold(0.0, f64::max),
+        differences,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_make_equal_weighted_market_is_cross_sectional_mean() {
+        let ret = array![
+            [0.01, 0.03, f64::NAN],
+            [-0.02, 0.04, 0.01],
+            [f64::NAN, f64::NAN, f64::NAN]
+        ];
+
+        let ew = make_equal_weighted_market(&ret);
+
+        assert!((ew[0] - 0.02).abs() < 1e-12);
+        assert!((ew[1] - 0.01).abs() < 1e-12);
+        assert!(ew[2].is_nan());
+    }
+
+    #[test]
+    fn test_make_market_return_uses_lagged_me() {
+        let ret = array![[0.10, 0.20], [0.01, 0.04]];
+        let me = array![[300.0, 100.0], [1.0, 1000.0]];
+
+        let vw = make_market_return(&ret, &me);
+
+        assert!(vw[0].is_nan());
+        assert!((vw[1] - 0.0175).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_reconcile_vwretd_matching_series() {
+        let crsp = array![0.012, -0.034, 0.021, 0.005, f64::NAN];
+        let computed = array![f64::NAN, -0.034, 0.021, 0.005, 0.01];
+
+        let report = reconcile_vwretd(&computed, &crsp);
+
+        assert_eq!(report.n_months, 3);
+        assert!((report.correlation - 1.0).abs() < 1e-12);
+        assert!(report.mean_abs_diff.abs() < 1e-15);
+        assert!(report.max_abs_diff.abs() < 1e-15);
+        assert!(report.differences[0].is_nan());
+    }
+}
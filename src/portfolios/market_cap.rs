@@ -0,0 +1,143 @@
+use super::sorts::compute_breakpoints;
+use ndarray::Array2;
+
+/// NYSE percentiles separating micro caps from small caps and small caps from large caps,
+/// following Fama and French (2008).
+pub const MICRO_CAP_PERCENTILE: f64 = 0.2;
+pub const SMALL_CAP_PERCENTILE: f64 = 0.5;
+
+/// Market-cap distribution of the sample over one decade, averaged across its months.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecadeMeSummary {
+    /// First year of the decade (e.g. 1990).
+    pub decade: i32,
+    /// Number of months with NYSE breakpoints.
+    pub n_months: usize,
+    /// Average 20th percentile of NYSE market equity.
+    pub micro_cutoff: f64,
+    /// Average median of NYSE market equity.
+    pub small_cutoff: f64,
+    /// Average fraction of total market equity in micro caps.
+    pub micro_share: f64,
+    /// Average fraction of total market equity in small caps.
+    pub small_share: f64,
+    /// Average fraction of total market equity in large caps.
+    pub large_share: f64,
+}
+
+/// Output of [`market_cap_summary`], one entry per decade in chronological order.
+#[derive(Debug, Clone, Default)]
+pub struct MeSummary {
+    pub decades: Vec<DecadeMeSummary>,
+}
+
+/// Summarizes the market-cap distribution of the sample by decade.
+///
+/// Each month, stocks are bucketed with the NYSE 20th percentile and median of market
+/// equity into micro, small and large caps (Fama and French, 2008), and the share of
+/// total market equity held by each bucket is computed. Stocks at a cutoff go to the
+/// upper bucket. The cutoffs and shares are then averaged within each decade, which
+/// shows how much of the sample, and of an anomaly's returns, can come from micro caps.
+///
+/// # Arguments
+///
+/// * `me` - Market equity (nMonths x nStocks).
+/// * `exchcd` - Exchange codes (nMonths x nStocks).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1).
+pub fn market_cap_summary(
+    me: &Array2<f64>,
+    exchcd: &Array2<i16>,
+    dates: &Array2<i32>,
+) -> MeSummary {
+    assert!(
+        me.dim() == exchcd.dim() && me.nrows() == dates.len(),
+        "ME, exchcd and dates must be aligned"
+    );
+    let size = me.mapv(|x| if x > 0.0 { x } else { f64::NAN });
+    let breakpoints = compute_breakpoints(
+        &size,
+        Some(exchcd),
+        &[MICRO_CAP_PERCENTILE, SMALL_CAP_PERCENTILE],
+    );
+
+    let mut summary = MeSummary::default();
+    for (t, &date) in dates.iter().enumerate() {
+        let (micro_cutoff, small_cutoff) = (breakpoints[[t, 0]], breakpoints[[t, 1]]);
+        let mut caps = [0.0; 3];
+        for &x in size.row(t).iter().filter(|x| x.is_finite()) {
+            let bucket = [micro_cutoff, small_cutoff]
+                .iter()
+                .filter(|&&c| c <= x)
+                .count();
+            caps[bucket] += x;
+        }
+        let total: f64 = caps.iter().sum();
+        if micro_cutoff.is_nan() || total <= 0.0 {
+            continue;
+        }
+
+        let decade = date / 100 / 10 * 10;
+        if summary.decades.last().is_none_or(|d| d.decade != decade) {
+            summary.decades.push(DecadeMeSummary {
+                decade,
+                n_months: 0,
+                micro_cutoff: 0.0,
+                small_cutoff: 0.0,
+                micro_share: 0.0,
+                small_share: 0.0,
+                large_share: 0.0,
+            });
+        }
+        let d = summary.decades.last_mut().unwrap();
+        d.n_months += 1;
+        d.micro_cutoff += micro_cutoff;
+        d.small_cutoff += small_cutoff;
+        d.micro_share += caps[0] / total;
+        d.small_share += caps[1] / total;
+        d.large_share += caps[2] / total;
+    }
+    for d in &mut summary.decades {
+        let n = d.n_months as f64;
+        d.micro_cutoff /= n;
+        d.small_cutoff /= n;
+        d.micro_share /= n;
+        d.small_share /= n;
+        d.large_share /= n;
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_market_cap_summary_known_distribution() {
+        // Five NYSE stocks of ME 1..5 (cutoffs 1.8 and 3) and a NASDAQ micro cap of 0.5
+        let me = array![
+            [1.0, 2.0, 3.0, 4.0, 5.0, 0.5],
+            [1.0, 2.0, 3.0, 4.0, 5.0, 0.5],
+            [2.0, 4.0, 6.0, 8.0, 10.0, 1.0],
+        ];
+        let exchcd = Array2::from_shape_fn((3, 6), |(_, j)| if j < 5 { 1i16 } else { 3 });
+        let dates = array![[199911], [199912], [200001]];
+
+        let summary = market_cap_summary(&me, &exchcd, &dates);
+
+        assert_eq!(summary.decades.len(), 2);
+        let nineties = &summary.decades[0];
+        assert_eq!((nineties.decade, nineties.n_months), (1990, 2));
+        assert!((nineties.micro_cutoff - 1.8).abs() < 1e-12);
+        assert!((nineties.small_cutoff - 3.0).abs() < 1e-12);
+        // Micro 1.5, small 2, large 12 out of 15.5
+        assert!((nineties.micro_share - 1.5 / 15.5).abs() < 1e-12);
+        assert!((nineties.small_share - 2.0 / 15.5).abs() < 1e-12);
+        assert!((nineties.large_share - 12.0 / 15.5).abs() < 1e-12);
+        // Doubling every ME leaves the shares unchanged
+        let two_thousands = &summary.decades[1];
+        assert_eq!(two_thousands.decade, 2000);
+        assert!((two_thousands.small_cutoff - 6.0).abs() < 1e-12);
+        assert!((two_thousands.large_share - nineties.large_share).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,11 @@
+pub mod anomaly;
+pub mod batch;
+pub mod double_sorts;
+pub mod export;
+pub mod market;
+pub mod market_cap;
+pub mod report;
+pub mod returns;
+pub mod screens;
+pub mod sorts;
+pub mod weights;
@@ -0,0 +1,243 @@
+use super::anomaly::AnomalyConfig;
+use super::returns::{long_short_spread, PortfolioResult};
+use crate::stats::regression::{add_constant, ols};
+use crate::stats::time_series::{nan_mean, nan_std, MONTHS_PER_YEAR};
+use crate::utilities::matrix_ops::to_log_returns;
+use crate::utilities::risk_free::RiskFree;
+use anyhow::{anyhow, Result};
+use ndarray::{Array1, Array2, Axis};
+use polars::prelude::*;
+
+/// Label of the long-short row in the anomaly table.
+pub const LONG_SHORT_LABEL: &str = "L-S";
+
+/// Builds the summary table of an anomaly as a DataFrame.
+///
+/// There is one row per portfolio and a final long-short row (`L-S`, long the last
+/// portfolio and short the first). The columns are:
+/// - `mean_excess`: average monthly excess return (the raw return for `L-S`, which is
+///   self-financing),
+/// - `capm_alpha`, `capm_t`: intercept and its t-statistic from a regression on the
+///   first factor (the market excess return),
+/// - `ff_alpha`, `ff_t`: intercept and its t-statistic from a regression on all factors,
+/// - `sharpe`: annualized Sharpe ratio,
+/// - `turnover`: average monthly one-way turnover (sum of both legs for `L-S`),
+/// - `net_return`: `mean_excess` minus trading costs of `config.cost_bps` basis points
+///   per unit of value traded (two trades per unit of one-way turnover).
+///
+/// With `config.log_returns`, the portfolio returns, the risk-free rate and the factors
+/// are converted to log returns, `ln(1 + r)`, before the excess returns and the averages
+/// and regressions over time of the portfolio rows are computed. Their mean excess
+/// returns and alphas are converted back to simple returns, `exp(x) - 1`, for the table.
+/// The `L-S` row stays in simple returns, as the difference of two log returns is not the
+/// return of the long-short position.
+///
+/// # Arguments
+///
+/// * `result` - Portfolios of the anomaly.
+/// * `factors` - Factor returns aligned with `result.dates` (nMonths x nFactors), with the
+///   market excess return in the first column.
+/// * `rf` - Source of the risk-free rate.
+/// * `config` - Settings of the anomaly, for the trading costs and the log-return option.
+pub fn anomaly_table(
+    result: &PortfolioResult,
+    factors: &Array2<f64>,
+    rf: &RiskFree,
+    config: &AnomalyConfig,
+) -> Result<DataFrame> {
+    if factors.nrows() != result.dates.len() || factors.ncols() == 0 {
+        return Err(anyhow!(
+            "Factors {:?} are not aligned with the {} portfolio dates",
+            factors.dim(),
+            result.dates.len()
+        ));
+    }
+    let rf = rf.series(&result.dates)?;
+    let identity: fn(f64) -> f64 = |x| x;
+    let (returns, rf_used, factors_used, to_simple) = if config.log_returns {
+        let log_rf = rf.mapv(|r| if r > -1.0 { r.ln_1p() } else { f64::NAN });
+        let to_simple: fn(f64) -> f64 = f64::exp_m1;
+        (
+            to_log_returns(&result.returns),
+            log_rf,
+            to_log_returns(factors),
+            to_simple,
+        )
+    } else {
+        (result.returns.clone(), rf, factors.clone(), identity)
+    };
+    let n = result.n_portfolios();
+    let cost = 2.0 * config.cost_bps / 10_000.0;
+    let market = factors_used.select(Axis(1), &[0]);
+
+    let mut labels = Vec::with_capacity(n + 1);
+    let mut rows: Vec<[f64; 8]> = Vec::with_capacity(n + 1);
+    for p in 0..n {
+        let excess = &returns.column(p) - &rf_used;
+        let turnover = nan_mean(&result.turnover.column(p).to_owned());
+        labels.push((p + 1).to_string());
+        rows.push(summary_row(
+            &excess,
+            &market,
+            &factors_used,
+            turnover,
+            cost,
+            to_simple,
+        )?);
+    }
+    // The self-financing spread of simple returns, regressed on the simple factors
+    let long_short = long_short_spread(&result.returns);
+    let turnover = nan_mean(&result.turnover.column(0).to_owned())
+        + nan_mean(&result.turnover.column(n - 1).to_owned());
+    labels.push(LONG_SHORT_LABEL.to_string());
+    rows.push(summary_row(
+        &long_short,
+        &factors.select(Axis(1), &[0]),
+        factors,
+        turnover,
+        cost,
+        identity,
+    )?);
+
+    let column = |k: usize| -> Vec<f64> { rows.iter().map(|row| row[k]).collect() };
+    Ok(df![
+        "portfolio" => labels,
+        "mean_excess" => column(0),
+        "capm_alpha" => column(1),
+        "capm_t" => column(2),
+        "ff_alpha" => column(3),
+        "ff_t" => column(4),
+        "sharpe" => column(5),
+        "turnover" => column(6),
+        "net_return" => column(7),
+    ]?)
+}
+
+fn summary_row(
+    excess: &Array1<f64>,
+    market: &Array2<f64>,
+    factors: &Array2<f64>,
+    turnover: f64,
+    cost: f64,
+    to_simple: fn(f64) -> f64,
+) -> Result<[f64; 8]> {
+    let mean = nan_mean(excess);
+    let (capm_alpha, capm_t) = alpha(excess, market)?;
+    let (ff_alpha, ff_t) = alpha(excess, factors)?;
+    let sharpe = MONTHS_PER_YEAR.sqrt() * mean / nan_std(excess);
+    let (mean, capm_alpha, ff_alpha) =
+        (to_simple(mean), to_simple(capm_alpha), to_simple(ff_alpha));
+    Ok([
+        mean,
+        capm_alpha,
+        capm_t,
+        ff_alpha,
+        ff_t,
+        sharpe,
+        turnover,
+        mean - cost * turnover,
+    ])
+}
+
+/// Intercept and t-statistic of a regression of `y` on a constant and `x`, over the
+/// months where all series are available.
+fn alpha(y: &Array1<f64>, x: &Array2<f64>) -> Result<(f64, f64)> {
+    let rows: Vec<usize> = (0..y.len())
+        .filter(|&t| y[t].is_finite() && x.row(t).iter().all(|v| v.is_finite()))
+        .collect();
+    if rows.len() <= x.ncols() + 1 {
+        return Ok((f64::NAN, f64::NAN));
+    }
+    let y = y.select(Axis(0), &rows);
+    let x = add_constant(&x.select(Axis(0), &rows));
+    let fit = ols(&y, &x)?;
+    Ok((fit.coefficients[0], fit.coefficients[0] / fit.std_errors[0]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::matrix_ops::month_range;
+
+    #[test]
+    fn test_anomaly_table_shape_and_long_short_row() {
+        let months = month_range(200001, 200212);
+        let (n_months, n_stocks) = (months.len(), 6);
+        let dates = Array2::from_shape_vec((n_months, 1), months).unwrap();
+        let assignments = Array2::from_shape_fn((n_months, n_stocks), |(_, j)| (j / 2) as i32 + 1);
+        let ret = Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+            0.005 * j as f64 + 0.01 * ((t * 3 + j) % 5) as f64 - 0.02
+        });
+        let factors = Array2::from_shape_fn((n_months, 3), |(t, k)| {
+            0.01 * ((t * (k + 2)) % 7) as f64 - 0.03
+        });
+        let result = PortfolioResult::new(assignments, &dates, &ret, None, 3).unwrap();
+        let rf = RiskFree::constant(&dates, 0.001);
+        let config = AnomalyConfig::builder().cost_bps(10.0).build().unwrap();
+
+        let table = anomaly_table(&result, &factors, &rf, &config).unwrap();
+
+        assert_eq!(table.shape(), (4, 9));
+        let labels: Vec<&str> = table
+            .column("portfolio")
+            .unwrap()
+            .str()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(labels, vec!["1", "2", "3", LONG_SHORT_LABEL]);
+        let mean = table.column("mean_excess").unwrap().f64().unwrap();
+        let long_short = result.long_short();
+        assert!((mean.get(3).unwrap() - nan_mean(&long_short)).abs() < 1e-12);
+        // Trading costs can only lower the net return
+        let net = table.column("net_return").unwrap().f64().unwrap();
+        assert!(net.get(0).unwrap() <= mean.get(0).unwrap());
+    }
+
+    #[test]
+    fn test_anomaly_table_in_log_space_reports_simple_returns() {
+        let months = month_range(200001, 200112);
+        let n_months = months.len();
+        let dates = Array2::from_shape_vec((n_months, 1), months).unwrap();
+        let assignments = Array2::from_shape_fn((n_months, 2), |(_, j)| j as i32 + 1);
+        let ret = Array2::from_shape_fn((n_months, 2), |(t, j)| {
+            if t % 2 == 0 {
+                0.10 * (j + 1) as f64
+            } else {
+                -0.05 * (j + 1) as f64
+            }
+        });
+        let factors = Array2::from_shape_fn((n_months, 1), |(t, _)| 0.01 * (t % 3) as f64);
+        let result = PortfolioResult::new(assignments, &dates, &ret, None, 2).unwrap();
+        let rf = RiskFree::constant(&dates, 0.0);
+        let config = AnomalyConfig::builder().log_returns(true).build().unwrap();
+
+        let table = anomaly_table(&result, &factors, &rf, &config).unwrap();
+
+        // The mean is the geometric average simple return, below the arithmetic one
+        let live = result.returns.column(0).to_owned();
+        let geometric = nan_mean(&live.mapv(f64::ln_1p)).exp_m1();
+        let mean = table.column("mean_excess").unwrap().f64().unwrap();
+        assert!((mean.get(0).unwrap() - geometric).abs() < 1e-12);
+        assert!(mean.get(0).unwrap() < nan_mean(&live));
+        // The alpha comes from log excess returns on log factors
+        let log_returns = to_log_returns(&result.returns).column(0).to_owned();
+        let (log_alpha, _) = alpha(&log_returns, &to_log_returns(&factors)).unwrap();
+        let capm_alpha = table.column("capm_alpha").unwrap().f64().unwrap();
+        assert!((capm_alpha.get(0).unwrap() - log_alpha.exp_m1()).abs() < 1e-12);
+        // The long-short row is the spread of simple returns, as without log returns
+        let simple = anomaly_table(
+            &result,
+            &factors,
+            &rf,
+            &AnomalyConfig::builder().build().unwrap(),
+        )
+        .unwrap();
+        for column in ["mean_excess", "capm_alpha", "ff_alpha", "net_return"] {
+            let log_mode = table.column(column).unwrap().f64().unwrap().get(2);
+            let simple_mode = simple.column(column).unwrap().f64().unwrap().get(2);
+            assert_eq!(log_mode, simple_mode, "{}", column);
+        }
+        assert!((mean.get(2).unwrap() - nan_mean(&result.long_short())).abs() < 1e-12);
+    }
+}
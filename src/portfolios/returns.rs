@@ -0,0 +1,414 @@
+use super::sorts::{assign_portfolios, compute_breakpoints};
+use super::weights::portfolio_weights;
+use crate::stats::time_series::{nan_mean, rolling_beta};
+use anyhow::{anyhow, Result};
+use ndarray::{s, Array1, Array2, ArrayView2};
+
+/// Portfolios formed from monthly assignments, with their returns and turnover.
+///
+/// All matrices share the rows of `dates`. Assignments and weights on row t are formed at
+/// the end of month t; returns on row t are earned over month t by the portfolios formed
+/// at the end of month t-1, so the first row of `returns` is NaN.
+#[derive(Debug, Clone)]
+pub struct PortfolioResult {
+    /// Dates vector in `YYYYMM` format (nMonths x 1).
+    pub dates: Array2<i32>,
+    /// Portfolio index of each stock (nMonths x nStocks), 0 when unassigned.
+    pub assignments: Array2<i32>,
+    /// Weight of each stock in its portfolio at formation (nMonths x nStocks).
+    pub weights: Array2<f64>,
+    /// Portfolio returns (nMonths x nPortfolios).
+    pub returns: Array2<f64>,
+    /// One-way turnover of each portfolio when rebalancing at the end of the month
+    /// (nMonths x nPortfolios).
+    pub turnover: Array2<f64>,
+    /// Number of leading months without any portfolio return: the one-month lag between
+    /// formation and returns, plus the lookback of the signal. Averages should be taken
+    /// over the live sample that follows, see [`PortfolioResult::live_returns`].
+    pub warmup_months: usize,
+}
+
+impl PortfolioResult {
+    /// Builds the portfolios from assignments, value-weighting by `me` when provided and
+    /// equal-weighting otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// * `assignments` - Portfolio index of each stock (nMonths x nStocks).
+    /// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1).
+    /// * `ret` - Returns (nMonths x nStocks).
+    /// * `me` - Optional market equity for value weighting (nMonths x nStocks).
+    /// * `n_portfolios` - Number of portfolios.
+    pub fn new(
+        assignments: Array2<i32>,
+        dates: &Array2<i32>,
+        ret: &Array2<f64>,
+        me: Option<&Array2<f64>>,
+        n_portfolios: usize,
+    ) -> Result<Self> {
+        if assignments.dim() != ret.dim() || assignments.nrows() != dates.len() {
+            return Err(anyhow!(
+                "Assignments {:?}, returns {:?} and {} dates are not aligned",
+                assignments.dim(),
+                ret.dim(),
+                dates.len()
+            ));
+        }
+        let weights = portfolio_weights(&assignments, me, n_portfolios, None);
+        let returns = holding_returns(&weights, &assignments, ret, n_portfolios);
+        let turnover = portfolio_turnover(&weights, &assignments, ret, n_portfolios);
+        let warmup_months = returns
+            .rows()
+            .into_iter()
+            .take_while(|row| row.iter().all(|r| r.is_nan()))
+            .count();
+        Ok(PortfolioResult {
+            dates: dates.clone(),
+            assignments,
+            weights,
+            returns,
+            turnover,
+            warmup_months,
+        })
+    }
+
+    pub fn n_portfolios(&self) -> usize {
+        self.returns.ncols()
+    }
+
+    /// Portfolio returns after the warm-up months.
+    pub fn live_returns(&self) -> ArrayView2<'_, f64> {
+        self.returns.slice(s![self.warmup_months.., ..])
+    }
+
+    /// Dates of the months after the warm-up.
+    pub fn live_dates(&self) -> ArrayView2<'_, i32> {
+        self.dates.slice(s![self.warmup_months.., ..])
+    }
+
+    /// Return of the long-short portfolio, long the last portfolio and short the first.
+    pub fn long_short(&self) -> Array1<f64> {
+        long_short_spread(&self.returns)
+    }
+}
+
+/// High-minus-low return of a portfolio return matrix (nMonths x nPortfolios): the last
+/// column minus the first, NaN when either is missing. See
+/// [`summarize_returns`](crate::stats::time_series::summarize_returns) for its statistics.
+pub fn long_short_spread(port_returns: &Array2<f64>) -> Array1<f64> {
+    assert!(
+        port_returns.ncols() >= 2,
+        "A long-short spread requires at least two portfolios"
+    );
+    &port_returns.column(port_returns.ncols() - 1) - &port_returns.column(0)
+}
+
+/// Portfolio returns from monthly assignments, value-weighted by `weights` (typically the
+/// `me` matrix) when provided and equal-weighted otherwise.
+///
+/// Row t holds the returns earned over month t by the portfolios formed at the end of
+/// month t-1, weighted by the ME of month t-1, see [`portfolio_weights`]. The first row,
+/// and any portfolio without constituents with a return in a month, are NaN.
+///
+/// # Arguments
+///
+/// * `assignments` - Portfolio index of each stock (nMonths x nStocks), 0 when unassigned.
+/// * `ret` - Returns (nMonths x nStocks).
+/// * `weights` - Optional market equity for value weighting (nMonths x nStocks).
+/// * `n_portfolios` - Number of portfolios.
+///
+/// # Returns
+///
+/// * `Array2<f64>` - The portfolio returns (nMonths x n_portfolios).
+pub fn portfolio_returns(
+    assignments: &Array2<i32>,
+    ret: &Array2<f64>,
+    weights: Option<&Array2<f64>>,
+    n_portfolios: usize,
+) -> Array2<f64> {
+    assert_eq!(
+        assignments.dim(),
+        ret.dim(),
+        "Assignments and returns must have the same dimensions"
+    );
+    let weights = portfolio_weights(assignments, weights, n_portfolios, None);
+    holding_returns(&weights, assignments, ret, n_portfolios)
+}
+
+/// Trailing window, in months, of the leg betas used by [`beta_neutralize`].
+pub const BETA_NEUTRAL_WINDOW: usize = 36;
+
+/// Beta-neutral long-short return: the short leg is scaled to the market beta of the long
+/// leg before differencing, `long - (beta_long / beta_short) * short`, which removes the
+/// market tilt left in a raw dollar-neutral long-short.
+///
+/// The betas are [`rolling_beta`]s over the [`BETA_NEUTRAL_WINDOW`] months ending in
+/// month t-1, so the hedge of month t only uses past returns. Months without both betas,
+/// or with a short-leg beta of zero, are NaN.
+///
+/// # Arguments
+///
+/// * `long` - Returns of the long leg (nMonths x 1).
+/// * `short` - Returns of the short leg (nMonths x 1).
+/// * `mkt` - Market returns (nMonths).
+pub fn beta_neutralize(long: &Array2<f64>, short: &Array2<f64>, mkt: &Array1<f64>) -> Array1<f64> {
+    assert!(
+        long.ncols() == 1 && short.ncols() == 1,
+        "Each leg must be a single return series"
+    );
+    assert!(
+        long.nrows() == mkt.len() && short.nrows() == mkt.len(),
+        "Legs and market returns must have the same length"
+    );
+    let long = long.column(0).to_owned();
+    let short = short.column(0).to_owned();
+    let beta_long = rolling_beta(&long, mkt, BETA_NEUTRAL_WINDOW);
+    let beta_short = rolling_beta(&short, mkt, BETA_NEUTRAL_WINDOW);
+
+    let mut hedged = Array1::from_elem(mkt.len(), f64::NAN);
+    for t in 1..mkt.len() {
+        let ratio = beta_long[t - 1] / beta_short[t - 1];
+        if ratio.is_finite() {
+            hedged[t] = long[t] - ratio * short[t];
+        }
+    }
+    hedged
+}
+
+/// Number of portfolios formed by [`predictive_decay`].
+pub const DECAY_PORTFOLIOS: usize = 10;
+
+/// Average long-short spread of a signal at increasing horizons, showing how quickly its
+/// predictive power decays and so how long its portfolios are worth holding.
+///
+/// Each month stocks are sorted into [`DECAY_PORTFOLIOS`] equal-weighted portfolios on the
+/// signal, with breakpoints over all stocks. Entry k-1 of the result is the mean, over the
+/// months where it is available, of the return in month t+k of the highest portfolio
+/// formed at the end of month t minus the lowest, for k = 1..=`max_horizon`.
+///
+/// # Arguments
+///
+/// * `signal` - Sorting variable (nMonths x nStocks).
+/// * `ret` - Returns (nMonths x nStocks).
+/// * `max_horizon` - Longest horizon, in months.
+pub fn predictive_decay(
+    signal: &Array2<f64>,
+    ret: &Array2<f64>,
+    max_horizon: usize,
+) -> Array1<f64> {
+    assert_eq!(
+        signal.dim(),
+        ret.dim(),
+        "Signal and returns must have the same dimensions"
+    );
+    let n = DECAY_PORTFOLIOS;
+    let percentiles: Vec<f64> = (1..n).map(|i| i as f64 / n as f64).collect();
+    let breakpoints = compute_breakpoints(signal, None, &percentiles);
+    let assignments = assign_portfolios(signal, &breakpoints);
+    let weights = portfolio_weights(&assignments, None, n, None);
+
+    Array1::from_shape_fn(max_horizon, |h| {
+        // Row t of the shifted returns holds the returns of month t+h, so that the
+        // portfolios formed at t-1 earn the returns k = h+1 months after formation
+        let mut ahead = Array2::from_elem(ret.dim(), f64::NAN);
+        if h < ret.nrows() {
+            ahead
+                .slice_mut(s![..ret.nrows() - h, ..])
+                .assign(&ret.slice(s![h.., ..]));
+        }
+        let returns = holding_returns(&weights, &assignments, &ahead, n);
+        nan_mean(&long_short_spread(&returns))
+    })
+}
+
+/// Returns earned over month t by the portfolios formed at the end of month t-1. Stocks
+/// without a return in month t are dropped and the remaining weights rescaled.
+pub(crate) fn holding_returns(
+    weights: &Array2<f64>,
+    assignments: &Array2<i32>,
+    ret: &Array2<f64>,
+    n_portfolios: usize,
+) -> Array2<f64> {
+    let mut returns = Array2::from_elem((ret.nrows(), n_portfolios), f64::NAN);
+    for t in 1..ret.nrows() {
+        let mut weighted = vec![0.0; n_portfolios];
+        let mut totals = vec![0.0; n_portfolios];
+        for (j, &p) in assignments.row(t - 1).iter().enumerate() {
+            let (w, r) = (weights[[t - 1, j]], ret[[t, j]]);
+            if p > 0 && w > 0.0 && r.is_finite() {
+                weighted[p as usize - 1] += w * r;
+                totals[p as usize - 1] += w;
+            }
+        }
+        for p in 0..n_portfolios {
+            if totals[p] > 0.0 {
+                returns[[t, p]] = weighted[p] / totals[p];
+            }
+        }
+    }
+    returns
+}
+
+/// One-way turnover at the end of month t: half the sum of absolute differences between
+/// the new weights and the weights of the previous formation after drifting with the
+/// month-t returns. Stocks without a month-t return are treated as sold.
+fn portfolio_turnover(
+    weights: &Array2<f64>,
+    assignments: &Array2<i32>,
+    ret: &Array2<f64>,
+    n_portfolios: usize,
+) -> Array2<f64> {
+    let mut turnover = Array2::from_elem((ret.nrows(), n_portfolios), f64::NAN);
+    for t in 1..ret.nrows() {
+        for p in 1..=n_portfolios as i32 {
+            let drifted: Vec<f64> = (0..ret.ncols())
+                .map(|j| {
+                    let (w, r) = (weights[[t - 1, j]], ret[[t, j]]);
+                    if assignments[[t - 1, j]] == p && w > 0.0 && r.is_finite() {
+                        w * (1.0 + r)
+                    } else {
+                        0.0
+                    }
+                })
+                .collect();
+            let drifted_total: f64 = drifted.iter().sum();
+            if drifted_total <= 0.0 {
+                continue;
+            }
+            let traded: f64 = (0..ret.ncols())
+                .map(|j| {
+                    let new = if assignments[[t, j]] == p {
+                        weights[[t, j]]
+                    } else {
+                        0.0
+                    };
+                    (new - drifted[j] / drifted_total).abs()
+                })
+                .sum();
+            turnover[[t, p as usize - 1]] = 0.5 * traded;
+        }
+    }
+    turnover
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_beta_neutralize_removes_market_exposure() {
+        let n = 240;
+        let mkt = Array1::from_shape_fn(n, |t| {
+            0.04 * (1.7 * t as f64).sin() + 0.01 * (0.3 * t as f64).cos()
+        });
+        let noise =
+            |phase: f64| Array1::from_shape_fn(n, |t| 0.01 * (3.1 * t as f64 + phase).sin());
+        let long = (&mkt * 1.5 + 0.01 + noise(1.0)).insert_axis(ndarray::Axis(1));
+        let short = (&mkt * 0.5 + noise(2.0)).insert_axis(ndarray::Axis(1));
+        let full_sample_beta = |ret: &Array1<f64>| {
+            let live: Vec<usize> = (0..n).filter(|&t| ret[t].is_finite()).collect();
+            let r = Array1::from_iter(live.iter().map(|&t| ret[t]));
+            let m = Array1::from_iter(live.iter().map(|&t| mkt[t]));
+            rolling_beta(&r, &m, live.len())[live.len() - 1]
+        };
+
+        let raw = &long.column(0) - &short.column(0);
+        let hedged = beta_neutralize(&long, &short, &mkt);
+
+        assert!((full_sample_beta(&raw) - 1.0).abs() < 0.05);
+        assert!(hedged.iter().take(BETA_NEUTRAL_WINDOW).all(|r| r.is_nan()));
+        assert!(full_sample_beta(&hedged).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_portfolio_result_returns_use_prior_formation() {
+        let assignments = array![[1, 1, 2], [1, 2, 2], [0, 0, 0]];
+        let dates = array![[200001], [200002], [200003]];
+        let ret = array![[0.5, 0.5, 0.5], [0.01, 0.03, 0.05], [0.02, 0.04, f64::NAN]];
+        let me = array![[100.0, 300.0, 50.0], [100.0, 100.0, 100.0], [1.0, 1.0, 1.0]];
+
+        let result = PortfolioResult::new(assignments, &dates, &ret, Some(&me), 2).unwrap();
+
+        assert!(result.returns.row(0).iter().all(|r| r.is_nan()));
+        assert!((result.returns[[1, 0]] - 0.025).abs() < 1e-12);
+        assert!((result.returns[[1, 1]] - 0.05).abs() < 1e-12);
+        // Stock 2 has no return in March and drops out of portfolio 2
+        assert!((result.returns[[2, 0]] - 0.02).abs() < 1e-12);
+        assert!((result.returns[[2, 1]] - 0.04).abs() < 1e-12);
+        assert!((result.long_short()[1] - 0.025).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_portfolio_returns_weight_by_prior_month_me() {
+        let assignments = array![[1, 1, 2], [2, 1, 0], [1, 1, 1]];
+        let ret = array![[0.9, 0.9, 0.9], [0.01, 0.03, 0.05], [0.02, 0.04, 0.06]];
+        let me = array![[100.0, 300.0, 50.0], [100.0, 100.0, 100.0], [1.0, 1.0, 1.0]];
+
+        let vw = portfolio_returns(&assignments, &ret, Some(&me), 2);
+        let ew = portfolio_returns(&assignments, &ret, None, 2);
+
+        assert_eq!(vw.dim(), (3, 2));
+        assert!(vw.row(0).iter().all(|r| r.is_nan()));
+        assert!((vw[[1, 0]] - 0.025).abs() < 1e-12);
+        assert!((ew[[1, 0]] - 0.02).abs() < 1e-12);
+        assert!((vw[[1, 1]] - 0.05).abs() < 1e-12);
+        assert!((vw[[2, 0]] - 0.04).abs() < 1e-12);
+        assert!((vw[[2, 1]] - 0.02).abs() < 1e-12);
+        // Portfolio 2 has no constituents
+        let empty = portfolio_returns(
+            &array![[1, 1], [1, 1]],
+            &array![[0.0, 0.0], [0.01, 0.03]],
+            None,
+            2,
+        );
+        assert!((empty[[1, 0]] - 0.02).abs() < 1e-12);
+        assert!(empty[[1, 1]].is_nan());
+    }
+
+    #[test]
+    fn test_long_short_spread_is_last_minus_first() {
+        let returns = array![[0.01, 0.02, 0.05], [0.03, 0.0, f64::NAN]];
+
+        let spread = long_short_spread(&returns);
+
+        assert!((spread[0] - 0.04).abs() < 1e-12);
+        assert!(spread[1].is_nan());
+    }
+
+    #[test]
+    fn test_turnover_of_unchanged_equal_weighted_portfolio_is_drift_only() {
+        let assignments = array![[1, 1], [1, 1]];
+        let dates = array![[200001], [200002]];
+        let ret = array![[0.0, 0.0], [0.0, 0.0]];
+
+        let result = PortfolioResult::new(assignments, &dates, &ret, None, 1).unwrap();
+
+        assert!(result.turnover[[1, 0]].abs() < 1e-12);
+
+        let assignments = array![[1, 0], [0, 1]];
+        let result = PortfolioResult::new(assignments, &dates, &ret, None, 1).unwrap();
+        assert!((result.turnover[[1, 0]] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_predictive_decay_peaks_at_first_horizon() {
+        let (n_months, n_stocks) = (120, 50);
+        // Rankings reshuffled every month, with a premium that halves with each month
+        let signal = Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+            (((j + 1) * (3 * t + 1)) % 53) as f64
+        });
+        let ret = Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+            (1..=3)
+                .filter(|&k| t >= k)
+                .map(|k| 0.001 * signal[[t - k, j]] / 2f64.powi(k as i32 - 1))
+                .sum::<f64>()
+        });
+
+        let decay = predictive_decay(&signal, &ret, 4);
+
+        assert_eq!(decay.len(), 4);
+        assert!(decay[0] > decay[1] && decay[1] > decay[2] && decay[2] > decay[3]);
+        assert!(decay[0] > 0.0);
+    }
+}
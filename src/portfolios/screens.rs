@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Result};
+use ndarray::{Array1, Array2, Axis, Zip};
+use std::collections::{HashMap, HashSet};
+
+/// Number of tradeable stocks each month once all screens are applied.
+///
+/// Each mask is `true` where a stock passes one screen (price, size, industry, liquidity,
+/// ...). A stock is tradeable in a month when it passes every screen, so the masks are
+/// combined with a logical AND before counting. Comparing the result with the count of a
+/// single mask shows how much each screen shrinks the sample.
+///
+/// # Arguments
+///
+/// * `masks` - Screen masks (nMonths x nStocks), all with the same dimensions.
+///
+/// # Returns
+///
+/// * `Result<Array1<f64>>` - The number of tradeable stocks each month (nMonths), or an
+///   error when no mask is given, since the number of months is then unknown, or when
+///   the masks do not have the same dimensions.
+pub fn tradeable_universe_size(masks: &[&Array2<bool>]) -> Result<Array1<f64>> {
+    let Some((first, rest)) = masks.split_first() else {
+        return Err(anyhow!("At least one screen mask is required"));
+    };
+    let mut tradeable = (*first).clone();
+    for mask in rest {
+        if mask.dim() != tradeable.dim() {
+            return Err(anyhow!(
+                "Screen mask {:?} does not match the first mask {:?}",
+                mask.dim(),
+                tradeable.dim()
+            ));
+        }
+        Zip::from(&mut tradeable)
+            .and(*mask)
+            .for_each(|t, &m| *t = *t && m);
+    }
+    Ok(tradeable
+        .axis_iter(Axis(0))
+        .map(|row| row.iter().filter(|&&t| t).count() as f64)
+        .collect())
+}
+
+/// Number of distinct permnos and of distinct firms (permcos) in the sample each month.
+///
+/// A firm with several share classes has one permno per class but a single permco, so the
+/// firm count is at most the permno count. Permnos missing from `permno_to_permco` count
+/// as firms of their own.
+///
+/// # Arguments
+///
+/// * `permno_to_permco` - Permco of each permno.
+/// * `permno` - Permno vector (nStocks x 1).
+/// * `valid_mask` - `true` where a stock is in the sample (nMonths x nStocks).
+///
+/// # Returns
+///
+/// * `(Array1<f64>, Array1<f64>)` - The permno and firm counts of each month (nMonths).
+pub fn firm_vs_permno_counts(
+    permno_to_permco: &HashMap<i32, i32>,
+    permno: &Array2<i32>,
+    valid_mask: &Array2<bool>,
+) -> (Array1<f64>, Array1<f64>) {
+    assert_eq!(
+        valid_mask.ncols(),
+        permno.len(),
+        "The mask must have one column per permno"
+    );
+    let permno: Vec<i32> = permno.iter().copied().collect();
+    let mut permnos = Array1::zeros(valid_mask.nrows());
+    let mut firms = Array1::zeros(valid_mask.nrows());
+    for (t, row) in valid_mask.axis_iter(Axis(0)).enumerate() {
+        // Firms are keyed by permco, or by permno when the permco is unknown
+        let mut seen = HashSet::new();
+        for (j, _) in row.iter().enumerate().filter(|(_, &valid)| valid) {
+            permnos[t] += 1.0;
+            seen.insert(match permno_to_permco.get(&permno[j]) {
+                Some(&permco) => (true, permco),
+                None => (false, permno[j]),
+            });
+        }
+        firms[t] = seen.len() as f64;
+    }
+    (permnos, firms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_tradeable_universe_size_counts_stocks_passing_all_screens() {
+        let price = array![[true, true, false, true], [true, true, true, true]];
+        let size = array![[true, false, true, true], [false, false, true, true]];
+
+        let combined = tradeable_universe_size(&[&price, &size]).unwrap();
+
+        assert_eq!(combined, array![2.0, 2.0]);
+        assert_eq!(
+            tradeable_universe_size(&[&price]).unwrap(),
+            array![3.0, 4.0]
+        );
+        assert!(tradeable_universe_size(&[]).is_err());
+        let one_month = Array2::from_elem((1, 4), true);
+        assert!(tradeable_universe_size(&[&price, &one_month]).is_err());
+    }
+
+    #[test]
+    fn test_firm_count_merges_share_classes() {
+        // 10001 and 10002 are two share classes of the same firm
+        let permno_to_permco = HashMap::from([(10001, 500), (10002, 500), (10003, 600)]);
+        let permno = array![[10001], [10002], [10003], [10004]];
+        let valid = array![[true, true, true, true], [true, false, true, false]];
+
+        let (permnos, firms) = firm_vs_permno_counts(&permno_to_permco, &permno, &valid);
+
+        assert_eq!(permnos, array![4.0, 2.0]);
+        assert_eq!(firms, array![3.0, 2.0]);
+    }
+}
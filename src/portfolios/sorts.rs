@@ -0,0 +1,530 @@
+use crate::stats::cross_section::quantile_sorted;
+use anyhow::{anyhow, Result};
+use ndarray::{Array2, Axis};
+
+/// CRSP exchange code of the New York Stock Exchange.
+pub const NYSE_EXCHCD: i16 = 1;
+
+/// Configuration of a univariate portfolio sort.
+#[derive(Debug, Clone)]
+pub struct SortConfig {
+    /// Number of portfolios to form each month.
+    pub n_portfolios: usize,
+    /// Compute the breakpoints from NYSE stocks only.
+    pub nyse_breakpoints: bool,
+    /// Drop permnos whose signal is NaN in every month before sorting.
+    pub prune_dead_permnos: bool,
+    /// Percentiles at which the portfolios are cut.
+    pub breakpoints: Breakpoints,
+    /// Months between the signal and the first holding month: portfolios sorted on the
+    /// signal of month t earn the returns of month t + `formation_lag`. The default of 1
+    /// holds them from the next month; 2 adds a one-month implementation delay.
+    pub formation_lag: usize,
+}
+
+impl Default for SortConfig {
+    fn default() -> Self {
+        SortConfig {
+            n_portfolios: 10,
+            nyse_breakpoints: true,
+            prune_dead_permnos: false,
+            breakpoints: Breakpoints::Equal,
+            formation_lag: 1,
+        }
+    }
+}
+
+/// Where the breakpoints between portfolios are placed in the signal distribution.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Breakpoints {
+    /// Equally spaced percentiles, so that each portfolio covers the same share of the
+    /// breakpoint stocks (deciles, quintiles, ...).
+    #[default]
+    Equal,
+    /// Share of the breakpoint stocks covered by each portfolio, from the lowest to the
+    /// highest, summing to one: `Custom(vec![0.3, 0.4, 0.3])` gives the 30th and 70th
+    /// percentiles used for the Fama-French value portfolios.
+    Custom(Vec<f64>),
+}
+
+impl Breakpoints {
+    /// Cumulative percentiles of the `n_portfolios - 1` breakpoints. Custom shares must
+    /// define `n_portfolios` portfolios, be positive and sum to one, so that the
+    /// percentiles are increasing and within (0, 1).
+    pub fn percentiles(&self, n_portfolios: usize) -> Result<Vec<f64>> {
+        match self {
+            Breakpoints::Equal => Ok((1..n_portfolios)
+                .map(|i| i as f64 / n_portfolios as f64)
+                .collect()),
+            Breakpoints::Custom(shares) => {
+                if shares.len() != n_portfolios {
+                    return Err(anyhow!(
+                        "{} custom breakpoint shares given for {} portfolios",
+                        shares.len(),
+                        n_portfolios
+                    ));
+                }
+                if shares.iter().any(|s| !(s.is_finite() && *s > 0.0)) {
+                    return Err(anyhow!(
+                        "Custom breakpoint shares must be positive, got {:?}",
+                        shares
+                    ));
+                }
+                let total: f64 = shares.iter().sum();
+                if (total - 1.0).abs() > 1e-9 {
+                    return Err(anyhow!(
+                        "Custom breakpoint shares must sum to one, got {}",
+                        total
+                    ));
+                }
+                Ok(shares[..n_portfolios - 1]
+                    .iter()
+                    .scan(0.0, |cumulative, s| {
+                        *cumulative += s;
+                        Some(*cumulative)
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+/// How often portfolios are re-formed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rebalance {
+    /// Portfolios are re-formed every month.
+    #[default]
+    Monthly,
+    /// Portfolios are re-formed at quarter ends (March, June, September and December)
+    /// and held for the following three months, even though the signal itself may be
+    /// recomputed monthly. This is the usual turnover-reducing variant for momentum.
+    Quarterly,
+}
+
+impl Rebalance {
+    /// Whether portfolios are formed at the end of month `yyyymm`.
+    pub fn is_formation_month(&self, yyyymm: i32) -> bool {
+        match self {
+            Rebalance::Monthly => true,
+            Rebalance::Quarterly => (yyyymm % 100) % 3 == 0,
+        }
+    }
+}
+
+/// Output of a univariate portfolio sort.
+#[derive(Debug, Clone)]
+pub struct SortResult {
+    /// Portfolio index (1..=n_portfolios) of each stock each month, 0 when unassigned
+    /// (nMonths x nStocks).
+    pub assignments: Array2<i32>,
+    /// Breakpoints used each month (nMonths x (n_portfolios - 1)).
+    pub breakpoints: Array2<f64>,
+    /// Permnos of the columns in `assignments`.
+    pub permno: Vec<i32>,
+    /// Column indices of the retained permnos in the input matrices.
+    pub columns: Vec<usize>,
+}
+
+/// Sorts stocks into portfolios each month based on a signal.
+///
+/// For every month, the breakpoints are the percentiles of the signal given by
+/// `config.breakpoints` (equally spaced by default), computed over NYSE stocks when
+/// `config.nyse_breakpoints` is set. A stock with a
+/// signal equal to a breakpoint goes to the upper portfolio and stocks with a missing
+/// signal are left unassigned (0).
+///
+/// # Arguments
+///
+/// * `signal` - Sorting variable (nMonths x nStocks).
+/// * `exchcd` - Exchange codes (nMonths x nStocks), required for NYSE breakpoints.
+/// * `permno` - Permno vector (nStocks x 1).
+/// * `config` - Sort configuration.
+///
+/// # Returns
+///
+/// * `Result<SortResult>` - The assignments, breakpoints and retained permnos.
+pub fn univariate_sort(
+    signal: &Array2<f64>,
+    exchcd: Option<&Array2<i16>>,
+    permno: &Array2<i32>,
+    config: &SortConfig,
+) -> Result<SortResult> {
+    if config.n_portfolios < 2 {
+        return Err(anyhow!("At least two portfolios are required"));
+    }
+    if signal.ncols() != permno.len() {
+        return Err(anyhow!(
+            "Signal has {} columns but there are {} permnos",
+            signal.ncols(),
+            permno.len()
+        ));
+    }
+    if let Some(exchcd) = exchcd {
+        if exchcd.dim() != signal.dim() {
+            return Err(anyhow!(
+                "Signal and exchcd matrices must have the same dimensions"
+            ));
+        }
+    } else if config.nyse_breakpoints {
+        return Err(anyhow!("NYSE breakpoints require the exchcd matrix"));
+    }
+
+    let columns: Vec<usize> = if config.prune_dead_permnos {
+        live_columns(signal)
+    } else {
+        (0..signal.ncols()).collect()
+    };
+    let signal = signal.select(Axis(1), &columns);
+    let exchcd = exchcd.map(|e| e.select(Axis(1), &columns));
+
+    let percentiles = config.breakpoints.percentiles(config.n_portfolios)?;
+    let breakpoint_exchcd = if config.nyse_breakpoints {
+        exchcd.as_ref()
+    } else {
+        None
+    };
+    let breakpoints = compute_breakpoints(&signal, breakpoint_exchcd, &percentiles);
+    let assignments = assign_portfolios(&signal, &breakpoints);
+
+    Ok(SortResult {
+        assignments,
+        breakpoints,
+        permno: columns.iter().map(|&j| permno[[j, 0]]).collect(),
+        columns,
+    })
+}
+
+/// Holds portfolio assignments between formation months.
+///
+/// Rows that fall on a formation month of `rebalance` keep their assignments and every
+/// other row repeats the assignments of the latest formation month, so stocks neither
+/// enter nor leave a portfolio between rebalancing dates. Rows before the first formation
+/// month are left unassigned (0).
+///
+/// # Arguments
+///
+/// * `assignments` - Monthly assignments (nMonths x nStocks).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1), sorted.
+/// * `rebalance` - Rebalancing frequency.
+pub fn rebalance_assignments(
+    assignments: &Array2<i32>,
+    dates: &Array2<i32>,
+    rebalance: Rebalance,
+) -> Array2<i32> {
+    assert_eq!(
+        assignments.nrows(),
+        dates.len(),
+        "Assignments must have one row per date"
+    );
+    let mut held = Array2::zeros(assignments.dim());
+    let mut formation_row: Option<usize> = None;
+    for (t, &date) in dates.iter().enumerate() {
+        if rebalance.is_formation_month(date) {
+            formation_row = Some(t);
+        }
+        if let Some(f) = formation_row {
+            held.row_mut(t).assign(&assignments.row(f));
+        }
+    }
+    held
+}
+
+/// Returns the indices of the columns with at least one finite value.
+pub fn live_columns(m: &Array2<f64>) -> Vec<usize> {
+    m.axis_iter(Axis(1))
+        .enumerate()
+        .filter(|(_, column)| column.iter().any(|x| x.is_finite()))
+        .map(|(j, _)| j)
+        .collect()
+}
+
+/// NYSE breakpoints of a sort into `n_portfolios` equally sized portfolios: for each
+/// month, the (1/n, 2/n, ...) quantiles of the signal over the NYSE stocks (`exchcd ==
+/// 1`) with a non-missing signal. Months without any such stock get NaN breakpoints.
+///
+/// # Returns
+///
+/// * `Array2<f64>` - Breakpoints (nMonths x (n_portfolios - 1)).
+pub fn nyse_breakpoints(
+    signal: &Array2<f64>,
+    exchcd: &Array2<i16>,
+    n_portfolios: usize,
+) -> Array2<f64> {
+    assert_eq!(
+        signal.dim(),
+        exchcd.dim(),
+        "Signal and exchcd matrices must have the same dimensions"
+    );
+    let percentiles: Vec<f64> = (1..n_portfolios)
+        .map(|i| i as f64 / n_portfolios as f64)
+        .collect();
+    compute_breakpoints(signal, Some(exchcd), &percentiles)
+}
+
+/// Computes the signal percentiles of each month, restricted to NYSE stocks when
+/// `exchcd` is provided. Months without any eligible stock get NaN breakpoints.
+pub(crate) fn compute_breakpoints(
+    signal: &Array2<f64>,
+    exchcd: Option<&Array2<i16>>,
+    percentiles: &[f64],
+) -> Array2<f64> {
+    let mut breakpoints = Array2::from_elem((signal.nrows(), percentiles.len()), f64::NAN);
+    for (t, row) in signal.axis_iter(Axis(0)).enumerate() {
+        let mut values: Vec<f64> = row
+            .iter()
+            .enumerate()
+            .filter(|(j, x)| x.is_finite() && exchcd.is_none_or(|e| e[[t, *j]] == NYSE_EXCHCD))
+            .map(|(_, &x)| x)
+            .collect();
+        if values.is_empty() {
+            continue;
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        for (k, &p) in percentiles.iter().enumerate() {
+            breakpoints[[t, k]] = quantile_sorted(&values, p);
+        }
+    }
+    breakpoints
+}
+
+/// Maps each signal value to its portfolio, 1 to n, given the monthly breakpoints (e.g.
+/// from [`nyse_breakpoints`]). Values equal to a breakpoint go to the upper portfolio;
+/// missing signals and months without breakpoints are left as 0.
+///
+/// # Arguments
+///
+/// * `signal` - Sorting variable (nMonths x nStocks).
+/// * `breakpoints` - Increasing breakpoints of each month (nMonths x (n - 1)).
+pub fn assign_portfolios(signal: &Array2<f64>, breakpoints: &Array2<f64>) -> Array2<i32> {
+    let mut assignments = Array2::zeros(signal.dim());
+    for ((t, j), &x) in signal.indexed_iter() {
+        let cuts = breakpoints.row(t);
+        if !x.is_finite() || cuts.iter().any(|c| c.is_nan()) {
+            continue;
+        }
+        assignments[[t, j]] = 1 + cuts.iter().filter(|&&c| c <= x).count() as i32;
+    }
+    assignments
+}
+
+/// Average monthly fraction of the stocks in the long or short leg that switch to the
+/// opposite leg the next month, a diagnostic of signal stability: a high crossing rate
+/// points to a noisy signal that will be costly to trade.
+///
+/// Each month t > 0, the stocks in portfolio 1 or `n_portfolios` at t - 1 that are still
+/// assigned at t are counted, and the fraction of them assigned to the opposite extreme
+/// portfolio at t is computed. Months without such stocks are skipped, and NaN is
+/// returned when no month qualifies.
+///
+/// # Arguments
+///
+/// * `assignment` - Portfolio assignments, 1 to `n_portfolios` and 0 when unassigned
+///   (nMonths x nStocks).
+/// * `n_portfolios` - Number of portfolios, at least 2.
+pub fn leg_crossing_rate(assignment: &Array2<i32>, n_portfolios: usize) -> f64 {
+    assert!(n_portfolios >= 2, "A sort needs at least 2 portfolios");
+    let (bottom, top) = (1, n_portfolios as i32);
+    let rates: Vec<f64> = assignment
+        .axis_windows(Axis(0), 2)
+        .into_iter()
+        .filter_map(|rows| {
+            let (mut n_in_legs, mut n_crossing) = (0usize, 0usize);
+            for (&before, &after) in rows.row(0).iter().zip(rows.row(1)) {
+                if (before == bottom || before == top) && after != 0 {
+                    n_in_legs += 1;
+                    if (before == bottom && after == top) || (before == top && after == bottom) {
+                        n_crossing += 1;
+                    }
+                }
+            }
+            (n_in_legs > 0).then(|| n_crossing as f64 / n_in_legs as f64)
+        })
+        .collect();
+    if rates.is_empty() {
+        return f64::NAN;
+    }
+    rates.iter().sum::<f64>() / rates.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{array, s};
+
+    #[test]
+    fn test_univariate_sort_all_stock_breakpoints() {
+        let signal = array![[1.0, 2.0, 3.0, 4.0], [4.0, f64::NAN, 2.0, 1.0]];
+        let permno = array![[10001], [10002], [10003], [10004]];
+        let config = SortConfig {
+            n_portfolios: 2,
+            nyse_breakpoints: false,
+            prune_dead_permnos: false,
+            ..Default::default()
+        };
+
+        let result = univariate_sort(&signal, None, &permno, &config).unwrap();
+
+        assert_eq!(result.assignments, array![[1, 1, 2, 2], [2, 0, 2, 1]]);
+        assert_eq!(result.breakpoints[[1, 0]], 2.0);
+    }
+
+    #[test]
+    fn test_leg_crossing_rate() {
+        let assignment = array![
+            [1, 3, 2, 1, 0],
+            // 1 of the 2 stocks still in the sample crosses, the 4th stock drops out
+            [3, 3, 1, 0, 2],
+            // 1 of 3
+            [3, 1, 2, 1, 3],
+            // 3 of 4
+            [1, 3, 2, 3, 2],
+        ];
+        let expected = (1.0 / 2.0 + 1.0 / 3.0 + 3.0 / 4.0) / 3.0;
+        assert!((leg_crossing_rate(&assignment, 3) - expected).abs() < 1e-12);
+        assert!(leg_crossing_rate(&array![[1, 3]], 3).is_nan());
+    }
+
+    #[test]
+    fn test_univariate_sort_nyse_breakpoints() {
+        let signal = array![[1.0, 2.0, 3.0, 10.0]];
+        let exchcd = array![[1i16, 1, 3, 3]];
+        let permno = array![[1], [2], [3], [4]];
+        let config = SortConfig {
+            n_portfolios: 2,
+            ..Default::default()
+        };
+
+        let result = univariate_sort(&signal, Some(&exchcd), &permno, &config).unwrap();
+
+        // NYSE median is 1.5, so all NASDAQ stocks land in the top portfolio
+        assert_eq!(result.assignments, array![[1, 2, 2, 2]]);
+    }
+
+    #[test]
+    fn test_nyse_breakpoints_ignore_other_exchanges_and_missing_signals() {
+        let nan = f64::NAN;
+        let signal = array![
+            [1.0, 2.0, 3.0, 4.0, 100.0, nan],
+            [nan, nan, 5.0, 6.0, 7.0, 8.0]
+        ];
+        let exchcd = array![[1i16, 1, 1, 1, 3, 1], [1, 1, 2, 3, 3, 2]];
+
+        let breakpoints = nyse_breakpoints(&signal, &exchcd, 4);
+
+        assert_eq!(breakpoints.dim(), (2, 3));
+        assert_eq!(breakpoints.row(0).to_vec(), vec![1.75, 2.5, 3.25]);
+        assert!(breakpoints.row(1).iter().all(|b| b.is_nan()));
+    }
+
+    #[test]
+    fn test_assign_portfolios_ties_go_up_and_nan_is_unassigned() {
+        let signal = array![[1.0, 2.0, 3.0, f64::NAN, 5.0], [1.0, 2.0, 3.0, 4.0, 5.0]];
+        let breakpoints = array![[2.0, 4.0], [f64::NAN, 4.0]];
+
+        let assignments = assign_portfolios(&signal, &breakpoints);
+
+        assert_eq!(assignments, array![[1, 2, 2, 0, 3], [0, 0, 0, 0, 0]]);
+    }
+
+    #[test]
+    fn test_custom_breakpoints_30_40_30() {
+        let signal = Array2::from_shape_fn((1, 10), |(_, j)| (j + 1) as f64);
+        let permno = Array2::from_shape_fn((10, 1), |(j, _)| 10001 + j as i32);
+        let config = SortConfig {
+            n_portfolios: 3,
+            nyse_breakpoints: false,
+            breakpoints: Breakpoints::Custom(vec![0.3, 0.4, 0.3]),
+            ..Default::default()
+        };
+
+        let result = univariate_sort(&signal, None, &permno, &config).unwrap();
+
+        let counts: Vec<usize> = (1..=3)
+            .map(|p| result.assignments.iter().filter(|&&a| a == p).count())
+            .collect();
+        assert_eq!(counts, vec![3, 4, 3]);
+        assert!((result.breakpoints[[0, 0]] - 3.7).abs() < 1e-12);
+        assert!((result.breakpoints[[0, 1]] - 7.3).abs() < 1e-12);
+
+        for shares in [vec![0.3, 0.7], vec![0.5, -0.1, 0.6], vec![0.3, 0.3, 0.3]] {
+            let config = SortConfig {
+                breakpoints: Breakpoints::Custom(shares),
+                ..config.clone()
+            };
+            assert!(univariate_sort(&signal, None, &permno, &config).is_err());
+        }
+    }
+
+    #[test]
+    fn test_univariate_sort_prunes_dead_permnos() {
+        let signal = array![
+            [1.0, f64::NAN, 3.0, 2.0],
+            [2.0, f64::NAN, 1.0, 3.0],
+            [3.0, f64::NAN, f64::NAN, 1.0]
+        ];
+        let permno = array![[10001], [10002], [10003], [10004]];
+        let mut config = SortConfig {
+            n_portfolios: 2,
+            nyse_breakpoints: false,
+            prune_dead_permnos: false,
+            ..Default::default()
+        };
+        let full = univariate_sort(&signal, None, &permno, &config).unwrap();
+
+        config.prune_dead_permnos = true;
+        let pruned = univariate_sort(&signal, None, &permno, &config).unwrap();
+
+        assert_eq!(pruned.permno, vec![10001, 10003, 10004]);
+        assert_eq!(pruned.columns, vec![0, 2, 3]);
+        assert_eq!(
+            pruned.assignments.slice(s![.., 0]),
+            full.assignments.slice(s![.., 0])
+        );
+        assert_eq!(
+            pruned.assignments.slice(s![.., 1..]),
+            full.assignments.slice(s![.., 2..])
+        );
+        assert_eq!(pruned.breakpoints, full.breakpoints);
+    }
+
+    #[test]
+    fn test_quarterly_momentum_changes_only_at_quarter_ends() {
+        use crate::signals::momentum::make_momentum;
+        use crate::utilities::matrix_ops::month_range;
+
+        let dates: Vec<i32> = month_range(200001, 200112);
+        let n_months = dates.len();
+        let n_stocks = 6;
+        // Returns that reshuffle the cross-sectional ranking every month
+        let ret = Array2::from_shape_fn((n_months, n_stocks), |(t, j)| {
+            0.01 * (((t * 7 + j * 5) % 11) as f64 - 5.0)
+        });
+        let dates = Array2::from_shape_vec((n_months, 1), dates).unwrap();
+        let permno = Array2::from_shape_fn((n_stocks, 1), |(j, _)| 10001 + j as i32);
+        let config = SortConfig {
+            n_portfolios: 3,
+            nyse_breakpoints: false,
+            ..Default::default()
+        };
+
+        let signal = make_momentum(&ret, 2, 1);
+        let monthly = univariate_sort(&signal, None, &permno, &config).unwrap();
+        let quarterly = rebalance_assignments(&monthly.assignments, &dates, Rebalance::Quarterly);
+
+        let mut monthly_changes = 0;
+        for t in 1..n_months {
+            if monthly.assignments.row(t) != monthly.assignments.row(t - 1) {
+                monthly_changes += 1;
+            }
+            if quarterly.row(t) != quarterly.row(t - 1) {
+                assert_eq!(dates[[t, 0]] % 100 % 3, 0, "changed at {}", dates[[t, 0]]);
+            }
+            if dates[[t, 0]] % 100 % 3 == 0 {
+                assert_eq!(quarterly.row(t), monthly.assignments.row(t));
+            }
+        }
+        assert!(monthly_changes > 8);
+        // January and February precede the first formation month
+        assert!(quarterly.row(0).iter().all(|&p| p == 0));
+    }
+}
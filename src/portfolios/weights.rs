@@ -0,0 +1,259 @@
+use crate::utilities::matrix_ops::lag_matrix;
+use anyhow::{anyhow, Result};
+use ndarray::Array2;
+use polars::prelude::*;
+
+/// Lags market equity by one month: row t holds the ME of month t-1, and the first row
+/// is NaN.
+///
+/// This is the canonical lag for value weighting. A return earned over month t is
+/// weighted by the ME at the end of month t-1, i.e. by row t of `lag_me(me)`. Code that
+/// weights holding-month returns directly, such as the market return, uses this function.
+/// [`portfolio_weights`] instead indexes weights by formation month, so its row t uses
+/// the unlagged ME of month t for the returns of month t+1, which is the same lag.
+pub fn lag_me(me: &Array2<f64>) -> Array2<f64> {
+    lag_matrix(me, 1)
+}
+
+/// Computes the weight of each stock within its portfolio.
+///
+/// Row t holds the weights of the portfolios formed at the end of month t, which earn
+/// the returns of month t+1. With `me = Some(..)` stocks are value-weighted by their
+/// market equity at formation (row t of `me`), and stocks without a positive market
+/// equity get no weight; with `me = None` the stocks of each portfolio are equally
+/// weighted. Unassigned stocks (portfolio 0) have a zero weight.
+///
+/// With `max_weight = Some(cap)` no stock may weigh more than `cap` in its portfolio:
+/// the weights above the cap are set to it and the excess is redistributed to the other
+/// stocks in proportion to their weights, repeating until every weight respects the cap.
+/// A portfolio with fewer than `1 / cap` stocks cannot satisfy the cap and is
+/// equally weighted instead.
+///
+/// # Arguments
+///
+/// * `assignments` - Portfolio index of each stock (nMonths x nStocks), 0 when unassigned.
+/// * `me` - Optional market equity used for value weighting (nMonths x nStocks).
+/// * `n_portfolios` - Number of portfolios.
+/// * `max_weight` - Optional cap on the weight of any single stock.
+///
+/// # Returns
+///
+/// * `Array2<f64>` - The weights (nMonths x nStocks); each non-empty portfolio sums to one.
+pub fn portfolio_weights(
+    assignments: &Array2<i32>,
+    me: Option<&Array2<f64>>,
+    n_portfolios: usize,
+    max_weight: Option<f64>,
+) -> Array2<f64> {
+    if let Some(me) = me {
+        assert_eq!(
+            me.dim(),
+            assignments.dim(),
+            "Assignments and ME must have the same dimensions"
+        );
+    }
+    let raw_weight = |t: usize, j: usize| -> f64 {
+        match me {
+            Some(me) => {
+                let w = me[[t, j]];
+                if w.is_finite() && w > 0.0 {
+                    w
+                } else {
+                    0.0
+                }
+            }
+            None => 1.0,
+        }
+    };
+
+    let mut weights = Array2::zeros(assignments.dim());
+    for t in 0..assignments.nrows() {
+        let mut totals = vec![0.0; n_portfolios + 1];
+        for (j, &p) in assignments.row(t).iter().enumerate() {
+            if p > 0 && (p as usize) <= n_portfolios {
+                totals[p as usize] += raw_weight(t, j);
+            }
+        }
+        for (j, &p) in assignments.row(t).iter().enumerate() {
+            if p > 0 && (p as usize) <= n_portfolios && totals[p as usize] > 0.0 {
+                weights[[t, j]] = raw_weight(t, j) / totals[p as usize];
+            }
+        }
+        if let Some(cap) = max_weight {
+            for p in 1..=n_portfolios as i32 {
+                let members: Vec<usize> = (0..assignments.ncols())
+                    .filter(|&j| assignments[[t, j]] == p && weights[[t, j]] > 0.0)
+                    .collect();
+                let mut w: Vec<f64> = members.iter().map(|&j| weights[[t, j]]).collect();
+                cap_weights(&mut w, cap);
+                for (&j, w) in members.iter().zip(w) {
+                    weights[[t, j]] = w;
+                }
+            }
+        }
+    }
+    weights
+}
+
+/// Caps weights summing to one at `cap`, redistributing the excess proportionally among
+/// the uncapped weights until none exceeds the cap.
+fn cap_weights(weights: &mut [f64], cap: f64) {
+    if weights.is_empty() {
+        return;
+    }
+    if cap * weights.len() as f64 <= 1.0 {
+        let equal = 1.0 / weights.len() as f64;
+        weights.iter_mut().for_each(|w| *w = equal);
+        return;
+    }
+    let mut capped = vec![false; weights.len()];
+    loop {
+        let mut newly_capped = false;
+        for (w, is_capped) in weights.iter_mut().zip(capped.iter_mut()) {
+            if !*is_capped && *w > cap {
+                *w = cap;
+                *is_capped = true;
+                newly_capped = true;
+            }
+        }
+        if !newly_capped {
+            return;
+        }
+        let excess = 1.0 - weights.iter().sum::<f64>();
+        let free: f64 = weights
+            .iter()
+            .zip(&capped)
+            .filter(|(_, &c)| !c)
+            .map(|(w, _)| w)
+            .sum();
+        for (w, _) in weights.iter_mut().zip(&capped).filter(|(_, &c)| !c) {
+            *w += excess * *w / free;
+        }
+    }
+}
+
+/// Reshapes portfolio weights into a long `(date, permno, portfolio, weight)` DataFrame,
+/// the usual input format of external backtesters and risk models. Zero weights are
+/// dropped, so there is one row per holding.
+///
+/// # Arguments
+///
+/// * `weights` - Weights from [`portfolio_weights`] (nMonths x nStocks).
+/// * `assignments` - Portfolio index of each stock (nMonths x nStocks).
+/// * `dates` - Formation dates in `YYYYMM` format (nMonths x 1).
+/// * `permno` - Permno vector (nStocks x 1).
+pub fn weights_long(
+    weights: &Array2<f64>,
+    assignments: &Array2<i32>,
+    dates: &Array2<i32>,
+    permno: &Array2<i32>,
+) -> Result<DataFrame> {
+    if weights.dim() != assignments.dim()
+        || weights.nrows() != dates.len()
+        || weights.ncols() != permno.len()
+    {
+        return Err(anyhow!(
+            "Weights {:?}, assignments {:?}, {} dates and {} permnos are not aligned",
+            weights.dim(),
+            assignments.dim(),
+            dates.len(),
+            permno.len()
+        ));
+    }
+
+    let mut date_col = Vec::new();
+    let mut permno_col = Vec::new();
+    let mut portfolio_col = Vec::new();
+    let mut weight_col = Vec::new();
+    for ((t, j), &w) in weights.indexed_iter() {
+        if w != 0.0 && w.is_finite() {
+            date_col.push(dates[[t, 0]]);
+            permno_col.push(permno[[j, 0]]);
+            portfolio_col.push(assignments[[t, j]]);
+            weight_col.push(w);
+        }
+    }
+
+    Ok(df![
+        "date" => date_col,
+        "permno" => permno_col,
+        "portfolio" => portfolio_col,
+        "weight" => weight_col,
+    ]?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_lag_me_shifts_by_one_month() {
+        let me = array![[1.0, 2.0], [3.0, f64::NAN], [5.0, 6.0]];
+
+        let lagged = lag_me(&me);
+
+        assert!(lagged.row(0).iter().all(|x| x.is_nan()));
+        assert_eq!(lagged.row(1).to_vec(), vec![1.0, 2.0]);
+        assert_eq!(lagged[[2, 0]], 3.0);
+        assert!(lagged[[2, 1]].is_nan());
+    }
+
+    #[test]
+    fn test_portfolio_weights_value_and_equal() {
+        let assignments = array![[1, 1, 2, 0], [2, 1, 1, 1]];
+        let me = array![[100.0, 300.0, 50.0, 10.0], [10.0, f64::NAN, 20.0, 60.0]];
+
+        let vw = portfolio_weights(&assignments, Some(&me), 2, None);
+        let ew = portfolio_weights(&assignments, None, 2, None);
+
+        assert_eq!(vw.row(0).to_vec(), vec![0.25, 0.75, 1.0, 0.0]);
+        assert_eq!(vw.row(1).to_vec(), vec![1.0, 0.0, 0.25, 0.75]);
+        assert_eq!(ew.row(0).to_vec(), vec![0.5, 0.5, 1.0, 0.0]);
+        assert!((ew[[1, 1]] - 1.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_portfolio_weights_cap_redistributes_excess() {
+        let assignments = array![[1, 1, 1, 1]];
+        let me = array![[70.0, 20.0, 6.0, 4.0]];
+
+        let capped = portfolio_weights(&assignments, Some(&me), 1, Some(0.4));
+
+        // The 0.3 of excess goes to the others in proportion 20:6:4, which brings the
+        // second stock exactly to the cap
+        let expected = [0.4, 0.4, 0.12, 0.08];
+        for (w, e) in capped.row(0).iter().zip(expected) {
+            assert!((w - e).abs() < 1e-12);
+        }
+        assert!((capped.sum() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_weights_long_has_one_row_per_holding() {
+        let assignments = array![[1, 1, 2, 0], [2, 1, 1, 1], [0, 0, 0, 0]];
+        let me = array![
+            [100.0, 300.0, 50.0, 10.0],
+            [10.0, f64::NAN, 20.0, 60.0],
+            [1.0, 1.0, 1.0, 1.0]
+        ];
+        let dates = array![[200001], [200002], [200003]];
+        let permno = array![[10001], [10002], [10003], [10004]];
+        let weights = portfolio_weights(&assignments, Some(&me), 2, None);
+
+        let long = weights_long(&weights, &assignments, &dates, &permno).unwrap();
+
+        let n_holdings = weights.iter().filter(|&&w| w != 0.0).count();
+        assert_eq!(n_holdings, 6);
+        assert_eq!(long.height(), n_holdings);
+        assert_eq!(
+            long.get_column_names_str(),
+            vec!["date", "permno", "portfolio", "weight"]
+        );
+        let last = long.get_row(n_holdings - 1).unwrap();
+        assert_eq!(last.0[0], AnyValue::Int32(200002));
+        assert_eq!(last.0[1], AnyValue::Int32(10004));
+        assert_eq!(last.0[2], AnyValue::Int32(1));
+        assert_eq!(last.0[3], AnyValue::Float64(0.75));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::utilities::matrix_ops::add_months;
+use ndarray::{Array2, Zip};
+use std::collections::HashMap;
+
+/// Builds the book-to-market matrix with the Fama-French timing.
+///
+/// In June of year t, book equity from the fiscal year ending in calendar year t-1 is
+/// divided by market equity at the end of December of t-1. The ratio is assigned to the
+/// rows from June of t to May of t+1, so that portfolios formed at the end of June are
+/// held from July to the following June.
+///
+/// Firms with a non-December fiscal year end still use December ME: a firm whose fiscal
+/// year ends in June of t-1 is matched with ME from December of t-1, not with ME at its
+/// fiscal year end. Using the fiscal-year-end ME would mix market values measured at
+/// different points in time across firms.
+///
+/// # Arguments
+///
+/// * `be` - Book equity (nMonths x nStocks), reported on the row of the fiscal year end
+///   month and NaN elsewhere. If several fiscal years end in the same calendar year, the
+///   latest is used.
+/// * `me` - Market equity (nMonths x nStocks).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1).
+/// * `exclude_negative_be` - Set BM to NaN where book equity is negative.
+pub fn make_book_to_market(
+    be: &Array2<f64>,
+    me: &Array2<f64>,
+    dates: &Array2<i32>,
+    exclude_negative_be: bool,
+) -> Array2<f64> {
+    assert_eq!(
+        be.dim(),
+        me.dim(),
+        "BE and ME must have the same dimensions"
+    );
+    assert_eq!(be.nrows(), dates.len(), "BE must have one row per date");
+    let row_of: HashMap<i32, usize> = dates.iter().enumerate().map(|(i, &d)| (d, i)).collect();
+
+    let mut bm = Array2::from_elem(be.dim(), f64::NAN);
+    let mut annual_be = Array2::from_elem(be.dim(), f64::NAN);
+    for &date in dates.iter() {
+        if date % 100 != 6 {
+            continue;
+        }
+        let year = date / 100;
+        let december_row = match row_of.get(&((year - 1) * 100 + 12)) {
+            Some(&row) => row,
+            None => continue,
+        };
+        let holding_rows: Vec<usize> = (0..12)
+            .filter_map(|k| row_of.get(&add_months(date, k)).copied())
+            .collect();
+        for j in 0..be.ncols() {
+            // Latest fiscal year ending in calendar year t-1
+            let fiscal_be = (1..=12)
+                .rev()
+                .filter_map(|m| row_of.get(&((year - 1) * 100 + m)))
+                .map(|&row| be[[row, j]])
+                .find(|b| b.is_finite());
+            if let Some(b) = fiscal_be {
+                let ratio = book_to_market_ratio(b, me[[december_row, j]]);
+                for &row in &holding_rows {
+                    bm[[row, j]] = ratio;
+                    annual_be[[row, j]] = b;
+                }
+            }
+        }
+    }
+    if exclude_negative_be {
+        self::exclude_negative_be(&mut bm, &annual_be);
+    }
+    bm
+}
+
+fn book_to_market_ratio(be: f64, me: f64) -> f64 {
+    if me.is_finite() && me > 0.0 {
+        be / me
+    } else {
+        f64::NAN
+    }
+}
+
+/// Sets the book-to-market signal to NaN wherever book equity is negative, so that
+/// negative-BE firms neither enter the breakpoints nor get assigned to a portfolio.
+pub fn exclude_negative_be(bm: &mut Array2<f64>, be: &Array2<f64>) {
+    assert_eq!(
+        bm.dim(),
+        be.dim(),
+        "BM and BE must have the same dimensions"
+    );
+    Zip::from(bm).and(be).for_each(|x, &b| {
+        if b < 0.0 {
+            *x = f64::NAN;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::matrix_ops::month_range;
+    use ndarray::array;
+
+    fn calendar(start: i32, end: i32) -> Array2<i32> {
+        let months = month_range(start, end);
+        Array2::from_shape_vec((months.len(), 1), months).unwrap()
+    }
+
+    #[test]
+    fn test_exclude_negative_be() {
+        let be = array![[50.0, -20.0, 10.0]];
+        let mut bm = array![[0.5, -0.1, 0.2]];
+
+        exclude_negative_be(&mut bm, &be);
+
+        assert_eq!(bm[[0, 0]], 0.5);
+        assert!(bm[[0, 1]].is_nan());
+        assert_eq!(bm[[0, 2]], 0.2);
+    }
+
+    #[test]
+    fn test_june_fiscal_year_end_uses_december_me() {
+        let dates = calendar(200001, 200212);
+        let row = |d: i32| dates.iter().position(|&x| x == d).unwrap();
+        let mut be = Array2::from_elem((dates.len(), 2), f64::NAN);
+        let mut me = Array2::from_elem((dates.len(), 2), 500.0);
+        // Stock 0 has a June fiscal year end, stock 1 a December one
+        be[[row(200006), 0]] = 100.0;
+        be[[row(200012), 1]] = 300.0;
+        me[[row(200012), 0]] = 1000.0;
+        me[[row(200012), 1]] = 600.0;
+
+        let bm = make_book_to_market(&be, &me, &dates, true);
+
+        // Formed in June 2001 with December 2000 ME, held until May 2002
+        for d in [200106, 200112, 200205] {
+            assert!((bm[[row(d), 0]] - 0.1).abs() < 1e-12, "{}", d);
+            assert!((bm[[row(d), 1]] - 0.5).abs() < 1e-12, "{}", d);
+        }
+        assert!(bm[[row(200105), 0]].is_nan());
+        assert!(bm[[row(200206), 0]].is_nan());
+    }
+
+    #[test]
+    fn test_make_book_to_market_excludes_negative_be() {
+        let dates = calendar(200012, 200106);
+        let mut be = Array2::from_elem((dates.len(), 2), f64::NAN);
+        be[[0, 0]] = 50.0;
+        be[[0, 1]] = -20.0;
+        let me = Array2::from_elem((dates.len(), 2), 100.0);
+
+        let kept = make_book_to_market(&be, &me, &dates, false);
+        let excluded = make_book_to_market(&be, &me, &dates, true);
+
+        let june = dates.len() - 1;
+        assert_eq!(kept[[june, 0]], 0.5);
+        assert_eq!(kept[[june, 1]], -0.2);
+        assert_eq!(excluded[[june, 0]], 0.5);
+        assert!(excluded[[june, 1]].is_nan());
+    }
+}
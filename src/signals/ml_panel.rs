@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Result};
+use ndarray::Array2;
+use polars::prelude::*;
+use std::collections::HashMap;
+
+/// Builds a tidy panel of signals and forward returns, one row per stock-month, for
+/// training cross-sectional models.
+///
+/// The columns are `date` (`YYYYMM`), `permno`, one column per signal in alphabetical
+/// order of their names, and `forward_return`. A stock-month is kept only when every
+/// signal and the forward return are finite.
+///
+/// # Arguments
+///
+/// * `signals` - Signals keyed by name (nMonths x nStocks each).
+/// * `forward_ret` - Label of each stock-month, e.g. the return of month t+1 on row t
+///   (nMonths x nStocks).
+/// * `permno` - Permno vector (nStocks x 1).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1).
+pub fn build_ml_panel(
+    signals: &HashMap<String, Array2<f64>>,
+    forward_ret: &Array2<f64>,
+    permno: &Array2<i32>,
+    dates: &Array2<i32>,
+) -> Result<DataFrame> {
+    if forward_ret.dim() != (dates.len(), permno.len()) {
+        return Err(anyhow!(
+            "Forward returns {:?} do not match {} dates and {} permnos",
+            forward_ret.dim(),
+            dates.len(),
+            permno.len()
+        ));
+    }
+    let mut names: Vec<&String> = signals.keys().collect();
+    names.sort();
+    for name in &names {
+        if signals[*name].dim() != forward_ret.dim() {
+            return Err(anyhow!(
+                "Signal {} {:?} does not match the forward returns {:?}",
+                name,
+                signals[*name].dim(),
+                forward_ret.dim()
+            ));
+        }
+    }
+
+    let mut panel_dates = Vec::new();
+    let mut panel_permnos = Vec::new();
+    let mut features: Vec<Vec<f64>> = vec![Vec::new(); names.len()];
+    let mut labels = Vec::new();
+    for ((t, j), &label) in forward_ret.indexed_iter() {
+        if !label.is_finite() || names.iter().any(|name| !signals[*name][[t, j]].is_finite()) {
+            continue;
+        }
+        panel_dates.push(dates[[t, 0]]);
+        panel_permnos.push(permno[[j, 0]]);
+        for (k, name) in names.iter().enumerate() {
+            features[k].push(signals[*name][[t, j]]);
+        }
+        labels.push(label);
+    }
+
+    let mut columns = vec![
+        Column::new("date".into(), panel_dates),
+        Column::new("permno".into(), panel_permnos),
+    ];
+    for (name, values) in names.iter().zip(features) {
+        columns.push(Column::new(name.as_str().into(), values));
+    }
+    columns.push(Column::new("forward_return".into(), labels));
+    Ok(DataFrame::new(columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_build_ml_panel_drops_incomplete_rows() {
+        let signals = HashMap::from([
+            ("size".to_string(), array![[1.0, 2.0], [3.0, f64::NAN]]),
+            ("bm".to_string(), array![[0.5, 0.6], [0.7, 0.8]]),
+        ]);
+        let forward_ret = array![[0.01, f64::NAN], [0.03, 0.04]];
+        let permno = array![[10001], [10002]];
+        let dates = array![[200001], [200002]];
+
+        let panel = build_ml_panel(&signals, &forward_ret, &permno, &dates).unwrap();
+
+        let names: Vec<&str> = panel
+            .get_column_names()
+            .iter()
+            .map(|n| n.as_str())
+            .collect();
+        assert_eq!(names, ["date", "permno", "bm", "size", "forward_return"]);
+        assert_eq!(panel.height(), 2);
+        let row = panel.get(1).unwrap();
+        assert_eq!(row[0], AnyValue::Int32(200002));
+        assert_eq!(row[1], AnyValue::Int32(10001));
+        assert_eq!(row[2], AnyValue::Float64(0.7));
+        assert_eq!(row[3], AnyValue::Float64(3.0));
+        assert_eq!(row[4], AnyValue::Float64(0.03));
+    }
+}
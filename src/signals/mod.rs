@@ -0,0 +1,3 @@
+pub mod book_to_market;
+pub mod ml_panel;
+pub mod momentum;
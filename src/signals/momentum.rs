@@ -0,0 +1,60 @@
+use ndarray::Array2;
+
+/// Computes past-performance (momentum) signals from the return matrix.
+///
+/// The signal at month t is the compounded return from `t - start_lag` to `t - end_lag`,
+/// both included, and is NaN unless every return in that window is available. The classic
+/// 12-1 momentum (`R`) is `make_momentum(ret, 11, 1)`: eleven months of returns that skip
+/// the most recent month t to avoid the short-term reversal. Portfolios formed on the signal
+/// at the end of month t are held from t+1.
+///
+/// The skip month only separates the signal from the first holding month. When portfolios
+/// are re-formed less often than monthly (see
+/// [`Rebalance::Quarterly`](crate::portfolios::sorts::Rebalance::Quarterly)), the later
+/// months of each holding period are held on a signal that is already two or three months
+/// old, so the gap to the formation window grows accordingly.
+///
+/// # Arguments
+///
+/// * `ret` - Monthly returns (nMonths x nStocks).
+/// * `start_lag` - Lag of the first month in the window (e.g. 11 for `R`).
+/// * `end_lag` - Lag of the last month in the window (e.g. 1 for `R`).
+pub fn make_momentum(ret: &Array2<f64>, start_lag: usize, end_lag: usize) -> Array2<f64> {
+    assert!(
+        start_lag >= end_lag,
+        "start_lag must not be smaller than end_lag"
+    );
+    let mut signal = Array2::from_elem(ret.dim(), f64::NAN);
+    for t in start_lag..ret.nrows() {
+        for j in 0..ret.ncols() {
+            let gross: f64 = (end_lag..=start_lag)
+                .map(|k| 1.0 + ret[[t - k, j]])
+                .product();
+            if gross.is_finite() {
+                signal[[t, j]] = gross - 1.0;
+            }
+        }
+    }
+    signal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_make_momentum_skips_recent_month() {
+        let ret = array![[0.10, 0.0], [0.20, f64::NAN], [0.50, 0.01], [0.30, 0.02]];
+
+        let signal = make_momentum(&ret, 2, 1);
+
+        assert!(signal[[0, 0]].is_nan());
+        assert!(signal[[1, 0]].is_nan());
+        assert!((signal[[2, 0]] - (1.1 * 1.2 - 1.0)).abs() < 1e-12);
+        assert!((signal[[3, 0]] - (1.2 * 1.5 - 1.0)).abs() < 1e-12);
+        // A missing return inside the window leaves the signal missing
+        assert!(signal[[2, 1]].is_nan());
+        assert!(signal[[3, 1]].is_nan());
+    }
+}
@@ -0,0 +1,518 @@
+use ndarray::{Array1, Array2, ArrayView1, Axis};
+
+/// Computes the value-weighted cross-sectional average of a characteristic for each month.
+///
+/// This is the same weighting used for the market return, generalized to any
+/// `nMonths x nStocks` characteristic (e.g. the aggregate book-to-market of the market).
+/// A stock only enters a month's average when both its characteristic and its weight are
+/// finite and the weight is positive. Months without any valid stock are NaN.
+///
+/// # Arguments
+///
+/// * `char` - Characteristic matrix (nMonths x nStocks).
+/// * `me` - Market equity matrix used as weights (nMonths x nStocks).
+///
+/// # Returns
+///
+/// * `Array1<f64>` - The weighted average for each month (nMonths).
+pub fn value_weighted_average(char: &Array2<f64>, me: &Array2<f64>) -> Array1<f64> {
+    assert_eq!(
+        char.dim(),
+        me.dim(),
+        "Characteristic and weight matrices must have the same dimensions"
+    );
+
+    let mut result = Array1::from_elem(char.nrows(), f64::NAN);
+    for (t, (char_row, me_row)) in char
+        .axis_iter(Axis(0))
+        .zip(me.axis_iter(Axis(0)))
+        .enumerate()
+    {
+        let (weighted_sum, weight_sum) = char_row.iter().zip(me_row.iter()).fold(
+            (0.0, 0.0),
+            |(weighted_sum, weight_sum), (&x, &w)| {
+                if x.is_finite() && w.is_finite() && w > 0.0 {
+                    (weighted_sum + x * w, weight_sum + w)
+                } else {
+                    (weighted_sum, weight_sum)
+                }
+            },
+        );
+        if weight_sum > 0.0 {
+            result[t] = weighted_sum / weight_sum;
+        }
+    }
+    result
+}
+
+/// Computes the `q`-th quantile of the finite entries of `values` using linear
+/// interpolation between order statistics. NaNs are dropped before the computation and
+/// NaN is returned when no finite value is left.
+pub fn quantile(values: &[f64], q: f64) -> f64 {
+    let mut finite: Vec<f64> = values.iter().copied().filter(|x| x.is_finite()).collect();
+    if finite.is_empty() {
+        return f64::NAN;
+    }
+    finite.sort_by(|a, b| a.total_cmp(b));
+    quantile_sorted(&finite, q)
+}
+
+/// Computes the median of the finite entries of `values`.
+pub fn median(values: &[f64]) -> f64 {
+    quantile(values, 0.5)
+}
+
+/// Computes the weighted `q`-th quantile of `values`.
+///
+/// The quantile is the smallest value whose cumulative weight reaches `q` of the total
+/// weight; when the cumulative weight lands exactly on the target the midpoint with the
+/// next value is returned, so equal weights reproduce the usual median. Pairs where
+/// either the value or the weight is not finite, or the weight is not positive, are
+/// dropped. Returns NaN when no valid pair is left.
+///
+/// # Arguments
+///
+/// * `values` - Observations.
+/// * `weights` - Weight of each observation (e.g. market equity).
+/// * `q` - Quantile in [0, 1].
+pub fn weighted_quantile(values: &[f64], weights: &[f64], q: f64) -> f64 {
+    assert_eq!(
+        values.len(),
+        weights.len(),
+        "Values and weights must have the same length"
+    );
+
+    let mut pairs: Vec<(f64, f64)> = values
+        .iter()
+        .zip(weights.iter())
+        .filter(|(x, w)| x.is_finite() && w.is_finite() && **w > 0.0)
+        .map(|(&x, &w)| (x, w))
+        .collect();
+    if pairs.is_empty() {
+        return f64::NAN;
+    }
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let total: f64 = pairs.iter().map(|(_, w)| w).sum();
+    let target = q.clamp(0.0, 1.0) * total;
+    let tolerance = 1e-12 * total;
+    let mut cumulative = 0.0;
+    for (i, &(x, w)) in pairs.iter().enumerate() {
+        cumulative += w;
+        if (cumulative - target).abs() <= tolerance && i + 1 < pairs.len() {
+            return 0.5 * (x + pairs[i + 1].0);
+        }
+        if cumulative >= target {
+            return x;
+        }
+    }
+    pairs[pairs.len() - 1].0
+}
+
+/// Linear-interpolation quantile of an already sorted, NaN-free slice.
+pub(crate) fn quantile_sorted(sorted: &[f64], q: f64) -> f64 {
+    let q = q.clamp(0.0, 1.0);
+    let pos = q * (sorted.len() - 1) as f64;
+    let lower = pos.floor() as usize;
+    let upper = pos.ceil() as usize;
+    let frac = pos - lower as f64;
+    sorted[lower] + frac * (sorted[upper] - sorted[lower])
+}
+
+/// Cross-sectional summary statistics of one matrix row (one date).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowStats {
+    /// Number of finite observations.
+    pub count: usize,
+    /// Mean of the finite observations.
+    pub mean: f64,
+    /// Sample standard deviation of the finite observations.
+    pub sd: f64,
+    /// Quantiles of the finite observations, in the order of the requested probabilities.
+    pub quantiles: Vec<f64>,
+}
+
+impl RowStats {
+    /// Computes the statistics of a row in a single pass. The mean and standard deviation
+    /// use Welford's online update and the quantiles use selection (`select_nth_unstable`)
+    /// on `buffer`, which is reused across rows to avoid allocating sorted copies.
+    pub fn from_row(row: ArrayView1<f64>, probs: &[f64], buffer: &mut Vec<f64>) -> Self {
+        buffer.clear();
+        let mut mean = 0.0;
+        let mut m2 = 0.0;
+        for &x in row.iter().filter(|x| x.is_finite()) {
+            buffer.push(x);
+            let delta = x - mean;
+            mean += delta / buffer.len() as f64;
+            m2 += delta * (x - mean);
+        }
+
+        let count = buffer.len();
+        if count == 0 {
+            return RowStats {
+                count,
+                mean: f64::NAN,
+                sd: f64::NAN,
+                quantiles: vec![f64::NAN; probs.len()],
+            };
+        }
+        let sd = if count > 1 {
+            (m2 / (count - 1) as f64).sqrt()
+        } else {
+            f64::NAN
+        };
+        let quantiles = probs.iter().map(|&q| select_quantile(buffer, q)).collect();
+
+        RowStats {
+            count,
+            mean,
+            sd,
+            quantiles,
+        }
+    }
+}
+
+/// Iterator over the [`RowStats`] of each row of a matrix.
+pub struct RowStatsIter<'a> {
+    rows: ndarray::iter::AxisIter<'a, f64, ndarray::Ix1>,
+    probs: &'a [f64],
+    buffer: Vec<f64>,
+}
+
+impl Iterator for RowStatsIter<'_> {
+    type Item = RowStats;
+
+    fn next(&mut self) -> Option<RowStats> {
+        let row = self.rows.next()?;
+        Some(RowStats::from_row(row, self.probs, &mut self.buffer))
+    }
+}
+
+/// Returns an iterator computing the cross-sectional [`RowStats`] of each date (row) of
+/// `m`, with the quantiles evaluated at `probs`.
+pub fn row_stats<'a>(m: &'a Array2<f64>, probs: &'a [f64]) -> RowStatsIter<'a> {
+    RowStatsIter {
+        rows: m.axis_iter(Axis(0)),
+        probs,
+        buffer: Vec::with_capacity(m.ncols()),
+    }
+}
+
+/// Measure of the cross-sectional dispersion of a signal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dispersion {
+    /// Sample standard deviation.
+    #[default]
+    StandardDeviation,
+    /// Difference between the 75th and the 25th percentiles, robust to outliers.
+    InterquartileRange,
+}
+
+/// Computes the cross-sectional dispersion of a signal each month, e.g. to study whether
+/// the spread of an anomaly signal predicts its future performance. NaNs are ignored;
+/// months with fewer than two finite values are NaN for the standard deviation, and
+/// months without any are NaN for the interquartile range.
+///
+/// # Arguments
+///
+/// * `signal` - Signal matrix (nMonths x nStocks).
+/// * `measure` - Dispersion measure.
+pub fn signal_dispersion(signal: &Array2<f64>, measure: Dispersion) -> Array1<f64> {
+    match measure {
+        Dispersion::StandardDeviation => row_stats(signal, &[]).map(|stats| stats.sd).collect(),
+        Dispersion::InterquartileRange => row_stats(signal, &[0.25, 0.75])
+            .map(|stats| stats.quantiles[1] - stats.quantiles[0])
+            .collect(),
+    }
+}
+
+/// Winsorizes a signal cross-sectionally: each month, values below the `lower` quantile
+/// are set to it and values above the `upper` quantile are set to it, with the quantiles
+/// computed over the finite values of the month. NaNs are left as they are, and a month
+/// without any finite value is returned unchanged.
+///
+/// # Arguments
+///
+/// * `signal` - Signal matrix (nMonths x nStocks).
+/// * `lower` - Lower quantile, e.g. 0.01.
+/// * `upper` - Upper quantile, e.g. 0.99.
+pub fn winsorize(signal: &Array2<f64>, lower: f64, upper: f64) -> Array2<f64> {
+    assert!(
+        (0.0..=1.0).contains(&lower) && lower <= upper && upper <= 1.0,
+        "Winsorizing quantiles must satisfy 0 <= lower <= upper <= 1"
+    );
+    let probs = [lower, upper];
+    let mut winsorized = signal.clone();
+    for (mut row, stats) in winsorized
+        .axis_iter_mut(Axis(0))
+        .zip(row_stats(signal, &probs))
+    {
+        if stats.count == 0 {
+            continue;
+        }
+        let (low, high) = (stats.quantiles[0], stats.quantiles[1]);
+        row.mapv_inplace(|x| if x.is_finite() { x.clamp(low, high) } else { x });
+    }
+    winsorized
+}
+
+/// Trims a signal cross-sectionally, in place: each month, values below the `lower`
+/// quantile or above the `upper` quantile are set to NaN instead of being clipped as in
+/// [`winsorize`], which avoids piling observations up at the caps. The quantiles are
+/// computed over the finite values of the month before any is dropped.
+///
+/// # Arguments
+///
+/// * `signal` - Signal matrix (nMonths x nStocks).
+/// * `lower` - Lower quantile, e.g. 0.01.
+/// * `upper` - Upper quantile, e.g. 0.99.
+pub fn trim(signal: &mut Array2<f64>, lower: f64, upper: f64) {
+    assert!(
+        (0.0..=1.0).contains(&lower) && lower <= upper && upper <= 1.0,
+        "Trimming quantiles must satisfy 0 <= lower <= upper <= 1"
+    );
+    let probs = [lower, upper];
+    let mut buffer = Vec::with_capacity(signal.ncols());
+    for mut row in signal.axis_iter_mut(Axis(0)) {
+        let stats = RowStats::from_row(row.view(), &probs, &mut buffer);
+        if stats.count == 0 {
+            continue;
+        }
+        let (low, high) = (stats.quantiles[0], stats.quantiles[1]);
+        row.mapv_inplace(|x| if x < low || x > high { f64::NAN } else { x });
+    }
+}
+
+/// Cross-sectional R² of a factor model each month: how much of the dispersion of the
+/// realized returns is explained by the returns the model predicts, `betas * factor_means`.
+///
+/// Month t compares the realized returns with the predicted ones over the stocks where both
+/// are finite, `R² = 1 - sum((r - r_hat)^2) / sum((r - mean(r))^2)`, without refitting an
+/// intercept or slope, so it is 1 when the predictions are exact and can be negative.
+/// Months with fewer than two stocks, or without any dispersion in the realized returns,
+/// are NaN.
+///
+/// # Arguments
+///
+/// * `ret` - Realized returns (nMonths x nStocks).
+/// * `betas` - Factor loadings of each stock (nStocks x nFactors).
+/// * `factor_means` - Factor risk premia (nFactors).
+pub fn cross_sectional_r2(
+    ret: &Array2<f64>,
+    betas: &Array2<f64>,
+    factor_means: &Array1<f64>,
+) -> Array1<f64> {
+    assert_eq!(
+        betas.nrows(),
+        ret.ncols(),
+        "Betas must have one row per stock"
+    );
+    assert_eq!(
+        betas.ncols(),
+        factor_means.len(),
+        "Betas must have one column per factor"
+    );
+    let predicted = betas.dot(factor_means);
+    ret.axis_iter(Axis(0))
+        .map(|row| {
+            let pairs: Vec<(f64, f64)> = row
+                .iter()
+                .zip(predicted.iter())
+                .filter(|(r, p)| r.is_finite() && p.is_finite())
+                .map(|(&r, &p)| (r, p))
+                .collect();
+            if pairs.len() < 2 {
+                return f64::NAN;
+            }
+            let mean = pairs.iter().map(|(r, _)| r).sum::<f64>() / pairs.len() as f64;
+            let tss: f64 = pairs.iter().map(|(r, _)| (r - mean).powi(2)).sum();
+            let rss: f64 = pairs.iter().map(|(r, p)| (r - p).powi(2)).sum();
+            if tss > 0.0 {
+                1.0 - rss / tss
+            } else {
+                f64::NAN
+            }
+        })
+        .collect()
+}
+
+/// Linear-interpolation quantile of a NaN-free, unsorted buffer using selection. The
+/// buffer is partially reordered in place.
+fn select_quantile(buffer: &mut [f64], q: f64) -> f64 {
+    let pos = q.clamp(0.0, 1.0) * (buffer.len() - 1) as f64;
+    let lower = pos.floor() as usize;
+    let frac = pos - lower as f64;
+    let (_, &mut lower_value, upper_part) = buffer.select_nth_unstable_by(lower, f64::total_cmp);
+    if frac == 0.0 || upper_part.is_empty() {
+        return lower_value;
+    }
+    let upper_value = upper_part.iter().copied().fold(f64::INFINITY, f64::min);
+    lower_value + frac * (upper_value - lower_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_value_weighted_average_two_stocks() {
+        let char = array![[1.0, 2.0], [0.5, f64::NAN], [f64::NAN, f64::NAN]];
+        let me = array![[100.0, 300.0], [10.0, 50.0], [1.0, 1.0]];
+
+        let vw = value_weighted_average(&char, &me);
+
+        // (1 * 100 + 2 * 300) / 400
+        assert!((vw[0] - 1.75).abs() < 1e-12);
+        // The NaN characteristic drops its weight as well
+        assert!((vw[1] - 0.5).abs() < 1e-12);
+        assert!(vw[2].is_nan());
+    }
+
+    #[test]
+    fn test_value_weighted_average_ignores_missing_weights() {
+        let char = array![[1.0, 3.0, 5.0]];
+        let me = array![[1.0, f64::NAN, 0.0]];
+
+        let vw = value_weighted_average(&char, &me);
+
+        assert!((vw[0] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_quantile_interpolates_and_drops_nan() {
+        let values = [4.0, f64::NAN, 1.0, 3.0, 2.0];
+        assert!((quantile(&values, 0.5) - 2.5).abs() < 1e-12);
+        assert!((quantile(&values, 0.0) - 1.0).abs() < 1e-12);
+        assert!((quantile(&values, 1.0) - 4.0).abs() < 1e-12);
+        assert!(quantile(&[f64::NAN], 0.5).is_nan());
+    }
+
+    #[test]
+    fn test_weighted_quantile_hand_computed_median() {
+        // Sorted values 1, 2, 3, 4 with weights 0.1, 0.2, 0.4, 0.3: the cumulative
+        // weight first reaches half of the total at 3.
+        let values = [3.0, 1.0, 4.0, 2.0, f64::NAN];
+        let weights = [0.4, 0.1, 0.3, 0.2, 5.0];
+
+        assert_eq!(weighted_quantile(&values, &weights, 0.5), 3.0);
+        assert_eq!(weighted_quantile(&values, &weights, 0.25), 2.0);
+        assert_eq!(weighted_quantile(&values, &weights, 1.0), 4.0);
+    }
+
+    #[test]
+    fn test_weighted_quantile_equal_weights_matches_median() {
+        let values = [5.0, 1.0, 3.0, 2.0];
+        let weights = [1.0; 4];
+
+        assert_eq!(weighted_quantile(&values, &weights, 0.5), 2.5);
+        assert_eq!(median(&values), 2.5);
+        assert!(weighted_quantile(&[1.0], &[f64::NAN], 0.5).is_nan());
+    }
+
+    #[test]
+    fn test_signal_dispersion_known_month() {
+        let signal = array![
+            [1.0, 2.0, 3.0, 4.0, 5.0, f64::NAN],
+            [7.0, f64::NAN, f64::NAN, f64::NAN, f64::NAN, f64::NAN]
+        ];
+
+        let sd = signal_dispersion(&signal, Dispersion::StandardDeviation);
+        let iqr = signal_dispersion(&signal, Dispersion::InterquartileRange);
+
+        assert!((sd[0] - 2.5f64.sqrt()).abs() < 1e-12);
+        assert!(sd[1].is_nan());
+        assert!((iqr[0] - 2.0).abs() < 1e-12);
+        assert_eq!(iqr[1], 0.0);
+    }
+
+    #[test]
+    fn test_row_stats_matches_naive_computation() {
+        let m = array![
+            [0.3, -1.2, f64::NAN, 4.5, 2.2, 0.0, 7.1],
+            [
+                f64::NAN,
+                f64::NAN,
+                f64::NAN,
+                f64::NAN,
+                f64::NAN,
+                f64::NAN,
+                f64::NAN
+            ],
+            [1.0, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0]
+        ];
+        let probs = [0.1, 0.25, 0.5, 0.9];
+
+        let stats: Vec<RowStats> = row_stats(&m, &probs).collect();
+
+        assert_eq!(stats.len(), 3);
+        assert_eq!(stats[1].count, 0);
+        assert!(stats[1].mean.is_nan());
+        for t in [0, 2] {
+            let values: Vec<f64> = m.row(t).iter().copied().filter(|x| x.is_finite()).collect();
+            let n = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / n;
+            let sd = (values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+
+            assert_eq!(stats[t].count, values.len());
+            assert!((stats[t].mean - mean).abs() < 1e-12);
+            assert!((stats[t].sd - sd).abs() < 1e-12);
+            for (k, &q) in probs.iter().enumerate() {
+                assert!((stats[t].quantiles[k] - quantile(&values, q)).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_winsorize_clips_each_month() {
+        let nan = f64::NAN;
+        let mut signal = Array2::from_shape_fn((3, 11), |(_, j)| j as f64);
+        signal[[0, 10]] = 1000.0;
+        signal[[1, 3]] = nan;
+        signal.row_mut(2).fill(nan);
+
+        let winsorized = winsorize(&signal, 0.1, 0.9);
+
+        // Month 0: the 10th and 90th percentiles of 0..=9 and 1000 are 1 and 9
+        assert_eq!(winsorized[[0, 0]], 1.0);
+        assert_eq!(winsorized[[0, 10]], 9.0);
+        assert_eq!(winsorized[[0, 5]], 5.0);
+        assert!(winsorized[[1, 3]].is_nan());
+        assert!(winsorized.row(2).iter().all(|x| x.is_nan()));
+    }
+
+    #[test]
+    fn test_trim_drops_what_winsorize_clips() {
+        let mut signal = Array2::from_shape_fn((2, 11), |(_, j)| j as f64);
+        signal[[0, 10]] = 1000.0;
+        signal[[1, 4]] = f64::NAN;
+        let winsorized = winsorize(&signal, 0.1, 0.9);
+
+        trim(&mut signal, 0.1, 0.9);
+
+        assert_eq!(winsorized[[0, 10]], 9.0);
+        assert!(signal[[0, 10]].is_nan());
+        assert!(signal[[0, 0]].is_nan());
+        assert_eq!(signal[[0, 5]], 5.0);
+        assert!(signal[[1, 4]].is_nan());
+        let kept = signal.row(0).iter().filter(|x| x.is_finite()).count();
+        assert_eq!(kept, 9);
+    }
+
+    #[test]
+    fn test_cross_sectional_r2_exact_predictions() {
+        let betas = array![[0.5, 1.0], [1.0, 0.0], [1.5, -1.0], [2.0, 0.5]];
+        let factor_means = array![0.01, 0.004];
+        let predicted = betas.dot(&factor_means);
+        let mut ret = Array2::from_shape_fn((3, 4), |(_, j)| predicted[j]);
+        ret[[1, 2]] = f64::NAN;
+        // The last month is off by a constant shift
+        ret.row_mut(2).mapv_inplace(|r| r + 0.01);
+
+        let r2 = cross_sectional_r2(&ret, &betas, &factor_means);
+
+        assert!((r2[0] - 1.0).abs() < 1e-12);
+        assert!((r2[1] - 1.0).abs() < 1e-12);
+        assert!(r2[2] < 1.0);
+    }
+}
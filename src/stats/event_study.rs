@@ -0,0 +1,186 @@
+use anyhow::{anyhow, Result};
+use ndarray::{s, Array1, Array2};
+use polars::prelude::*;
+use std::collections::HashMap;
+
+/// Average cumulative abnormal returns around the delisting month of each stock.
+///
+/// The abnormal return of a stock in month t is its return minus the market return.
+/// For each event month k in `window.0..=window.1` (0 being the delisting month), the
+/// abnormal returns of all stocks with a valid observation at k are averaged, and the
+/// averages are cumulated from the start of the window.
+///
+/// # Arguments
+///
+/// * `ret` - Returns (nMonths x nStocks).
+/// * `mkt` - Market returns (nMonths).
+/// * `delist_month` - Row index of the delisting month of each delisted stock, keyed by
+///   the stock's column index in `ret`.
+/// * `window` - First and last event month, relative to delisting.
+///
+/// # Returns
+///
+/// * `Result<DataFrame>` - One row per event month with columns `event_month`,
+///   `n_stocks`, `mean_ar` and `car`.
+pub fn car_around_delisting(
+    ret: &Array2<f64>,
+    mkt: &Array1<f64>,
+    delist_month: &HashMap<i32, i32>,
+    window: (i32, i32),
+) -> Result<DataFrame> {
+    if mkt.len() != ret.nrows() {
+        return Err(anyhow!(
+            "Market return has {} months but returns have {}",
+            mkt.len(),
+            ret.nrows()
+        ));
+    }
+    if window.0 > window.1 {
+        return Err(anyhow!("Invalid event window {:?}", window));
+    }
+    if let Some(column) = delist_month
+        .keys()
+        .find(|&&j| j < 0 || j as usize >= ret.ncols())
+    {
+        return Err(anyhow!("Stock column {} is out of range", column));
+    }
+
+    let event_months: Vec<i32> = (window.0..=window.1).collect();
+    let mut n_stocks = Vec::with_capacity(event_months.len());
+    let mut mean_ar = Vec::with_capacity(event_months.len());
+    for &k in &event_months {
+        let abnormal: Vec<f64> = delist_month
+            .iter()
+            .filter_map(|(&j, &delisting)| {
+                let t = delisting + k;
+                if t < 0 || t as usize >= ret.nrows() {
+                    return None;
+                }
+                let ar = ret[[t as usize, j as usize]] - mkt[t as usize];
+                ar.is_finite().then_some(ar)
+            })
+            .collect();
+        n_stocks.push(abnormal.len() as u32);
+        mean_ar.push(if abnormal.is_empty() {
+            f64::NAN
+        } else {
+            abnormal.iter().sum::<f64>() / abnormal.len() as f64
+        });
+    }
+    let car: Vec<f64> = mean_ar
+        .iter()
+        .scan(0.0, |cumulative, ar| {
+            if ar.is_finite() {
+                *cumulative += ar;
+            }
+            Some(*cumulative)
+        })
+        .collect();
+
+    Ok(df![
+        "event_month" => event_months,
+        "n_stocks" => n_stocks,
+        "mean_ar" => mean_ar,
+        "car" => car,
+    ]?)
+}
+
+/// Compounded buy-and-hold return of each stock from row `start_row` to row `end_row`,
+/// both included: `prod(1 + r) - 1` over the months of the window with a return.
+///
+/// Missing returns are gaps that are skipped rather than ending the holding, and a stock
+/// without any return in the window gets NaN.
+///
+/// # Arguments
+///
+/// * `ret` - Returns (nMonths x nStocks).
+/// * `start_row` - First month of the window.
+/// * `end_row` - Last month of the window.
+pub fn buy_and_hold(ret: &Array2<f64>, start_row: usize, end_row: usize) -> Array1<f64> {
+    assert!(
+        start_row <= end_row && end_row < ret.nrows(),
+        "Invalid window {}..={} for {} months",
+        start_row,
+        end_row,
+        ret.nrows()
+    );
+    ret.slice(s![start_row..=end_row, ..])
+        .columns()
+        .into_iter()
+        .map(|column| {
+            let mut growth = 1.0;
+            let mut n_months = 0;
+            for r in column.iter().filter(|r| r.is_finite()) {
+                growth *= 1.0 + r;
+                n_months += 1;
+            }
+            if n_months > 0 {
+                growth - 1.0
+            } else {
+                f64::NAN
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_car_around_delisting_known_drop() {
+        let mkt = Array1::from_elem(12, 0.01);
+        let mut ret = Array2::from_elem((12, 3), 0.01);
+        // Stocks 0 and 2 lose 10% relative to the market in the two months before
+        // delisting, stock 0 in month 8 and stock 2 in month 6
+        for (j, delisting) in [(0, 8), (2, 6)] {
+            ret[[delisting - 2, j]] = -0.09;
+            ret[[delisting - 1, j]] = -0.09;
+            for t in delisting + 1..12 {
+                ret[[t, j]] = f64::NAN;
+            }
+        }
+        let delist_month = HashMap::from([(0, 8), (2, 6)]);
+
+        let car = car_around_delisting(&ret, &mkt, &delist_month, (-3, 1)).unwrap();
+
+        assert_eq!(car.height(), 5);
+        let n_stocks: Vec<u32> = car
+            .column("n_stocks")
+            .unwrap()
+            .u32()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(n_stocks, vec![2, 2, 2, 2, 0]);
+        let cumulative: Vec<f64> = car
+            .column("car")
+            .unwrap()
+            .f64()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        let expected = [0.0, -0.1, -0.2, -0.2, -0.2];
+        for (c, e) in cumulative.iter().zip(expected) {
+            assert!((c - e).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_buy_and_hold_compounds_over_gaps() {
+        let nan = f64::NAN;
+        let ret = ndarray::array![
+            [0.50, 0.1, nan],
+            [0.10, nan, nan],
+            [-0.20, 0.2, nan],
+            [0.05, 0.1, 0.3],
+        ];
+
+        let bh = buy_and_hold(&ret, 1, 3);
+
+        assert!((bh[0] - (1.1 * 0.8 * 1.05 - 1.0)).abs() < 1e-12);
+        assert!((bh[1] - (1.2 * 1.1 - 1.0)).abs() < 1e-12);
+        assert!((bh[2] - 0.3).abs() < 1e-12);
+        assert!(buy_and_hold(&ret, 0, 2)[2].is_nan());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod cross_section;
+pub mod event_study;
+pub mod regression;
+pub mod time_series;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
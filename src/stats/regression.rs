@@ -0,0 +1,659 @@
+use anyhow::{anyhow, Result};
+use ndarray::{Array1, Array2, Axis};
+use statrs::distribution::{ContinuousCDF, FisherSnedecor};
+use std::collections::{HashMap, HashSet};
+
+/// Output of an ordinary least squares regression.
+#[derive(Debug, Clone)]
+pub struct OlsResult {
+    /// Estimated coefficients, in the order of the regressor columns.
+    pub coefficients: Array1<f64>,
+    /// Classical (homoskedastic) standard errors of the coefficients.
+    pub std_errors: Array1<f64>,
+    /// Residuals of the observations used in the fit.
+    pub residuals: Array1<f64>,
+    /// Coefficient of determination (centered).
+    pub r_squared: f64,
+    /// Number of observations used.
+    pub n_obs: usize,
+}
+
+/// Fits `y = x * b + e` by ordinary least squares.
+///
+/// The regressor matrix is used as is: add a column of ones with [`add_constant`] to
+/// estimate an intercept. Returns an error when the dimensions disagree, when there
+/// are fewer observations than regressors, or when `x'x` is singular.
+pub fn ols(y: &Array1<f64>, x: &Array2<f64>) -> Result<OlsResult> {
+    if y.len() != x.nrows() {
+        return Err(anyhow!(
+            "Dependent variable has {} observations but regressors have {}",
+            y.len(),
+            x.nrows()
+        ));
+    }
+    if x.nrows() < x.ncols() {
+        return Err(anyhow!(
+            "Not enough observations ({}) for {} regressors",
+            x.nrows(),
+            x.ncols()
+        ));
+    }
+    let xtx_inv = invert(&x.t().dot(x))?;
+    let coefficients = xtx_inv.dot(&x.t().dot(y));
+    let residuals = y - &x.dot(&coefficients);
+
+    let mean = y.mean().unwrap_or(f64::NAN);
+    let tss: f64 = y.iter().map(|v| (v - mean).powi(2)).sum();
+    let rss: f64 = residuals.iter().map(|e| e * e).sum();
+    let sigma2 = rss / (x.nrows() as f64 - x.ncols() as f64);
+    let std_errors = xtx_inv.diag().mapv(|v| (v * sigma2).sqrt());
+    Ok(OlsResult {
+        coefficients,
+        std_errors,
+        residuals,
+        r_squared: 1.0 - rss / tss,
+        n_obs: y.len(),
+    })
+}
+
+/// Share of a strategy's return variance explained by a factor model: the R² of a
+/// regression of the strategy on a constant and the factors, over the months where all
+/// series are available. NaN when there are too few months or the factors are collinear.
+/// Returns an error when the strategy and the factors do not have the same number of
+/// months; see [`align_by_date`] to align them first.
+///
+/// # Arguments
+///
+/// * `strategy` - Strategy returns (nMonths).
+/// * `factors` - Factor returns (nMonths x nFactors).
+pub fn explained_variance_by_factors(strategy: &Array1<f64>, factors: &Array2<f64>) -> Result<f64> {
+    if strategy.len() != factors.nrows() {
+        return Err(anyhow!(
+            "Strategy has {} months but factors have {}",
+            strategy.len(),
+            factors.nrows()
+        ));
+    }
+    let months: Vec<usize> = (0..strategy.len())
+        .filter(|&t| strategy[t].is_finite() && factors.row(t).iter().all(|f| f.is_finite()))
+        .collect();
+    if months.len() <= factors.ncols() + 1 {
+        return Ok(f64::NAN);
+    }
+    let y = strategy.select(Axis(0), &months);
+    let x = add_constant(&factors.select(Axis(0), &months));
+    Ok(ols(&y, &x).map_or(f64::NAN, |fit| fit.r_squared))
+}
+
+/// Aligns portfolio returns and factors on their common months.
+///
+/// The regression functions match observations by row position, so series covering
+/// different samples must be aligned first. Rows are matched on their `YYYYMM` dates and
+/// only the months present in both inputs are kept, in the order of `return_dates`.
+/// Returns an error when a matrix does not have one row per date, when a dates vector
+/// has duplicates, or when the two samples have no month in common.
+///
+/// # Returns
+///
+/// * `Result<(Array2<f64>, Array2<f64>, Array2<i32>)>` - The aligned returns, factors
+///   and their common dates vector (nMonths x 1).
+pub fn align_by_date(
+    returns: &Array2<f64>,
+    return_dates: &Array2<i32>,
+    factors: &Array2<f64>,
+    factor_dates: &Array2<i32>,
+) -> Result<(Array2<f64>, Array2<f64>, Array2<i32>)> {
+    if returns.nrows() != return_dates.len() {
+        return Err(anyhow!(
+            "Returns have {} months but there are {} return dates",
+            returns.nrows(),
+            return_dates.len()
+        ));
+    }
+    if factors.nrows() != factor_dates.len() {
+        return Err(anyhow!(
+            "Factors have {} months but there are {} factor dates",
+            factors.nrows(),
+            factor_dates.len()
+        ));
+    }
+    let mut factor_row = HashMap::with_capacity(factor_dates.len());
+    for (t, &date) in factor_dates.iter().enumerate() {
+        if factor_row.insert(date, t).is_some() {
+            return Err(anyhow!("Factor dates contain {} more than once", date));
+        }
+    }
+    let mut seen = HashSet::with_capacity(return_dates.len());
+    let mut return_rows = Vec::new();
+    let mut factor_rows = Vec::new();
+    let mut dates = Vec::new();
+    for (t, &date) in return_dates.iter().enumerate() {
+        if !seen.insert(date) {
+            return Err(anyhow!("Return dates contain {} more than once", date));
+        }
+        if let Some(&f) = factor_row.get(&date) {
+            return_rows.push(t);
+            factor_rows.push(f);
+            dates.push(date);
+        }
+    }
+    if dates.is_empty() {
+        return Err(anyhow!("Returns and factors have no month in common"));
+    }
+    Ok((
+        returns.select(Axis(0), &return_rows),
+        factors.select(Axis(0), &factor_rows),
+        Array2::from_shape_vec((dates.len(), 1), dates)?,
+    ))
+}
+
+/// Prepends a column of ones to a regressor matrix.
+pub fn add_constant(x: &Array2<f64>) -> Array2<f64> {
+    let mut with_constant = Array2::ones((x.nrows(), x.ncols() + 1));
+    with_constant.slice_mut(ndarray::s![.., 1..]).assign(x);
+    with_constant
+}
+
+/// Inverts a square matrix by Gauss-Jordan elimination with partial pivoting.
+/// Returns an error if the matrix is singular (or numerically so).
+pub fn invert(a: &Array2<f64>) -> Result<Array2<f64>> {
+    let n = a.nrows();
+    if n != a.ncols() {
+        return Err(anyhow!("Cannot invert a non-square {:?} matrix", a.dim()));
+    }
+    let scale = a.iter().fold(0.0f64, |m, v| m.max(v.abs())).max(1.0);
+    let mut m = a.clone();
+    let mut inv = Array2::eye(n);
+    for c in 0..n {
+        let pivot = (c..n)
+            .max_by(|&i, &j| m[[i, c]].abs().total_cmp(&m[[j, c]].abs()))
+            .unwrap();
+        if m[[pivot, c]].abs() <= 1e-12 * scale {
+            return Err(anyhow!("Matrix is singular and cannot be inverted"));
+        }
+        if pivot != c {
+            for k in 0..n {
+                m.swap([pivot, k], [c, k]);
+                inv.swap([pivot, k], [c, k]);
+            }
+        }
+        let d = m[[c, c]];
+        m.row_mut(c).mapv_inplace(|v| v / d);
+        inv.row_mut(c).mapv_inplace(|v| v / d);
+        for r in 0..n {
+            if r != c {
+                let f = m[[r, c]];
+                if f != 0.0 {
+                    let m_c = m.row(c).to_owned();
+                    let inv_c = inv.row(c).to_owned();
+                    m.row_mut(r).scaled_add(-f, &m_c);
+                    inv.row_mut(r).scaled_add(-f, &inv_c);
+                }
+            }
+        }
+    }
+    Ok(inv)
+}
+
+/// Standard lag length for Newey-West adjustments, `floor(4 * (T / 100)^(2/9))`.
+pub fn default_newey_west_lags(n_obs: usize) -> usize {
+    (4.0 * (n_obs as f64 / 100.0).powf(2.0 / 9.0)).floor() as usize
+}
+
+/// How the standard errors of the Fama-MacBeth average slopes are computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StdErrorMethod {
+    /// Newey-West (Bartlett kernel) standard errors of the slope time series, robust to
+    /// serial correlation in the monthly slopes (e.g. from persistent characteristics).
+    /// `None` uses [`default_newey_west_lags`].
+    NeweyWest { lags: Option<usize> },
+    /// The classic Fama-MacBeth standard error, `sd(slopes) / sqrt(T)`. Because each
+    /// month contributes a single slope estimate, it is robust to any cross-sectional
+    /// correlation of the residuals within a month, which is what clustering by time
+    /// delivers in a pooled regression. It assumes the monthly slopes are serially
+    /// uncorrelated.
+    ClusteredByTime,
+}
+
+impl Default for StdErrorMethod {
+    fn default() -> Self {
+        StdErrorMethod::NeweyWest { lags: None }
+    }
+}
+
+/// Output of a Fama-MacBeth regression.
+#[derive(Debug, Clone)]
+pub struct FamaMacBethResult {
+    /// Time-series average of the monthly slopes; the first entry is the intercept,
+    /// followed by one entry per characteristic.
+    pub coefficients: Array1<f64>,
+    /// Standard errors of the average slopes.
+    pub std_errors: Array1<f64>,
+    /// t-statistics of the average slopes.
+    pub t_stats: Array1<f64>,
+    /// Monthly slopes (nMonths x (1 + nCharacteristics)), NaN for skipped months.
+    pub slopes: Array2<f64>,
+    /// Number of months with a cross-sectional regression.
+    pub n_months: usize,
+    /// Method used for the standard errors.
+    pub method: StdErrorMethod,
+}
+
+impl FamaMacBethResult {
+    /// Average slope and its t-statistic for the characteristic at index `characteristic`
+    /// of the `x` passed to [`fama_macbeth`], skipping the intercept.
+    pub fn characteristic(&self, characteristic: usize) -> (f64, f64) {
+        (
+            self.coefficients[characteristic + 1],
+            self.t_stats[characteristic + 1],
+        )
+    }
+}
+
+/// Default minimum number of stocks of a monthly cross-sectional regression in
+/// [`fama_macbeth`].
+pub const FAMA_MACBETH_MIN_OBS: usize = 30;
+
+/// Runs Fama-MacBeth regressions of returns on characteristics.
+///
+/// Each month, returns are regressed cross-sectionally on an intercept and the
+/// characteristics, using the stocks for which all of them are available. Months with
+/// fewer than `min_obs` such stocks (e.g. [`FAMA_MACBETH_MIN_OBS`]), or not more stocks
+/// than regressors, are skipped. The coefficients are the time-series averages of the
+/// monthly slopes, with standard errors computed according to `method`.
+///
+/// # Arguments
+///
+/// * `y` - Returns (nMonths x nStocks).
+/// * `x` - Characteristics, each (nMonths x nStocks).
+/// * `method` - Standard error method.
+/// * `min_obs` - Minimum number of stocks of a monthly regression.
+pub fn fama_macbeth(
+    y: &Array2<f64>,
+    x: &[Array2<f64>],
+    method: StdErrorMethod,
+    min_obs: usize,
+) -> Result<FamaMacBethResult> {
+    if x.iter().any(|c| c.dim() != y.dim()) {
+        return Err(anyhow!(
+            "All characteristics must have the same dimensions as the returns {:?}",
+            y.dim()
+        ));
+    }
+    let k = x.len() + 1;
+    let mut slopes = Array2::from_elem((y.nrows(), k), f64::NAN);
+    for t in 0..y.nrows() {
+        let valid: Vec<usize> = (0..y.ncols())
+            .filter(|&j| y[[t, j]].is_finite() && x.iter().all(|c| c[[t, j]].is_finite()))
+            .collect();
+        if valid.len() <= k || valid.len() < min_obs {
+            continue;
+        }
+        let y_t: Array1<f64> = valid.iter().map(|&j| y[[t, j]]).collect();
+        let x_t = Array2::from_shape_fn((valid.len(), k), |(i, c)| {
+            if c == 0 {
+                1.0
+            } else {
+                x[c - 1][[t, valid[i]]]
+            }
+        });
+        if let Ok(fit) = ols(&y_t, &x_t) {
+            slopes.row_mut(t).assign(&fit.coefficients);
+        }
+    }
+
+    let months: Vec<usize> = (0..y.nrows())
+        .filter(|&t| slopes[[t, 0]].is_finite())
+        .collect();
+    let n_months = months.len();
+    if n_months < 2 {
+        return Err(anyhow!(
+            "Fewer than two months with a valid cross-sectional regression"
+        ));
+    }
+    let used = slopes.select(Axis(0), &months);
+    let coefficients = used.mean_axis(Axis(0)).unwrap();
+    let std_errors: Array1<f64> = used
+        .axis_iter(Axis(1))
+        .map(|series| match method {
+            StdErrorMethod::ClusteredByTime => series.std(1.0) / (n_months as f64).sqrt(),
+            StdErrorMethod::NeweyWest { lags } => newey_west_mean_se(
+                &series.to_owned(),
+                lags.unwrap_or_else(|| default_newey_west_lags(n_months)),
+            ),
+        })
+        .collect();
+    let t_stats = &coefficients / &std_errors;
+
+    Ok(FamaMacBethResult {
+        coefficients,
+        std_errors,
+        t_stats,
+        slopes,
+        n_months,
+        method,
+    })
+}
+
+/// Output of the Gibbons-Ross-Shanken test.
+#[derive(Debug, Clone)]
+pub struct GrsResult {
+    /// GRS F-statistic.
+    pub statistic: f64,
+    /// p-value from the F(N, T - N - L) distribution.
+    pub p_value: f64,
+    /// Intercept of each portfolio's time-series regression on the factors.
+    pub alphas: Array1<f64>,
+    /// Number of months used (T).
+    pub n_months: usize,
+}
+
+/// Gibbons-Ross-Shanken test that the alphas of all portfolios are jointly zero.
+///
+/// The inputs are matched by row, see [`align_by_date`] for series covering different
+/// samples. Each portfolio's excess return is regressed on a constant and the factors
+/// over the months where every series is available, and
+///
+/// `GRS = (T - N - L) / N * a' S^-1 a / (1 + m' W^-1 m)`
+///
+/// where `a` are the alphas, `S` the residual covariance, `m` the factor means and `W`
+/// the factor covariance (both covariances with a `1/T` denominator). Under normality
+/// it follows an F(N, T - N - L) distribution. Returns an error when there are not
+/// enough months or when either covariance matrix is singular.
+///
+/// # Arguments
+///
+/// * `portfolio_returns` - Portfolio excess returns (nMonths x N).
+/// * `factors` - Factor returns (nMonths x L).
+pub fn grs_test(portfolio_returns: &Array2<f64>, factors: &Array2<f64>) -> Result<GrsResult> {
+    if portfolio_returns.nrows() != factors.nrows() {
+        return Err(anyhow!(
+            "Portfolio returns have {} months but factors have {}",
+            portfolio_returns.nrows(),
+            factors.nrows()
+        ));
+    }
+    let months: Vec<usize> = (0..factors.nrows())
+        .filter(|&t| {
+            portfolio_returns.row(t).iter().all(|v| v.is_finite())
+                && factors.row(t).iter().all(|v| v.is_finite())
+        })
+        .collect();
+    let (n, l, t) = (portfolio_returns.ncols(), factors.ncols(), months.len());
+    if t <= n + l {
+        return Err(anyhow!(
+            "GRS test needs more than {} months, only {} available",
+            n + l,
+            t
+        ));
+    }
+    let returns = portfolio_returns.select(Axis(0), &months);
+    let f = factors.select(Axis(0), &months);
+    let x = add_constant(&f);
+
+    let mut alphas = Array1::zeros(n);
+    let mut residuals = Array2::zeros((t, n));
+    for (i, y) in returns.axis_iter(Axis(1)).enumerate() {
+        let fit = ols(&y.to_owned(), &x)?;
+        alphas[i] = fit.coefficients[0];
+        residuals.column_mut(i).assign(&fit.residuals);
+    }
+    let sigma = residuals.t().dot(&residuals) / t as f64;
+    let factor_means = f.mean_axis(Axis(0)).unwrap();
+    let demeaned = &f - &factor_means;
+    let omega = demeaned.t().dot(&demeaned) / t as f64;
+
+    let quadratic = alphas.dot(&invert(&sigma)?.dot(&alphas));
+    let sharpe = factor_means.dot(&invert(&omega)?.dot(&factor_means));
+    let (df1, df2) = (n as f64, (t - n - l) as f64);
+    let statistic = df2 / df1 * quadratic / (1.0 + sharpe);
+    let p_value = FisherSnedecor::new(df1, df2)?.sf(statistic);
+
+    Ok(GrsResult {
+        statistic,
+        p_value,
+        alphas,
+        n_months: t,
+    })
+}
+
+/// Newey-West (HAC) standard errors of OLS coefficients.
+///
+/// The covariance is `(x'x)^-1 S (x'x)^-1`, where `S` sums the products
+/// `e_t e_{t-l} x_t x_{t-l}'` over the lags `l = 0..=lags`, the lagged terms counted in both
+/// directions and weighted by the Bartlett kernel `1 - l / (lags + 1)`. Lags beyond the
+/// sample are ignored. Returns NaN standard errors when `x'x` is singular, and an error
+/// when the residuals and the regressors do not have the same number of observations.
+///
+/// # Arguments
+///
+/// * `residuals` - Regression residuals (nObs).
+/// * `x` - Regressors of the regression, including any constant (nObs x nRegressors).
+/// * `lags` - Number of lags, e.g. [`default_newey_west_lags`].
+pub fn newey_west(residuals: &Array1<f64>, x: &Array2<f64>, lags: usize) -> Result<Array1<f64>> {
+    if residuals.len() != x.nrows() {
+        return Err(anyhow!(
+            "{} residuals but {} observations of the regressors",
+            residuals.len(),
+            x.nrows()
+        ));
+    }
+    let Ok(xtx_inv) = invert(&x.t().dot(x)) else {
+        return Ok(Array1::from_elem(x.ncols(), f64::NAN));
+    };
+    let n = residuals.len();
+    // Scores e_t * x_t, one row per observation
+    let scores = x * &residuals.view().insert_axis(Axis(1));
+    let mut s = scores.t().dot(&scores);
+    for l in 1..=lags.min(n.saturating_sub(1)) {
+        let gamma = scores
+            .slice(ndarray::s![l.., ..])
+            .t()
+            .dot(&scores.slice(ndarray::s![..n - l, ..]));
+        let weight = 1.0 - l as f64 / (lags + 1) as f64;
+        s = s + weight * (&gamma + &gamma.t());
+    }
+    Ok(xtx_inv
+        .dot(&s)
+        .dot(&xtx_inv)
+        .diag()
+        .mapv(|v| v.max(0.0).sqrt()))
+}
+
+/// Newey-West standard error of the mean of a series: the [`newey_west`] standard error
+/// of the intercept of a regression on a constant.
+pub(crate) fn newey_west_mean_se(series: &Array1<f64>, lags: usize) -> f64 {
+    let mean = series.mean().unwrap_or(f64::NAN);
+    let demeaned = series - mean;
+    // The constant has one row per observation, so the lengths always match
+    newey_west(&demeaned, &Array2::ones((series.len(), 1)), lags).map_or(f64::NAN, |se| se[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::test_utils::lcg_noise;
+    use ndarray::array;
+
+    #[test]
+    fn test_newey_west_two_lags_by_hand() {
+        let residuals = array![1.0, -1.0, 2.0, 0.0, -2.0];
+        // A constant and a regressor orthogonal to it, so that x'x = diag(5, 4)
+        let x = array![[1.0, 1.0], [1.0, -1.0], [1.0, 1.0], [1.0, -1.0], [1.0, 0.0]];
+
+        let se = newey_west(&residuals, &x, 2).unwrap();
+
+        // Constant: autocovariances 10, -3, -2 and Bartlett weights 2/3, 1/3 give
+        // S = 10 + 2 * (2/3 * -3 + 1/3 * -2) = 14/3, and a variance of S / 5^2
+        assert!((se[0] - (14.0 / 3.0 / 25.0f64).sqrt()).abs() < 1e-12);
+        // Regressor: scores 1, 1, 2, 0, 0 give S = 6 + 2 * (2/3 * 3 + 1/3 * 2) = 34/3
+        assert!((se[1] - (34.0 / 3.0 / 16.0f64).sqrt()).abs() < 1e-12);
+        assert!((newey_west_mean_se(&residuals, 2) - se[0]).abs() < 1e-12);
+        assert!(newey_west(&residuals, &x.slice(ndarray::s![..4, ..]).to_owned(), 2).is_err());
+    }
+
+    #[test]
+    fn test_align_by_date_matches_months_not_positions() {
+        let returns = array![[0.01, 0.02], [0.03, 0.04], [0.05, 0.06]];
+        let return_dates = array![[200001], [200002], [200003]];
+        // Factors start one month later and run one month past the returns
+        let factors = array![[0.2], [0.3], [0.4]];
+        let factor_dates = array![[200002], [200003], [200004]];
+
+        let (r, f, dates) =
+            align_by_date(&returns, &return_dates, &factors, &factor_dates).unwrap();
+
+        assert_eq!(dates, array![[200002], [200003]]);
+        assert_eq!(r, array![[0.03, 0.04], [0.05, 0.06]]);
+        assert_eq!(f, array![[0.2], [0.3]]);
+
+        // Position-wise the samples differ in length, which the GRS test rejects
+        assert!(grs_test(&returns, &factors.slice(ndarray::s![..2, ..]).to_owned()).is_err());
+        assert!(align_by_date(&returns, &return_dates, &factors, &array![[200002]]).is_err());
+        assert!(align_by_date(
+            &returns,
+            &return_dates,
+            &factors,
+            &array![[199901], [199902], [199903]]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_ols_recovers_exact_coefficients() {
+        let x = add_constant(&array![[1.0], [2.0], [3.0], [4.0]]);
+        let y = array![3.0, 5.0, 7.0, 9.0];
+
+        let fit = ols(&y, &x).unwrap();
+
+        assert!((fit.coefficients[0] - 1.0).abs() < 1e-10);
+        assert!((fit.coefficients[1] - 2.0).abs() < 1e-10);
+        assert!((fit.r_squared - 1.0).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_ols_standard_errors() {
+        let x = add_constant(&array![[0.0], [1.0], [2.0], [3.0]]);
+        let y = array![0.0, 2.0, 1.0, 3.0];
+
+        let fit = ols(&y, &x).unwrap();
+
+        // Slope 0.8, residual variance 1.8 / 2, sum of squared deviations of x is 5
+        assert!((fit.coefficients[1] - 0.8).abs() < 1e-12);
+        assert!((fit.std_errors[1] - (0.9f64 / 5.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_invert_singular_matrix_errors() {
+        assert!(invert(&array![[1.0, 2.0], [2.0, 4.0]]).is_err());
+        let inv = invert(&array![[4.0, 7.0], [2.0, 6.0]]).unwrap();
+        assert!((inv[[0, 0]] - 0.6).abs() < 1e-12);
+        assert!((inv[[1, 0]] + 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_fama_macbeth_clustered_and_newey_west_agree_on_iid_data() {
+        let (n_months, n_stocks) = (240, 50);
+        let characteristic =
+            Array2::from_shape_vec((n_months, n_stocks), lcg_noise(n_months * n_stocks, 1))
+                .unwrap();
+        let noise = Array2::from_shape_vec((n_months, n_stocks), lcg_noise(n_months * n_stocks, 2))
+            .unwrap();
+        let ret = 0.01 + 0.5 * &characteristic + 0.2 * &noise;
+
+        let x = [characteristic];
+
+        let nw = fama_macbeth(&ret, &x, StdErrorMethod::default(), FAMA_MACBETH_MIN_OBS).unwrap();
+        let clustered = fama_macbeth(
+            &ret,
+            &x,
+            StdErrorMethod::ClusteredByTime,
+            FAMA_MACBETH_MIN_OBS,
+        )
+        .unwrap();
+
+        assert_eq!(nw.n_months, n_months);
+        assert!((nw.coefficients[1] - 0.5).abs() < 0.02);
+        assert_eq!(nw.coefficients, clustered.coefficients);
+        // Slopes are serially independent, so both methods estimate the same quantity
+        for k in 0..2 {
+            let ratio = nw.std_errors[k] / clustered.std_errors[k];
+            assert!(
+                ratio > 0.75 && ratio < 1.25,
+                "ratio {} for coefficient {}",
+                ratio,
+                k
+            );
+        }
+        assert!(clustered.t_stats[1] > 10.0);
+        assert_eq!(nw.characteristic(0), (nw.coefficients[1], nw.t_stats[1]));
+    }
+
+    #[test]
+    fn test_fama_macbeth_skips_thin_months() {
+        let (n_months, n_stocks) = (24, 40);
+        let characteristic =
+            Array2::from_shape_vec((n_months, n_stocks), lcg_noise(n_months * n_stocks, 5))
+                .unwrap();
+        let mut ret = 0.01 + 0.5 * &characteristic;
+        // Only 10 stocks have a return in the first six months
+        ret.slice_mut(ndarray::s![..6, 10..]).fill(f64::NAN);
+
+        let fit = fama_macbeth(&ret, &[characteristic], StdErrorMethod::default(), 20).unwrap();
+
+        assert_eq!(fit.n_months, n_months - 6);
+        assert!(fit.slopes.row(0).iter().all(|b| b.is_nan()));
+        assert!((fit.characteristic(0).0 - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_grs_single_portfolio_equals_squared_alpha_t_stat() {
+        let n = 60;
+        let factor: Array1<f64> = lcg_noise(n, 3).iter().map(|e| 0.01 + 0.1 * e).collect();
+        let noise = lcg_noise(n, 4);
+        let ret: Array1<f64> = (0..n)
+            .map(|t| 0.004 + 1.2 * factor[t] + 0.05 * noise[t])
+            .collect();
+        let factors = factor.clone().insert_axis(Axis(1));
+
+        let grs = grs_test(&ret.clone().insert_axis(Axis(1)), &factors).unwrap();
+
+        // With one portfolio, GRS reduces to the squared t-statistic of the intercept
+        let fit = ols(&ret, &add_constant(&factors)).unwrap();
+        let t_stat = fit.coefficients[0] / fit.std_errors[0];
+        assert_eq!(grs.n_months, n);
+        assert!((grs.alphas[0] - fit.coefficients[0]).abs() < 1e-12);
+        assert!((grs.statistic - t_stat * t_stat).abs() < 1e-9);
+        assert!(grs.p_value > 0.0 && grs.p_value < 1.0);
+    }
+
+    #[test]
+    fn test_grs_errors_on_singular_residual_covariance() {
+        let factor = Array2::from_shape_vec((6, 1), lcg_noise(6, 5)).unwrap();
+        let noise = Array2::from_shape_vec((6, 1), lcg_noise(6, 6)).unwrap();
+        let ret = &factor + &noise;
+        let duplicated = ndarray::concatenate![Axis(1), ret, ret];
+
+        assert!(grs_test(&duplicated, &factor).is_err());
+    }
+
+    #[test]
+    fn test_explained_variance_of_factor_combination_is_one() {
+        let n = 48;
+        let factors = Array2::from_shape_vec((n, 2), lcg_noise(2 * n, 8)).unwrap();
+        let mut strategy = 0.002 + 0.5 * &factors.column(0) - 1.5 * &factors.column(1);
+        strategy[3] = f64::NAN;
+
+        let r2 = explained_variance_by_factors(&strategy, &factors).unwrap();
+
+        assert!((r2 - 1.0).abs() < 1e-10);
+        let noise = Array1::from(lcg_noise(n, 9));
+        let partial = explained_variance_by_factors(&(&strategy + &noise), &factors).unwrap();
+        assert!(partial > 0.0 && partial < 1.0);
+        assert!(explained_variance_by_factors(
+            &strategy,
+            &factors.slice(ndarray::s![1.., ..]).to_owned()
+        )
+        .is_err());
+    }
+}
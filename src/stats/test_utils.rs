@@ -0,0 +1,12 @@
+/// Deterministic uniform(-0.5, 0.5) draws from a linear congruential generator.
+pub(crate) fn lcg_noise(n: usize, seed: u64) -> Vec<f64> {
+    let mut state = seed;
+    (0..n)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+        })
+        .collect()
+}
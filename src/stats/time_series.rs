@@ -0,0 +1,662 @@
+use super::regression::{add_constant, default_newey_west_lags, newey_west_mean_se, ols};
+use crate::utilities::risk_free::RiskFree;
+use anyhow::{anyhow, Result};
+use ndarray::{Array1, Array2, ArrayView1};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// Number of months per year, used to annualize monthly statistics.
+pub const MONTHS_PER_YEAR: f64 = 12.0;
+
+/// Threshold below which a dispersion is treated as exactly zero (floating-point noise).
+const ZERO_TOLERANCE: f64 = 1e-12;
+
+/// Mean of the finite entries of a series, NaN if there are none.
+pub fn nan_mean(x: &Array1<f64>) -> f64 {
+    let (sum, n) = x
+        .iter()
+        .filter(|v| v.is_finite())
+        .fold((0.0, 0usize), |(sum, n), v| (sum + v, n + 1));
+    if n == 0 {
+        f64::NAN
+    } else {
+        sum / n as f64
+    }
+}
+
+/// Sample standard deviation of the finite entries of a series, NaN if there are fewer
+/// than two.
+pub fn nan_std(x: &Array1<f64>) -> f64 {
+    let mean = nan_mean(x);
+    let (ss, n) = x
+        .iter()
+        .filter(|v| v.is_finite())
+        .fold((0.0, 0usize), |(ss, n), v| (ss + (v - mean).powi(2), n + 1));
+    if n < 2 {
+        f64::NAN
+    } else {
+        (ss / (n - 1) as f64).sqrt()
+    }
+}
+
+/// Subtracts the risk-free rate from a monthly return series.
+///
+/// # Arguments
+///
+/// * `ret` - Monthly returns (nMonths).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1).
+/// * `rf` - Source of the risk-free rate, aligned to `dates`.
+pub fn to_excess(ret: &Array1<f64>, dates: &Array2<i32>, rf: &RiskFree) -> Result<Array1<f64>> {
+    if ret.len() != dates.len() {
+        return Err(anyhow!(
+            "Return series has {} months but there are {} dates",
+            ret.len(),
+            dates.len()
+        ));
+    }
+    Ok(ret - &rf.series(dates)?)
+}
+
+/// Annualized Sharpe ratio of a monthly return series, `sqrt(12) * mean / sd` of the
+/// excess returns. Pass `rf = None` for zero-investment portfolios such as long-short
+/// spreads, which are already excess returns.
+pub fn sharpe_ratio(ret: &Array1<f64>, dates: &Array2<i32>, rf: Option<&RiskFree>) -> Result<f64> {
+    let excess = match rf {
+        Some(rf) => to_excess(ret, dates, rf)?,
+        None => ret.clone(),
+    };
+    Ok(MONTHS_PER_YEAR.sqrt() * nan_mean(&excess) / nan_std(&excess))
+}
+
+/// Annualized information ratio of a strategy against a benchmark: the annualized mean
+/// active return divided by the annualized tracking error (sd of active returns).
+///
+/// Months where either series is missing are dropped pairwise. When the tracking error
+/// is zero (the strategy is the benchmark plus a constant), the ratio is reported as
+/// `+/-inf` following the sign of the mean active return, or NaN if that is zero too.
+pub fn information_ratio(strategy: &Array1<f64>, benchmark: &Array1<f64>) -> f64 {
+    assert_eq!(
+        strategy.len(),
+        benchmark.len(),
+        "Strategy and benchmark must have the same length"
+    );
+    let active: Array1<f64> = strategy - benchmark;
+    let mean = nan_mean(&active);
+    let tracking_error = nan_std(&active);
+    if !mean.is_finite() || tracking_error.is_nan() {
+        return f64::NAN;
+    }
+    if tracking_error <= ZERO_TOLERANCE * mean.abs().max(1.0) {
+        return if mean.abs() <= ZERO_TOLERANCE {
+            f64::NAN
+        } else {
+            mean.signum() * f64::INFINITY
+        };
+    }
+    MONTHS_PER_YEAR.sqrt() * mean / tracking_error
+}
+
+/// Rolling Sharpe ratio over a trailing window of `window` months.
+///
+/// Entry t uses the months `t - window + 1..=t`. NaN months are skipped, and a window
+/// needs at least half of its months (and at least two) to be valid, otherwise the
+/// entry is NaN, as are the first `window - 1` entries. With `annualize` the monthly
+/// ratio is scaled by `sqrt(12)`.
+pub fn rolling_sharpe(ret: &Array1<f64>, window: usize, annualize: bool) -> Array1<f64> {
+    let min_obs = (window / 2).max(2);
+    let scale = if annualize {
+        MONTHS_PER_YEAR.sqrt()
+    } else {
+        1.0
+    };
+    let mut sharpe = Array1::from_elem(ret.len(), f64::NAN);
+    if window == 0 {
+        return sharpe;
+    }
+    for t in (window - 1)..ret.len() {
+        let slice = ret.slice(ndarray::s![t + 1 - window..=t]).to_owned();
+        if slice.iter().filter(|r| r.is_finite()).count() >= min_obs {
+            sharpe[t] = scale * nan_mean(&slice) / nan_std(&slice);
+        }
+    }
+    sharpe
+}
+
+/// Rolling market beta over a trailing window of `window` months.
+///
+/// Entry t is `cov(ret, mkt) / var(mkt)` over the months `t - window + 1..=t` where both
+/// series are available. As in [`rolling_sharpe`], a window needs at least half of its
+/// months (and at least two) to be valid, otherwise the entry is NaN, as are the first
+/// `window - 1` entries.
+pub fn rolling_beta(ret: &Array1<f64>, mkt: &Array1<f64>, window: usize) -> Array1<f64> {
+    assert_eq!(
+        ret.len(),
+        mkt.len(),
+        "Returns and market returns must have the same length"
+    );
+    let min_obs = (window / 2).max(2);
+    let mut beta = Array1::from_elem(ret.len(), f64::NAN);
+    if window == 0 {
+        return beta;
+    }
+    for t in (window - 1)..ret.len() {
+        let pairs: Vec<(f64, f64)> = (t + 1 - window..=t)
+            .map(|s| (ret[s], mkt[s]))
+            .filter(|(r, m)| r.is_finite() && m.is_finite())
+            .collect();
+        if pairs.len() < min_obs {
+            continue;
+        }
+        let n = pairs.len() as f64;
+        let mean_r = pairs.iter().map(|(r, _)| r).sum::<f64>() / n;
+        let mean_m = pairs.iter().map(|(_, m)| m).sum::<f64>() / n;
+        let (cov, var) = pairs.iter().fold((0.0, 0.0), |(cov, var), (r, m)| {
+            (
+                cov + (r - mean_r) * (m - mean_m),
+                var + (m - mean_m).powi(2),
+            )
+        });
+        if var > ZERO_TOLERANCE {
+            beta[t] = cov / var;
+        }
+    }
+    beta
+}
+
+/// Rolling loadings of a strategy on a set of factors, to see whether its exposures drift
+/// over time (e.g. the time-varying market beta of momentum).
+///
+/// Row t holds the slopes of an OLS regression of the strategy on a constant and the
+/// factors over the months `t - window + 1..=t` where all series are available. As in
+/// [`rolling_beta`], a window needs at least half of its months to be valid, and more
+/// months than regressors, otherwise the row is NaN, as are the first `window - 1` rows.
+///
+/// # Arguments
+///
+/// * `strategy` - Strategy returns (nMonths).
+/// * `factors` - Factor returns (nMonths x nFactors).
+/// * `window` - Number of months of each regression.
+///
+/// # Returns
+///
+/// * `Array2<f64>` - The factor loadings (nMonths x nFactors).
+pub fn rolling_factor_loadings(
+    strategy: &Array1<f64>,
+    factors: &Array2<f64>,
+    window: usize,
+) -> Array2<f64> {
+    assert_eq!(
+        strategy.len(),
+        factors.nrows(),
+        "Strategy and factors must cover the same months"
+    );
+    let min_obs = (window / 2).max(factors.ncols() + 2);
+    let mut loadings = Array2::from_elem(factors.dim(), f64::NAN);
+    if window == 0 {
+        return loadings;
+    }
+    for t in (window - 1)..strategy.len() {
+        let months: Vec<usize> = (t + 1 - window..=t)
+            .filter(|&s| strategy[s].is_finite() && factors.row(s).iter().all(|f| f.is_finite()))
+            .collect();
+        if months.len() < min_obs {
+            continue;
+        }
+        let y = strategy.select(ndarray::Axis(0), &months);
+        let x = add_constant(&factors.select(ndarray::Axis(0), &months));
+        if let Ok(fit) = ols(&y, &x) {
+            loadings
+                .row_mut(t)
+                .assign(&fit.coefficients.slice(ndarray::s![1..]));
+        }
+    }
+    loadings
+}
+
+/// Drawdown of a return series from the running peak of its cumulative wealth index.
+///
+/// Wealth starts at one and compounds the monthly returns, with NaN months treated as
+/// flat (zero return). Entry t is `W_t / max(1, W_0..=W_t) - 1`, which is zero at a new
+/// peak and negative below it.
+///
+/// # Returns
+///
+/// * `(Array1<f64>, f64)` - The drawdown series and the maximum drawdown, reported as its
+///   most negative value (0 if wealth never falls below a prior peak).
+pub fn drawdown_series(ret: &Array1<f64>) -> (Array1<f64>, f64) {
+    let mut wealth = 1.0;
+    let mut peak = 1.0f64;
+    let drawdowns: Array1<f64> = ret
+        .iter()
+        .map(|r| {
+            if r.is_finite() {
+                wealth *= 1.0 + r;
+            }
+            peak = peak.max(wealth);
+            wealth / peak - 1.0
+        })
+        .collect();
+    let max_drawdown = drawdowns.iter().fold(0.0, |m: f64, &d| m.min(d));
+    (drawdowns, max_drawdown)
+}
+
+/// Descriptors of the tails of a monthly return series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailStats {
+    /// Fraction of the valid months with a strictly positive return.
+    pub fraction_positive: f64,
+    /// Best single-month return.
+    pub best_month: f64,
+    /// Worst single-month return.
+    pub worst_month: f64,
+    /// Longest number of consecutive months spent below a prior peak, as measured by
+    /// [`drawdown_series`].
+    pub longest_drawdown_months: usize,
+}
+
+/// Fraction of positive months, best and worst month and longest drawdown duration of
+/// a return series. NaN months are ignored, except in the drawdown duration where they
+/// count as flat months. Without any valid month, the return statistics are NaN.
+pub fn tail_stats(ret: &Array1<f64>) -> TailStats {
+    let valid: Vec<f64> = ret.iter().copied().filter(|r| r.is_finite()).collect();
+    let (fraction_positive, best_month, worst_month) = if valid.is_empty() {
+        (f64::NAN, f64::NAN, f64::NAN)
+    } else {
+        (
+            valid.iter().filter(|&&r| r > 0.0).count() as f64 / valid.len() as f64,
+            valid.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            valid.iter().copied().fold(f64::INFINITY, f64::min),
+        )
+    };
+    let (drawdowns, _) = drawdown_series(ret);
+    let (longest_drawdown_months, _) = drawdowns.iter().fold((0, 0), |(longest, run), &d| {
+        let run = if d < 0.0 { run + 1 } else { 0 };
+        (longest.max(run), run)
+    });
+    TailStats {
+        fraction_positive,
+        best_month,
+        worst_month,
+        longest_drawdown_months,
+    }
+}
+
+/// Sample autocorrelations of a series at lags `1..=max_lag`.
+///
+/// The mean and variance are taken over the finite entries, and the lag-k
+/// autocovariance sums over the pairs where both months are finite, divided by the
+/// total sum of squares as in the usual sample ACF. Lags without any valid pair, or a
+/// series without dispersion, give NaN.
+pub fn autocorrelation(ret: &Array1<f64>, max_lag: usize) -> Array1<f64> {
+    let mean = nan_mean(ret);
+    let demeaned = ret.mapv(|r| r - mean);
+    let ss: f64 = demeaned
+        .iter()
+        .filter(|d| d.is_finite())
+        .map(|d| d * d)
+        .sum();
+    (1..=max_lag)
+        .map(|k| {
+            let pairs: Vec<f64> = (k..ret.len())
+                .map(|t| demeaned[t] * demeaned[t - k])
+                .filter(|p| p.is_finite())
+                .collect();
+            if pairs.is_empty() || ss <= ZERO_TOLERANCE {
+                f64::NAN
+            } else {
+                pairs.iter().sum::<f64>() / ss
+            }
+        })
+        .collect()
+}
+
+/// Covariance matrix of stock returns from pairwise-complete observations.
+///
+/// Entry (i, j) is the sample covariance of stocks i and j over the months where both
+/// have a return, and NaN when there are fewer than `min_overlap` (and at least two)
+/// such months. The diagonal holds the variances. Pairs are computed in parallel.
+///
+/// # Arguments
+///
+/// * `ret` - Returns (nMonths x nStocks).
+/// * `min_overlap` - Minimum number of common months of a pair.
+///
+/// # Returns
+///
+/// * `Array2<f64>` - The symmetric covariance matrix (nStocks x nStocks).
+pub fn return_covariance(ret: &Array2<f64>, min_overlap: usize) -> Array2<f64> {
+    let n_stocks = ret.ncols();
+    let pairs: Vec<(usize, usize)> = (0..n_stocks)
+        .flat_map(|i| (i..n_stocks).map(move |j| (i, j)))
+        .collect();
+    let covariances: Vec<f64> = pairs
+        .par_iter()
+        .map(|&(i, j)| pairwise_covariance(ret.column(i), ret.column(j), min_overlap.max(2)))
+        .collect();
+    let mut cov = Array2::from_elem((n_stocks, n_stocks), f64::NAN);
+    for (&(i, j), c) in pairs.iter().zip(covariances) {
+        cov[[i, j]] = c;
+        cov[[j, i]] = c;
+    }
+    cov
+}
+
+/// Sample covariance over the months where both series are finite, NaN with fewer than
+/// `min_overlap` such months.
+fn pairwise_covariance(x: ArrayView1<f64>, y: ArrayView1<f64>, min_overlap: usize) -> f64 {
+    let common: Vec<(f64, f64)> = x
+        .iter()
+        .zip(y.iter())
+        .filter(|(a, b)| a.is_finite() && b.is_finite())
+        .map(|(&a, &b)| (a, b))
+        .collect();
+    let n = common.len();
+    if n < min_overlap {
+        return f64::NAN;
+    }
+    let mean_x = common.iter().map(|(a, _)| a).sum::<f64>() / n as f64;
+    let mean_y = common.iter().map(|(_, b)| b).sum::<f64>() / n as f64;
+    common
+        .iter()
+        .map(|(a, b)| (a - mean_x) * (b - mean_y))
+        .sum::<f64>()
+        / (n - 1) as f64
+}
+
+/// Summary statistics of a monthly return series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReturnStats {
+    /// Number of months with a return.
+    pub n_months: usize,
+    /// Mean monthly return.
+    pub mean: f64,
+    /// Standard deviation of the monthly returns.
+    pub std: f64,
+    /// Annualized Sharpe ratio, `sqrt(12) * mean / std`, without subtracting a risk-free
+    /// rate.
+    pub sharpe: f64,
+    /// t-statistic of the mean with Newey-West standard errors.
+    pub t_stat: f64,
+}
+
+impl ReturnStats {
+    /// Computes the statistics over the finite months of `ret`, with `lags` Newey-West
+    /// lags for the t-statistic ([`default_newey_west_lags`] when `None`). Fewer than two
+    /// months give NaN statistics.
+    pub fn from_series(ret: &Array1<f64>, lags: Option<usize>) -> Self {
+        let valid: Array1<f64> = ret.iter().copied().filter(|r| r.is_finite()).collect();
+        let n_months = valid.len();
+        if n_months < 2 {
+            return ReturnStats {
+                n_months,
+                mean: nan_mean(&valid),
+                std: f64::NAN,
+                sharpe: f64::NAN,
+                t_stat: f64::NAN,
+            };
+        }
+        let (mean, std) = (nan_mean(&valid), nan_std(&valid));
+        let lags = lags.unwrap_or_else(|| default_newey_west_lags(n_months));
+        ReturnStats {
+            n_months,
+            mean,
+            std,
+            sharpe: MONTHS_PER_YEAR.sqrt() * mean / std,
+            t_stat: mean / newey_west_mean_se(&valid, lags),
+        }
+    }
+}
+
+/// Mean, standard deviation, annualized Sharpe ratio and Newey-West t-statistic of a
+/// return series such as a long-short spread, with the [`default_newey_west_lags`]. Use
+/// [`ReturnStats::from_series`] to set the number of lags.
+pub fn summarize_returns(series: &Array1<f64>) -> ReturnStats {
+    ReturnStats::from_series(series, None)
+}
+
+/// Splits a return series by a discrete state each month (e.g. an NBER recession flag
+/// or high/low volatility regimes) and computes the [`ReturnStats`] within each state.
+///
+/// Months with a missing return are dropped. The months of a state are generally not
+/// contiguous, so the Newey-West lags apply to the sequence of that state's months.
+///
+/// # Arguments
+///
+/// * `ret` - Monthly returns (nMonths).
+/// * `state` - State of each month (nMonths).
+pub fn conditional_returns(ret: &Array1<f64>, state: &Array1<i32>) -> HashMap<i32, ReturnStats> {
+    assert_eq!(
+        ret.len(),
+        state.len(),
+        "Returns and states must have the same length"
+    );
+    let mut by_state: HashMap<i32, Vec<f64>> = HashMap::new();
+    for (&r, &s) in ret.iter().zip(state.iter()) {
+        if r.is_finite() {
+            by_state.entry(s).or_default().push(r);
+        }
+    }
+    by_state
+        .into_iter()
+        .map(|(s, returns)| (s, ReturnStats::from_series(&Array1::from(returns), None)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::test_utils::lcg_noise;
+    use ndarray::array;
+
+    #[test]
+    fn test_return_covariance_of_perfectly_correlated_stocks() {
+        let nan = f64::NAN;
+        let ret = array![
+            [0.01, 0.02, nan],
+            [0.03, 0.06, 0.01],
+            [-0.02, -0.04, nan],
+            [nan, 0.10, nan],
+            [0.00, 0.00, 0.02],
+        ];
+
+        let cov = return_covariance(&ret, 3);
+
+        // Stock 1 is twice stock 0 over their four common months
+        let var0 = nan_std(&array![0.01, 0.03, -0.02, 0.00]).powi(2);
+        assert!((cov[[0, 0]] - var0).abs() < 1e-12);
+        assert!((cov[[0, 1]] - 2.0 * var0).abs() < 1e-12);
+        assert_eq!(cov[[0, 1]], cov[[1, 0]]);
+        // Stock 2 has only two months
+        assert!(cov[[2, 2]].is_nan());
+        assert!(cov[[0, 2]].is_nan());
+        assert!(return_covariance(&ret, 2)[[0, 2]].is_finite());
+    }
+
+    #[test]
+    fn test_rolling_factor_loadings_track_a_changing_beta() {
+        let n = 120;
+        let factors = Array2::from_shape_fn((n, 2), |(t, k)| {
+            0.03 * ((1.3 + k as f64) * t as f64).sin() + 0.01 * (0.4 * t as f64 + k as f64).cos()
+        });
+        // Market loading of 0.5 in the first half and 1.5 in the second, constant 0.3 on
+        // the second factor
+        let strategy = Array1::from_shape_fn(n, |t| {
+            let beta = if t < n / 2 { 0.5 } else { 1.5 };
+            0.002 + beta * factors[[t, 0]] + 0.3 * factors[[t, 1]]
+        });
+
+        let loadings = rolling_factor_loadings(&strategy, &factors, 24);
+
+        assert!(loadings.row(22).iter().all(|b| b.is_nan()));
+        assert!((loadings[[30, 0]] - 0.5).abs() < 1e-9);
+        assert!((loadings[[n - 1, 0]] - 1.5).abs() < 1e-9);
+        // Windows straddling the change are in between
+        assert!(loadings[[70, 0]] > 0.5 && loadings[[70, 0]] < 1.5);
+        assert!((loadings[[n - 1, 1]] - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_summarize_returns_uses_default_lags() {
+        let ret = Array1::from_shape_fn(120, |t| 0.01 + 0.02 * (0.7 * t as f64).sin());
+
+        let stats = summarize_returns(&ret);
+
+        assert_eq!(stats, ReturnStats::from_series(&ret, Some(4)));
+        assert_ne!(stats, ReturnStats::from_series(&ret, Some(0)));
+        assert_eq!(stats.n_months, 120);
+        assert!((stats.sharpe - MONTHS_PER_YEAR.sqrt() * stats.mean / stats.std).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_conditional_returns_per_state_means() {
+        let ret = array![0.02, -0.03, 0.04, -0.01, f64::NAN, 0.00, -0.02, 0.06];
+        let state = array![0, 1, 0, 1, 1, 0, 1, 0];
+
+        let stats = conditional_returns(&ret, &state);
+
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[&0].n_months, 4);
+        assert!((stats[&0].mean - 0.03).abs() < 1e-12);
+        assert_eq!(stats[&1].n_months, 3);
+        assert!((stats[&1].mean + 0.02).abs() < 1e-12);
+        assert!(stats[&1].t_stat < 0.0);
+    }
+
+    #[test]
+    fn test_rolling_beta_recovers_linear_exposure() {
+        let mkt = Array1::from_shape_fn(30, |t| 0.05 * (1.3 * t as f64).sin());
+        let mut ret = &mkt * 1.5 + 0.002;
+        ret[12] = f64::NAN;
+
+        let beta = rolling_beta(&ret, &mkt, 12);
+
+        assert!(beta.iter().take(11).all(|b| b.is_nan()));
+        for t in 11..30 {
+            assert!((beta[t] - 1.5).abs() < 1e-10, "{}", t);
+        }
+    }
+
+    #[test]
+    fn test_to_excess_with_constant_user_rf() {
+        let dates = array![[200001], [200002], [200003]];
+        let ret = array![0.02, f64::NAN, -0.01];
+        let rf = RiskFree::constant(&dates, 0.005);
+
+        let excess = to_excess(&ret, &dates, &rf).unwrap();
+
+        assert!((excess[0] - 0.015).abs() < 1e-12);
+        assert!(excess[1].is_nan());
+        assert!((excess[2] + 0.015).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_sharpe_ratio_with_risk_free() {
+        let dates = array![[200001], [200002], [200003], [200004]];
+        let ret = array![0.02, 0.00, 0.02, 0.00];
+        let rf = RiskFree::constant(&dates, 0.01);
+
+        // Excess returns alternate between +1% and -1%, so the Sharpe ratio is zero
+        assert!(sharpe_ratio(&ret, &dates, Some(&rf)).unwrap().abs() < 1e-12);
+        let raw = sharpe_ratio(&ret, &dates, None).unwrap();
+        let expected = 12f64.sqrt() * 0.01 / (0.0004f64 / 3.0).sqrt();
+        assert!((raw - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_information_ratio() {
+        let benchmark = array![0.01, -0.02, 0.03, 0.00, f64::NAN];
+        let strategy = array![0.02, -0.02, 0.05, 0.00, 0.04];
+
+        // Active returns 0.01, 0.00, 0.02, 0.00 after dropping the NaN month
+        let active = array![0.01, 0.00, 0.02, 0.00];
+        let expected = 12f64.sqrt() * nan_mean(&active) / nan_std(&active);
+        assert!((information_ratio(&strategy, &benchmark) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_information_ratio_zero_tracking_error() {
+        let benchmark = array![0.013, -0.021, 0.034, 0.007];
+        let strategy = &benchmark + 0.001;
+
+        assert_eq!(information_ratio(&strategy, &benchmark), f64::INFINITY);
+        assert_eq!(information_ratio(&benchmark, &strategy), f64::NEG_INFINITY);
+        assert!(information_ratio(&benchmark, &benchmark).is_nan());
+    }
+
+    #[test]
+    fn test_rolling_sharpe_is_flat_for_stationary_series() {
+        // Alternating returns: every 12-month window has mean 1% and the same sd
+        let ret: Array1<f64> = (0..48)
+            .map(|t| if t % 2 == 0 { 0.02 } else { 0.0 })
+            .collect();
+
+        let sharpe = rolling_sharpe(&ret, 12, true);
+
+        let expected = 12f64.sqrt() * 0.01 / (12.0 * 0.0001 / 11.0f64).sqrt();
+        assert!(sharpe.iter().take(11).all(|s| s.is_nan()));
+        assert!(sharpe.iter().skip(11).all(|s| (s - expected).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_rolling_sharpe_requires_minimum_observations() {
+        let mut ret: Array1<f64> = (0..6).map(|t| 0.01 * t as f64).collect();
+        ret[1] = f64::NAN;
+        ret[2] = f64::NAN;
+
+        let sharpe = rolling_sharpe(&ret, 4, false);
+
+        // The window ending at month 3 holds two valid months (0 and 3)
+        assert!(sharpe[3].is_finite());
+        ret[3] = f64::NAN;
+        let sharpe = rolling_sharpe(&ret, 4, false);
+        assert!(sharpe[3].is_nan());
+        assert!(sharpe[4].is_nan());
+        assert!(sharpe[5].is_finite());
+    }
+
+    #[test]
+    fn test_autocorrelation_recovers_ar1_coefficient() {
+        let noise = lcg_noise(20_000, 7);
+        let mut ret = Array1::zeros(noise.len());
+        for t in 1..ret.len() {
+            ret[t] = 0.6 * ret[t - 1] + noise[t - 1];
+        }
+        ret[100] = f64::NAN;
+
+        let acf = autocorrelation(&ret, 3);
+
+        assert_eq!(acf.len(), 3);
+        assert!((acf[0] - 0.6).abs() < 0.03);
+        assert!((acf[1] - 0.36).abs() < 0.03);
+        assert!(autocorrelation(&array![0.01, 0.01, 0.01], 1)[0].is_nan());
+    }
+
+    #[test]
+    fn test_drawdown_series_known_peak_to_trough() {
+        // Wealth 1.1, 1.21, 0.968, 0.968, 0.726, 1.452
+        let ret = array![0.1, 0.1, -0.2, f64::NAN, -0.25, 1.0];
+
+        let (drawdowns, max_drawdown) = drawdown_series(&ret);
+
+        assert_eq!(drawdowns[1], 0.0);
+        assert!((drawdowns[2] + 0.2).abs() < 1e-12);
+        assert!((drawdowns[3] + 0.2).abs() < 1e-12);
+        assert!((drawdowns[4] + 0.4).abs() < 1e-12);
+        assert_eq!(drawdowns[5], 0.0);
+        assert!((max_drawdown + 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_tail_stats_known_best_and_worst() {
+        let ret = array![0.05, -0.02, f64::NAN, -0.03, 0.01, 0.08, -0.01, 0.00];
+
+        let stats = tail_stats(&ret);
+
+        assert!((stats.fraction_positive - 3.0 / 7.0).abs() < 1e-12);
+        assert_eq!(stats.best_month, 0.08);
+        assert_eq!(stats.worst_month, -0.03);
+        // Below the first peak from month 1 until the recovery in month 5
+        assert_eq!(stats.longest_drawdown_months, 4);
+        assert!(tail_stats(&array![f64::NAN]).best_month.is_nan());
+    }
+}
@@ -0,0 +1,256 @@
+use super::point_in_time::point_in_time_fundamentals;
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use ndarray::Array2;
+use polars::prelude::*;
+use std::collections::HashMap;
+
+/// Link types of the CRSP-Compustat merged database kept by [`link_comp_to_crsp`]: links
+/// researched and confirmed by CRSP (`LC`) or unresearched but valid (`LU`).
+pub const CCM_LINK_TYPES: [&str; 2] = ["LU", "LC"];
+
+/// Link flags kept by [`link_comp_to_crsp`]: the primary issue of the firm (`P`) or the
+/// primary issue assigned by CRSP when Compustat has none (`C`).
+pub const CCM_LINK_PRIMARY: [&str; 2] = ["P", "C"];
+
+/// Maps Compustat records to CRSP permnos through the CCM link history.
+///
+/// Each record of `funda` is joined on `gvkey` with the links of `lnkhist` (the
+/// `crsp_ccmxpf_lnkhist` table) whose `linktype` is in [`CCM_LINK_TYPES`], whose
+/// `linkprim` is in [`CCM_LINK_PRIMARY`] and whose validity window contains the
+/// record's `datadate`: `linkdt <= datadate <= linkenddt`, with a null `linkenddt`
+/// standing for a link still active. Records without a valid link are dropped.
+///
+/// The output holds the columns of `funda` along with the `permno` (`Int32`, as in the
+/// CRSP matrices) of the link, so that characteristic matrices can be aligned with the
+/// CRSP universe.
+///
+/// # Arguments
+///
+/// * `funda` - Compustat fundamentals with `gvkey` and `datadate` columns.
+/// * `lnkhist` - CCM link history with `gvkey`, `lpermno`, `linktype`, `linkprim`,
+///   `linkdt` and `linkenddt` columns. `gvkey` must have the same type as in `funda`.
+pub fn link_comp_to_crsp(funda: LazyFrame, lnkhist: LazyFrame) -> Result<DataFrame> {
+    let link_types = Series::new("linktype".into(), CCM_LINK_TYPES.as_slice());
+    let link_primary = Series::new("linkprim".into(), CCM_LINK_PRIMARY.as_slice());
+    let links = lnkhist
+        .filter(
+            col("linktype")
+                .is_in(lit(link_types))
+                .and(col("linkprim").is_in(lit(link_primary))),
+        )
+        .select([
+            col("gvkey"),
+            col("lpermno").cast(DataType::Int32).alias("permno"),
+            col("linkdt").cast(DataType::Date),
+            col("linkenddt").cast(DataType::Date),
+        ]);
+
+    funda
+        .join(
+            links,
+            [col("gvkey")],
+            [col("gvkey")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .filter(
+            col("datadate")
+                .cast(DataType::Date)
+                .gt_eq(col("linkdt"))
+                .and(
+                    col("linkenddt")
+                        .is_null()
+                        .or(col("datadate").cast(DataType::Date).lt_eq(col("linkenddt"))),
+                ),
+        )
+        .drop(["linkdt", "linkenddt"])
+        .collect()
+        .context("Failed to link the Compustat records to CRSP permnos.")
+}
+
+/// Usual gap, in months, between a fiscal year end and the public release of its
+/// annual report.
+pub const DEFAULT_REPORTING_LAG: usize = 6;
+
+/// Turns an annual accounting item into a monthly characteristic.
+///
+/// Each record is placed on the row of its `datadate` month and the result goes through
+/// [`point_in_time_fundamentals`]: the value of a fiscal year is used from the month
+/// `lag_months` after its `datadate` (e.g. [`DEFAULT_REPORTING_LAG`]), when it is assumed
+/// public, and carried forward until the next fiscal year becomes available, with the
+/// data frozen at `as_of` if given. Null values are skipped, and when two records fall in
+/// the same month the one with the later `datadate` is kept. Permnos outside `permno` and
+/// records whose `datadate` month is not in `dates` are ignored, so `dates` should start
+/// at the first fiscal year end of interest rather than at the first formation month.
+///
+/// # Arguments
+///
+/// * `funda_linked` - Compustat records with a `permno`, as returned by
+///   [`link_comp_to_crsp`], with `datadate` and `item` columns.
+/// * `item` - Accounting item to align (e.g. `"ceq"`).
+/// * `permno` - Permno vector (nPermno x 1).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1), sorted.
+/// * `lag_months` - Months between the fiscal year end and the first month of use.
+/// * `as_of` - Optional date at which the available information is frozen.
+///
+/// # Returns
+///
+/// * `Result<Array2<f64>>` - The item (nMonths x nPermno), NaN before the first report.
+pub fn align_annual_to_monthly(
+    funda_linked: &DataFrame,
+    item: &str,
+    permno: &Array2<i32>,
+    dates: &Array2<i32>,
+    lag_months: usize,
+    as_of: Option<NaiveDate>,
+) -> Result<Array2<f64>> {
+    if dates.iter().zip(dates.iter().skip(1)).any(|(a, b)| a >= b) {
+        return Err(anyhow!("The dates vector must be sorted"));
+    }
+    let reporting_lag = u32::try_from(lag_months)
+        .with_context(|| format!("Reporting lag of {} months is too large", lag_months))?;
+    // Sorted by datadate so that a later record of the same month overwrites an earlier one
+    let records = funda_linked
+        .clone()
+        .lazy()
+        .select([
+            col("permno").cast(DataType::Int32),
+            col("datadate").cast(DataType::Date),
+            col(item).cast(DataType::Float64),
+        ])
+        .sort(["datadate"], Default::default())
+        .with_column(
+            col("datadate")
+                .dt()
+                .to_string("%Y%m")
+                .cast(DataType::Int32)
+                .alias("month"),
+        )
+        .collect()
+        .with_context(|| format!("Failed to read {} from the linked records.", item))?;
+
+    let column_of: HashMap<i32, usize> = permno.iter().enumerate().map(|(j, &p)| (p, j)).collect();
+    let row_of: HashMap<i32, usize> = dates.iter().enumerate().map(|(t, &d)| (d, t)).collect();
+    let mut values = Array2::from_elem((dates.len(), permno.len()), f64::NAN);
+    for ((p, month), value) in records
+        .column("permno")?
+        .i32()?
+        .into_iter()
+        .zip(records.column("month")?.i32()?)
+        .zip(records.column(item)?.f64()?)
+    {
+        let (Some(p), Some(month), Some(value)) = (p, month, value) else {
+            continue;
+        };
+        if let (Some(&j), Some(&t)) = (column_of.get(&p), row_of.get(&month)) {
+            values[[t, j]] = value;
+        }
+    }
+    Ok(point_in_time_fundamentals(
+        &values,
+        dates,
+        reporting_lag,
+        as_of,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::matrix_ops::add_months;
+
+    #[test]
+    fn test_link_comp_to_crsp() {
+        let date = |y: i32, m: u32, d: u32| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let funda = df![
+            "gvkey" => ["001000", "001000", "001001", "001002", "001003"],
+            "datadate" => [
+                date(1999, 12, 31), date(2001, 12, 31),
+                date(2005, 6, 30), date(2005, 12, 31), date(2005, 12, 31),
+            ],
+            "at" => [10.0, 12.0, 50.0, 7.0, 3.0],
+        ]
+        .unwrap();
+        let lnkhist = df![
+            "gvkey" => ["001000", "001000", "001001", "001002", "001003"],
+            "lpermno" => [25881.0, 25882.0, 10015.0, 10016.0, 10017.0],
+            "linktype" => ["LU", "LC", "LC", "NR", "LU"],
+            "linkprim" => ["P", "P", "C", "P", "J"],
+            "linkdt" => [
+                date(1990, 1, 1), date(2001, 1, 1),
+                date(2000, 1, 1), date(2000, 1, 1), date(2000, 1, 1),
+            ],
+            "linkenddt" => [
+                Some(date(2000, 12, 31)), None,
+                None, None, None,
+            ],
+        ]
+        .unwrap();
+
+        let linked = link_comp_to_crsp(funda.lazy(), lnkhist.lazy())
+            .unwrap()
+            .sort(["gvkey", "datadate"], Default::default())
+            .unwrap();
+
+        // 001002 has a non-research link type and 001003 a secondary issue
+        assert_eq!(
+            linked
+                .column("gvkey")
+                .unwrap()
+                .str()
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![Some("001000"), Some("001000"), Some("001001")]
+        );
+        assert_eq!(
+            linked.column("permno").unwrap().i32().unwrap().to_vec(),
+            vec![Some(25881), Some(25882), Some(10015)]
+        );
+        assert_eq!(
+            linked.column("at").unwrap().f64().unwrap().to_vec(),
+            vec![Some(10.0), Some(12.0), Some(50.0)]
+        );
+    }
+
+    #[test]
+    fn test_align_annual_to_monthly_lags_reports() {
+        let date = |y: i32, m: u32, d: u32| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let funda_linked = df![
+            "permno" => [10001, 10001, 10002, 10003],
+            "datadate" => [
+                date(2000, 12, 31), date(2001, 12, 31), date(2001, 3, 31), date(2001, 6, 30),
+            ],
+            "ceq" => [Some(100.0), Some(120.0), None, Some(5.0)],
+        ]
+        .unwrap();
+        let permno = ndarray::array![[10001], [10002]];
+        let dates = Array2::from_shape_fn((25, 1), |(t, _)| add_months(200012, t as i32));
+        let align = |as_of| {
+            align_annual_to_monthly(
+                &funda_linked,
+                "ceq",
+                &permno,
+                &dates,
+                DEFAULT_REPORTING_LAG,
+                as_of,
+            )
+            .unwrap()
+        };
+
+        let ceq = align(None);
+
+        assert_eq!(ceq.dim(), (25, 2));
+        // FY2000 is used from June 2001 (row 6) to May 2002 (row 17)
+        assert!(ceq[[5, 0]].is_nan());
+        assert_eq!(ceq[[6, 0]], 100.0);
+        assert_eq!(ceq[[17, 0]], 100.0);
+        assert_eq!(ceq[[18, 0]], 120.0);
+        assert_eq!(ceq[[24, 0]], 120.0);
+        assert!(ceq.column(1).iter().all(|x| x.is_nan()));
+
+        // Frozen in March 2002, FY2001 is never reported
+        let frozen = align(NaiveDate::from_ymd_opt(2002, 3, 15));
+        assert_eq!(frozen[[24, 0]], 100.0);
+    }
+}
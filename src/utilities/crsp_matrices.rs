@@ -0,0 +1,39 @@
+use anyhow::{anyhow, Result};
+use ndarray::Array2;
+
+/// The CRSP monthly matrices needed to form and evaluate portfolios, aligned on the same
+/// dates (rows) and permnos (columns).
+#[derive(Debug, Clone)]
+pub struct CrspMatrices {
+    /// Dates vector in `YYYYMM` format (nMonths x 1).
+    pub dates: Array2<i32>,
+    /// Permno vector (nStocks x 1).
+    pub permno: Array2<i32>,
+    /// Returns (nMonths x nStocks).
+    pub ret: Array2<f64>,
+    /// Market equity (nMonths x nStocks), used for value weighting when provided.
+    pub me: Option<Array2<f64>>,
+    /// Exchange codes (nMonths x nStocks), required for NYSE breakpoints.
+    pub exchcd: Option<Array2<i16>>,
+}
+
+impl CrspMatrices {
+    /// Checks that every matrix has one row per date and one column per permno.
+    pub fn validate(&self) -> Result<()> {
+        let dim = (self.dates.len(), self.permno.len());
+        let dims = [
+            Some(self.ret.dim()),
+            self.me.as_ref().map(|m| m.dim()),
+            self.exchcd.as_ref().map(|m| m.dim()),
+        ];
+        if dims.iter().flatten().any(|&d| d != dim) {
+            return Err(anyhow!(
+                "CRSP matrices {:?} do not match {} dates and {} permnos",
+                dims,
+                dim.0,
+                dim.1
+            ));
+        }
+        Ok(())
+    }
+}
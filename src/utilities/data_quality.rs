@@ -0,0 +1,169 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use ndarray::Array2;
+use polars::prelude::*;
+
+/// A permno whose monthly data starts before its name history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackfilledPermno {
+    pub permno: i32,
+    /// Date of the first MSF observation.
+    pub first_observation: NaiveDate,
+    /// Earliest `namedt` of the permno in the name history.
+    pub name_start: NaiveDate,
+}
+
+/// Outcome of [`backfill_check`].
+#[derive(Debug, Clone, Default)]
+pub struct BackfillReport {
+    /// Number of distinct permnos in the MSF.
+    pub n_permnos: usize,
+    /// Permnos observed before their name history starts, sorted by permno.
+    pub backfilled: Vec<BackfilledPermno>,
+    /// Permnos of the MSF without any name history, sorted.
+    pub missing_name_history: Vec<i32>,
+}
+
+impl BackfillReport {
+    /// Whether no permno was flagged.
+    pub fn is_clean(&self) -> bool {
+        self.backfilled.is_empty() && self.missing_name_history.is_empty()
+    }
+}
+
+/// Flags permnos whose monthly data appears to precede their listing.
+///
+/// The first MSF observation of each permno is compared with the start of its name
+/// history (the earliest `namedt` in STOCKNAMES). Data before the listing suggests it
+/// was backfilled, a source of survivorship bias, so these permnos are reported along
+/// with those that have no name history at all.
+///
+/// # Arguments
+///
+/// * `msf` - CRSP monthly stock file with `permno` and `date` columns.
+/// * `stocknames` - CRSP name history with `permno` and `namedt` columns.
+pub fn backfill_check(msf: &DataFrame, stocknames: &DataFrame) -> Result<BackfillReport> {
+    let first_observation = msf
+        .clone()
+        .lazy()
+        .group_by([col("permno")])
+        .agg([col("date").min().alias("first_observation")]);
+    let name_start = stocknames
+        .clone()
+        .lazy()
+        .group_by([col("permno")])
+        .agg([col("namedt").min().alias("name_start")]);
+    let joined = first_observation
+        .join(
+            name_start,
+            [col("permno")],
+            [col("permno")],
+            JoinArgs::new(JoinType::Left),
+        )
+        .sort(["permno"], Default::default())
+        .collect()?;
+
+    let permnos = joined.column("permno")?.cast(&DataType::Int32)?;
+    let first_observations = joined.column("first_observation")?.cast(&DataType::Date)?;
+    let name_starts = joined.column("name_start")?.cast(&DataType::Date)?;
+
+    let mut report = BackfillReport {
+        n_permnos: joined.height(),
+        ..Default::default()
+    };
+    for ((permno, first), start) in permnos
+        .i32()?
+        .into_iter()
+        .zip(first_observations.date()?.as_date_iter())
+        .zip(name_starts.date()?.as_date_iter())
+    {
+        let (Some(permno), Some(first)) = (permno, first) else {
+            continue;
+        };
+        match start {
+            None => report.missing_name_history.push(permno),
+            Some(name_start) if first < name_start => report.backfilled.push(BackfilledPermno {
+                permno,
+                first_observation: first,
+                name_start,
+            }),
+            Some(_) => {}
+        }
+    }
+    Ok(report)
+}
+
+/// Finds the stocks whose signal never changes, usually the sign of a bug in its
+/// construction, to check before running a sort.
+///
+/// Returns the column (permno) indices of `signal` (nMonths x nStocks) with at least two
+/// non-NaN values, all identical. Columns with fewer observations are not flagged.
+pub fn detect_degenerate_columns(signal: &Array2<f64>) -> Vec<usize> {
+    signal
+        .columns()
+        .into_iter()
+        .enumerate()
+        .filter_map(|(j, column)| {
+            let mut values = column.iter().filter(|v| !v.is_nan());
+            let first = *values.next()?;
+            let mut n_values = 1;
+            for &value in values {
+                if value != first {
+                    return None;
+                }
+                n_values += 1;
+            }
+            (n_values > 1).then_some(j)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backfill_check_flags_data_before_listing() {
+        let date = |y: i32, m: u32, d: u32| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let msf = df![
+            "permno" => [10001, 10001, 10002, 10002, 10003],
+            "date" => [
+                date(2000, 1, 31), date(2000, 2, 29),
+                date(1999, 11, 30), date(1999, 12, 31),
+                date(2000, 1, 31),
+            ],
+        ]
+        .unwrap();
+        let stocknames = df![
+            "permno" => [10001, 10002, 10002],
+            "namedt" => [date(2000, 1, 3), date(2000, 6, 1), date(1999, 12, 15)],
+        ]
+        .unwrap();
+
+        let report = backfill_check(&msf, &stocknames).unwrap();
+
+        assert_eq!(report.n_permnos, 3);
+        assert_eq!(
+            report.backfilled,
+            vec![BackfilledPermno {
+                permno: 10002,
+                first_observation: date(1999, 11, 30),
+                name_start: date(1999, 12, 15),
+            }]
+        );
+        assert_eq!(report.missing_name_history, vec![10003]);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn test_detect_degenerate_columns() {
+        let nan = f64::NAN;
+        let signal = ndarray::array![
+            [0.5, 1.0, nan, nan],
+            [nan, 2.0, 3.0, nan],
+            [0.5, 1.0, nan, nan],
+            [0.5, nan, nan, nan],
+        ];
+        assert_eq!(detect_degenerate_columns(&signal), vec![0]);
+    }
+}
@@ -1,20 +1,23 @@
 use anyhow::anyhow;
-use anyhow::Result;
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use dotenv::dotenv;
 use log::info;
 use native_tls::TlsConnector;
 use polars::prelude::*;
 use postgres_native_tls::MakeTlsConnector;
+use rayon::prelude::*;
 use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
-use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use tokio_postgres::types::{FromSql, Type};
 use tokio_postgres::Client;
 use tokio_postgres::Row;
 
-#[derive(Debug)]
 pub struct WrdsConfig {
     pub user: String,
     pub password: String,
@@ -39,6 +42,56 @@ impl WrdsConfig {
         }
     }
 
+    /// Reads the configuration from a `.pgpass`-style file, so that each WRDS profile
+    /// can be kept in its own file. The first line that is neither empty nor a comment
+    /// (`#`) must be `host:port:dbname:user:password`, with `\:` and `\\` escaping a
+    /// colon or a backslash; a `*` host, port or dbname falls back to the WRDS default.
+    ///
+    /// The `WRDS_*` variables of [`WrdsConfig::from_env`], from the environment or a
+    /// `.env` file, take precedence over the file field by field.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        dotenv().ok();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read WRDS config file: {:?}", path))?;
+        Self::from_pgpass(&content, |key| env::var(key).ok())
+            .with_context(|| format!("Invalid WRDS config file: {:?}", path))
+    }
+
+    fn from_pgpass(content: &str, env_var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .ok_or_else(|| anyhow!("No connection line"))?;
+        let fields = split_pgpass_line(line);
+        let [host, port, dbname, user, password] = fields.as_slice() else {
+            return Err(anyhow!(
+                "Expected host:port:dbname:user:password, got {} fields",
+                fields.len()
+            ));
+        };
+        let or_default = |field: &String, default: &str| {
+            if field == "*" {
+                default.to_string()
+            } else {
+                field.clone()
+            }
+        };
+        let port = env_var("WRDS_PORT").unwrap_or_else(|| or_default(port, "9737"));
+        Ok(WrdsConfig {
+            user: env_var("WRDS_USER").unwrap_or_else(|| user.clone()),
+            password: env_var("WRDS_PASSWORD").unwrap_or_else(|| password.clone()),
+            host: env_var("WRDS_HOST")
+                .unwrap_or_else(|| or_default(host, "wrds-pgdata.wharton.upenn.edu")),
+            port: port
+                .parse()
+                .with_context(|| format!("Port {:?} is not a number", port))?,
+            dbname: env_var("WRDS_DBNAME").unwrap_or_else(|| or_default(dbname, "wrds")),
+        })
+    }
+
+    /// PostgreSQL connection string, with the password in plain text: pass it to the
+    /// driver but never log it.
     pub fn connection_string(&self) -> String {
         format!(
             "host={} port={} user={} password={} dbname={}",
@@ -47,6 +100,33 @@ impl WrdsConfig {
     }
 }
 
+/// Masks the password, so that printing the configuration does not leak it.
+impl std::fmt::Debug for WrdsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WrdsConfig")
+            .field("user", &self.user)
+            .field("password", &"***")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("dbname", &self.dbname)
+            .finish()
+    }
+}
+
+/// Splits a `.pgpass` line on the colons that are not escaped by a backslash.
+fn split_pgpass_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => fields.last_mut().unwrap().extend(chars.next()),
+            ':' => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
 /// Establishes a connection to the WRDS PostgreSQL database using the provided configuration.
 /// Utilizes SSL/TLS for secure communication.
 ///
@@ -76,6 +156,59 @@ pub async fn establish_connection(config: &WrdsConfig) -> Result<Client> {
 
     Ok(client)
 }
+
+/// Default number of connection attempts of [`establish_connection_with_retry`].
+pub const DEFAULT_CONNECT_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry, doubled after each failed attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Establishes a connection like [`establish_connection`], retrying transient failures.
+///
+/// Network and TLS errors are retried up to `max_attempts` attempts in total (see
+/// [`DEFAULT_CONNECT_ATTEMPTS`]), waiting 500ms before the first retry and doubling the
+/// wait each time, up to 30s. Errors reported by the server, such as a wrong password,
+/// are returned immediately.
+pub async fn establish_connection_with_retry(
+    config: &WrdsConfig,
+    max_attempts: u32,
+) -> Result<Client> {
+    let mut attempt = 1;
+    loop {
+        match establish_connection(config).await {
+            Ok(client) => return Ok(client),
+            Err(e) if attempt < max_attempts && is_transient(&e) => {
+                let delay = backoff_delay(attempt);
+                info!(
+                    "Connection attempt {} of {} failed ({}), retrying in {:?}",
+                    attempt, max_attempts, e, delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Wait after the failed attempt number `attempt` (starting at 1).
+fn backoff_delay(attempt: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// Whether a connection error is worth retrying: connection-level PostgreSQL errors are,
+/// errors returned by the server (authentication, unknown database, ...) are not.
+fn is_transient(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<tokio_postgres::Error>()
+        .is_some_and(|e| e.as_db_error().is_none())
+}
+
 /// Downloads a table from the WRDS PostgreSQL database and saves it to disk in the specified format.
 ///
 /// # Arguments
@@ -83,24 +216,30 @@ pub async fn establish_connection(config: &WrdsConfig) -> Result<Client> {
 /// * `libname` - WRDS library name (e.g., "CRSP").
 /// * `memname` - WRDS table name (e.g., "MSF").
 /// * `dir_path` - Directory path to save the downloaded table.
-/// * `custom_query` - Optional custom SQL query to execute.
+/// * `options` - Query and conversion options, see [`WrdsTableOptions`].
 /// * `output_format` - Output format for the saved table ("csv" or "parquet").
 ///
 /// # Returns
 /// * `Result<()>` - Ok if the table was successfully downloaded and saved, or an error.
 ///
 /// # Example
-/// ```rust
+/// ```rust,no_run
 /// use anyhow::Result;
-/// use tokio_postgres::Client;
-/// use wrds::utilities::data_download::get_wrds_table;
+/// use assayinganomalies::utilities::get_crsp_data::{
+///     establish_connection, get_wrds_table, WrdsConfig, WrdsTableOptions,
+/// };
 ///
 /// #[tokio::main]
 /// async fn main() -> Result<()> {
-///    let config = WrdsConfig::from_env();
-///   let client = establish_connection(&config).await?;
-///  get_wrds_table(&client, "CRSP", "MSF", "data/crsp", None, "parquet").await.unwrap();
-/// Ok(())
+///     let config = WrdsConfig::from_env();
+///     let client = establish_connection(&config).await?;
+///     let columns = ["permno", "date", "ret", "prc"];
+///     let options = WrdsTableOptions {
+///         columns: Some(&columns),
+///         ..Default::default()
+///     };
+///     get_wrds_table(&client, "CRSP", "MSF", "data/crsp", &options, "parquet").await?;
+///     Ok(())
 /// }
 /// ```
 ///
@@ -109,18 +248,145 @@ pub async fn get_wrds_table(
     libname: &str,
     memname: &str,
     dir_path: &str,
-    custom_query: Option<&str>,
+    options: &WrdsTableOptions<'_>,
     output_format: &str,
 ) -> Result<()> {
     fs::create_dir_all(dir_path).expect("Failed to create directory");
 
+    let output_file = format!(
+        "{}/{}_{}.{}",
+        dir_path,
+        libname.to_lowercase(),
+        memname.to_lowercase(),
+        output_format
+    );
+    save_wrds_table(
+        client,
+        libname,
+        memname,
+        &output_file,
+        options,
+        output_format,
+    )
+    .await?;
+    info!("Saved table {}.{} to {}", libname, memname, output_file);
+    Ok(())
+}
+
+/// Downloads a table with [`write_wrds_table`] into `<output_file>.tmp`, renamed to
+/// `output_file` once the download is complete, so that a failed query or connection
+/// neither leaves a truncated file nor replaces a previous download.
+async fn save_wrds_table(
+    client: &Client,
+    libname: &str,
+    memname: &str,
+    output_file: &str,
+    options: &WrdsTableOptions<'_>,
+    output_format: &str,
+) -> Result<()> {
+    let tmp_file = format!("{}.tmp", output_file);
+    let file = std::fs::File::create(&tmp_file)?;
+    if let Err(e) = write_wrds_table(client, libname, memname, file, options, output_format).await {
+        fs::remove_file(&tmp_file).ok();
+        return Err(e);
+    }
+    fs::rename(&tmp_file, output_file)
+        .with_context(|| format!("Failed to move {} to {}", tmp_file, output_file))?;
+    Ok(())
+}
+
+/// Options of the WRDS table downloads ([`get_wrds_table`], [`write_wrds_table`] and
+/// [`get_wrds_table_chunked`]). The default downloads the whole table with `numeric`
+/// columns read as `f64`, converting the rows in parallel.
+#[derive(Debug, Clone)]
+pub struct WrdsTableOptions<'a> {
+    /// Custom SQL query to execute instead of selecting the table.
+    pub custom_query: Option<&'a str>,
+    /// Subset of columns to download instead of all of them. Names are quoted, so they
+    /// must match the table's (lowercase) column names exactly. Cannot be combined with
+    /// `custom_query`, which selects its own columns.
+    pub columns: Option<&'a [&'a str]>,
+    /// How `numeric` columns are read, see [`NumericMode`].
+    pub numeric: NumericMode,
+    /// Convert the columns of the downloaded rows in parallel with rayon, and the
+    /// `numeric` columns in parallel chunks of rows. Turn off to keep the conversion on
+    /// the calling thread, e.g. when the rayon pool is busy with other work.
+    pub parallel: bool,
+}
+
+impl Default for WrdsTableOptions<'_> {
+    fn default() -> Self {
+        WrdsTableOptions {
+            custom_query: None,
+            columns: None,
+            numeric: NumericMode::default(),
+            parallel: true,
+        }
+    }
+}
+
+impl WrdsTableOptions<'_> {
+    /// SQL query downloading `libname.memname` with these options.
+    fn query(&self, libname: &str, memname: &str) -> Result<String> {
+        match (self.custom_query, self.columns) {
+            (Some(_), Some(_)) => Err(anyhow!(
+                "A column subset cannot be combined with a custom query for {}.{}",
+                libname,
+                memname
+            )),
+            (Some(custom_query), None) => Ok(custom_query.to_string()),
+            (None, Some(columns)) => select_columns_query(libname, memname, columns),
+            (None, None) => Ok(format!("SELECT * FROM {}.{}", libname, memname)),
+        }
+    }
+}
+
+/// Builds `SELECT "col1", "col2", ... FROM lib.mem`. Each column name is quoted as a
+/// PostgreSQL identifier, with embedded double quotes doubled, so that a name cannot
+/// inject SQL. Returns an error if no column, or an empty column name, is given.
+fn select_columns_query(libname: &str, memname: &str, columns: &[&str]) -> Result<String> {
+    if columns.is_empty() {
+        return Err(anyhow!("At least one column must be requested"));
+    }
+    let quoted = columns
+        .iter()
+        .map(|column| {
+            if column.is_empty() {
+                Err(anyhow!("Column names cannot be empty"))
+            } else {
+                Ok(format!("\"{}\"", column.replace('"', "\"\"")))
+            }
+        })
+        .collect::<Result<Vec<String>>>()?;
+    Ok(format!(
+        "SELECT {} FROM {}.{}",
+        quoted.join(", "),
+        libname,
+        memname
+    ))
+}
+
+/// Downloads a table from the WRDS PostgreSQL database and writes it to any writer, such
+/// as an in-memory buffer or a cloud storage adapter, instead of a local file.
+///
+/// # Arguments
+/// * `client` - A reference to the PostgreSQL client.
+/// * `libname` - WRDS library name (e.g., "CRSP").
+/// * `memname` - WRDS table name (e.g., "MSF").
+/// * `writer` - Destination of the serialized table.
+/// * `options` - Query and conversion options, see [`WrdsTableOptions`].
+/// * `output_format` - Output format ("csv" or "parquet").
+pub async fn write_wrds_table<W: Write>(
+    client: &Client,
+    libname: &str,
+    memname: &str,
+    writer: W,
+    options: &WrdsTableOptions<'_>,
+    output_format: &str,
+) -> Result<()> {
     // Construct table name and SQL query
     let table_name = format!("{}.{}", libname, memname);
-    let query = if let Some(custom_query) = custom_query {
-        custom_query.to_string() // Convert to owned `String` if custom query is provided
-    } else {
-        format!("SELECT * FROM {}", table_name) // Format a new query string
-    };
+    let query = options.query(libname, memname)?;
 
     // Execute query
     let rows = client.query(query.as_str(), &[]).await?;
@@ -128,120 +394,626 @@ pub async fn get_wrds_table(
         return Err(anyhow!("No data found for table: {}", table_name));
     }
 
-    // Prepare DataFrame columns dynamically
-    let mut columns: Vec<Column> = vec![];
-    let schema = rows[0].columns();
+    // Build DataFrame
+    let mut df = rows_to_dataframe(&rows, options.numeric, options.parallel)?;
+    write_dataframe(&mut df, writer, output_format)
+}
 
-    for (idx, column) in schema.iter().enumerate() {
-        let col_name: PlSmallStr = column.name().into(); // Convert to `PlSmallStr`
+/// How PostgreSQL `numeric` columns are read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumericMode {
+    /// Convert to `f64`, which is enough for returns and prices but rounds values with
+    /// more than about 15 significant digits.
+    #[default]
+    Float,
+    /// Keep the exact decimal value, with its scale, as a string column (e.g.
+    /// "1234567890.123456789"), to be parsed back with `rust_decimal`.
+    Exact,
+}
 
-        let data_type = column.type_();
-        let current_series = match data_type.name() {
-            "numeric" => {
-                let col_data: Vec<Option<f64>> = numeric_column_to_f64(&rows, idx);
-                Column::new(col_name.clone(), Series::new(col_name, col_data))
-            }
-            // if date, convert to Vec<chrono>
-            "date" => {
-                let col_data: Vec<Option<chrono::NaiveDate>> =
-                    rows.iter().map(|row| row.get(idx)).collect();
-                Column::new(col_name.clone(), Series::new(col_name, col_data))
-            }
-            "int2" => {
-                let col_data: Vec<Option<i16>> = rows.iter().map(|row| row.get(idx)).collect();
-                Column::new(col_name.clone(), Series::new(col_name, col_data))
-            }
-            "int4" => {
-                let col_data: Vec<Option<i32>> = rows.iter().map(|row| row.get(idx)).collect();
-                Column::new(col_name.clone(), Series::new(col_name, col_data))
-            }
-            "float8" => {
-                let col_data: Vec<Option<f64>> = rows.iter().map(|row| row.get(idx)).collect();
-                Column::new(col_name.clone(), Series::new(col_name, col_data))
-            }
-            "text" | "varchar" => {
-                let col_data: Vec<Option<&str>> = rows.iter().map(|row| row.get(idx)).collect();
-                Column::new(col_name.clone(), Series::new(col_name, col_data))
+/// Default number of rows fetched per batch by [`get_wrds_table_chunked`].
+pub const DEFAULT_FETCH_ROWS: usize = 100_000;
+
+/// Name of the server-side cursor used by [`get_wrds_table_chunked`].
+const CURSOR_NAME: &str = "wrds_table_cursor";
+
+/// Downloads a table like [`get_wrds_table`] but in batches, to a parquet file.
+///
+/// The query runs behind a server-side cursor and `batch_rows` rows are fetched at a
+/// time; each batch is converted to a DataFrame and appended to the parquet file as new
+/// row groups before the next one is fetched. Memory use therefore depends on the batch
+/// size rather than on the size of the table, which matters for tables like CRSP.MSF.
+/// The batches are written to `<output_file>.tmp`, renamed once the last one is written,
+/// so that a failed download neither leaves a truncated file nor replaces a previous one.
+///
+/// # Arguments
+/// * `client` - A reference to the PostgreSQL client.
+/// * `libname` - WRDS library name (e.g., "CRSP").
+/// * `memname` - WRDS table name (e.g., "MSF").
+/// * `dir_path` - Directory path to save the downloaded table.
+/// * `options` - Query and conversion options, see [`WrdsTableOptions`].
+/// * `batch_rows` - Number of rows fetched per batch, e.g. [`DEFAULT_FETCH_ROWS`].
+pub async fn get_wrds_table_chunked(
+    client: &Client,
+    libname: &str,
+    memname: &str,
+    dir_path: &str,
+    options: &WrdsTableOptions<'_>,
+    batch_rows: usize,
+) -> Result<()> {
+    if batch_rows == 0 {
+        return Err(anyhow!("The batch size must be at least one row"));
+    }
+    fs::create_dir_all(dir_path)?;
+    let table_name = format!("{}.{}", libname, memname);
+    let query = options.query(libname, memname)?;
+    let output_file = format!(
+        "{}/{}_{}.parquet",
+        dir_path,
+        libname.to_lowercase(),
+        memname.to_lowercase()
+    );
+    let tmp_file = format!("{}.tmp", output_file);
+    let mut batches = ParquetBatches::new(std::fs::File::create(&tmp_file)?);
+
+    let written = async {
+        // Cursors only live inside a transaction
+        client
+            .batch_execute(&format!(
+                "BEGIN; DECLARE {} NO SCROLL CURSOR FOR {}",
+                CURSOR_NAME, query
+            ))
+            .await?;
+        let fetch = format!("FETCH {} FROM {}", batch_rows, CURSOR_NAME);
+        let download = async {
+            loop {
+                let rows = client.query(fetch.as_str(), &[]).await?;
+                if rows.is_empty() {
+                    return Ok::<(), anyhow::Error>(());
+                }
+                batches.append(&rows_to_dataframe(
+                    &rows,
+                    options.numeric,
+                    options.parallel,
+                )?)?;
+                info!("Fetched {} rows of {}", batches.n_rows, table_name);
             }
-            "bool" => {
-                let col_data: Vec<Option<bool>> = rows.iter().map(|row| row.get(idx)).collect();
+        };
+        if let Err(e) = download.await {
+            client.batch_execute("ROLLBACK").await.ok();
+            return Err(e);
+        }
+        client
+            .batch_execute(&format!("CLOSE {}; COMMIT", CURSOR_NAME))
+            .await?;
+
+        if batches.n_rows == 0 {
+            return Err(anyhow!("No data found for table: {}", table_name));
+        }
+        batches.finish()
+    };
+    if let Err(e) = written.await {
+        fs::remove_file(&tmp_file).ok();
+        return Err(e);
+    }
+    fs::rename(&tmp_file, &output_file)
+        .with_context(|| format!("Failed to move {} to {}", tmp_file, output_file))?;
+    info!("Saved table {} to {}", table_name, output_file);
+    Ok(())
+}
+
+/// Parquet file written one batch of rows at a time, each batch as new row groups. The
+/// schema is taken from the first batch.
+struct ParquetBatches<W: Write> {
+    writer: Option<W>,
+    batched: Option<polars::io::parquet::write::BatchedWriter<W>>,
+    n_rows: usize,
+}
+
+impl<W: Write> ParquetBatches<W> {
+    fn new(writer: W) -> Self {
+        ParquetBatches {
+            writer: Some(writer),
+            batched: None,
+            n_rows: 0,
+        }
+    }
+
+    fn append(&mut self, df: &DataFrame) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            self.batched = Some(ParquetWriter::new(writer).batched(&df.schema())?);
+        }
+        if let Some(batched) = self.batched.as_mut() {
+            batched.write_batch(df)?;
+        }
+        self.n_rows += df.height();
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        if let Some(batched) = self.batched {
+            batched.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts query rows into a DataFrame, converting the columns in parallel if
+/// `parallel` is set.
+fn rows_to_dataframe(rows: &[Row], numeric: NumericMode, parallel: bool) -> Result<DataFrame> {
+    let schema = rows[0].columns();
+    let convert = |(idx, column): (usize, &tokio_postgres::Column)| {
+        rows_to_column(rows, idx, column.name(), column.type_(), numeric, parallel)
+    };
+    let columns: Vec<Column> = if parallel {
+        schema.par_iter().enumerate().map(convert).collect()
+    } else {
+        schema.iter().enumerate().map(convert).collect()
+    };
+    Ok(DataFrame::new(columns)?)
+}
+
+/// Access to the typed values of a query row. Implemented by `tokio_postgres::Row`, and
+/// kept as a trait so that the column conversion does not depend on a live connection.
+pub trait RowValues {
+    fn value<'a, T: FromSql<'a>>(&'a self, idx: usize) -> T;
+}
+
+impl RowValues for Row {
+    fn value<'a, T: FromSql<'a>>(&'a self, idx: usize) -> T {
+        self.get(idx)
+    }
+}
+
+/// Converts one column of the query rows into a Polars column.
+fn rows_to_column<R: RowValues + Sync>(
+    rows: &[R],
+    idx: usize,
+    name: &str,
+    data_type: &Type,
+    numeric: NumericMode,
+    parallel: bool,
+) -> Column {
+    let col_name: PlSmallStr = name.into(); // Convert to `PlSmallStr`
+
+    match data_type.name() {
+        "numeric" => match numeric {
+            NumericMode::Float => {
+                let col_data: Vec<Option<f64>> = numeric_column_to_f64(rows, idx, parallel);
                 Column::new(col_name.clone(), Series::new(col_name, col_data))
             }
-            _ => {
-                // For unsupported types, store as strings for now
-                let col_data: Vec<Option<String>> = rows
-                    .iter()
-                    .map(|row| row.get::<_, Option<String>>(idx))
+            NumericMode::Exact => {
+                let col_data: Vec<Option<String>> = numeric_column_to_decimal(rows, idx, parallel)
+                    .into_iter()
+                    .map(|d| d.map(|d| d.to_string()))
                     .collect();
                 Column::new(col_name.clone(), Series::new(col_name, col_data))
             }
-        };
-        columns.push(current_series);
+        },
+        // if date, convert to Vec<chrono>
+        "date" => {
+            let col_data: Vec<Option<chrono::NaiveDate>> =
+                rows.iter().map(|row| row.value(idx)).collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
+        // Read timestamptz as an instant in UTC and keep its UTC calendar date, so that
+        // the YYYYMM encoding does not depend on the local timezone or DST
+        "timestamptz" => {
+            let col_data: Vec<Option<NaiveDate>> = rows
+                .iter()
+                .map(|row| {
+                    row.value::<Option<DateTime<Utc>>>(idx)
+                        .map(|ts| utc_date(&ts))
+                })
+                .collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
+        // Timestamps without timezone are kept as they are, with PostgreSQL's microsecond
+        // precision
+        "timestamp" => {
+            let col_data = rows
+                .iter()
+                .map(|row| row.value::<Option<NaiveDateTime>>(idx));
+            DatetimeChunked::from_naive_datetime_options(col_name, col_data, TimeUnit::Microseconds)
+                .into_column()
+        }
+        "int2" => {
+            let col_data: Vec<Option<i16>> = rows.iter().map(|row| row.value(idx)).collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
+        "int8" => {
+            let col_data: Vec<Option<i64>> = rows.iter().map(|row| row.value(idx)).collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
+        "int4" => {
+            let col_data: Vec<Option<i32>> = rows.iter().map(|row| row.value(idx)).collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
+        "float8" => {
+            let col_data: Vec<Option<f64>> = rows.iter().map(|row| row.value(idx)).collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
+        "text" | "varchar" => {
+            let col_data: Vec<Option<&str>> = rows.iter().map(|row| row.value(idx)).collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
+        "bool" => {
+            let col_data: Vec<Option<bool>> = rows.iter().map(|row| row.value(idx)).collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
+        _ => {
+            // For unsupported types, store as strings for now
+            let col_data: Vec<Option<String>> = rows
+                .iter()
+                .map(|row| row.value::<Option<String>>(idx))
+                .collect();
+            Column::new(col_name.clone(), Series::new(col_name, col_data))
+        }
     }
+}
 
-    // Build DataFrame
-    let mut df = DataFrame::new(columns)?;
-
-    // Save DataFrame to desired format
-    let output_file = format!(
-        "{}/{}_{}.{}",
-        dir_path,
-        libname.to_lowercase(),
-        memname.to_lowercase(),
-        output_format
-    );
+/// Serializes a DataFrame to a writer in the given format ("csv" or "parquet"). In parquet,
+/// a timestamp `date` column is stored as a `Date`, see [`date_as_date32`].
+fn write_dataframe<W: Write>(df: &mut DataFrame, mut writer: W, output_format: &str) -> Result<()> {
     match output_format {
-        "csv" => {
-            let mut file = std::fs::File::create(&output_file)?;
-            CsvWriter::new(&mut file).finish(&mut df)?;
-        }
+        "csv" => CsvWriter::new(&mut writer).finish(df)?,
         "parquet" => {
-            let mut file = std::fs::File::create(&output_file)?;
-            ParquetWriter::new(&mut file).finish(&mut df)?;
+            date_as_date32(df)?;
+            ParquetWriter::new(&mut writer).finish(df)?;
         }
         _ => return Err(anyhow!("Unsupported output format: {}", output_format)),
     }
-    info!("Saved table {} to {}", table_name, output_file);
     Ok(())
 }
 
-pub async fn get_crsp_data(client: &Client, dir_path: &str, output_format: &str) -> Result<()> {
-    // Download required tables
-    let tables = [
-        ("CRSP", "MSFHDR"),    //
-        ("CRSP", "MSF"),       // Main dataset
-        ("CRSP", "MSEDELIST"), // delisting returns
-        ("CRSP", "MSEEXCHDATES"),
-        ("CRSP", "CCMXPF_LNKHIST"),
-        ("CRSP", "STOCKNAMES"),
-    ];
+/// Casts a timestamp `date` column to `Date`, keeping its calendar date, so that it is
+/// stored with the Arrow `Date32` logical type and read back as a `Date` column for the
+/// range filters of the monthly pipeline. Other `date` columns, such as `YYYYMM` integers,
+/// and frames without one are left unchanged.
+fn date_as_date32(df: &mut DataFrame) -> Result<()> {
+    let Ok(date) = df.column("date") else {
+        return Ok(());
+    };
+    if let DataType::Datetime(_, _) = date.dtype() {
+        let cast = date
+            .cast(&DataType::Date)
+            .context("Failed to cast the date column to Date")?;
+        df.with_column(cast)?;
+    }
+    Ok(())
+}
+
+/// SIZ delisting codes of the CIZ delisting action types. CIZ splits the code into action,
+/// status, reason and payment types, so only the family of the code is kept: the generic
+/// code of mergers (200), exchanges (300), liquidations (400) and drops (500, which counts
+/// as a performance-related delisting). Other action types have no code.
+pub(crate) const CIZ_DELISTING_CODES: [(&str, i32); 4] =
+    [("MER", 200), ("EXC", 300), ("LIQ", 400), ("GDR", 500)];
+
+/// Format of the CRSP stock files on WRDS.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CrspFormat {
+    /// Legacy stock and index format (MSF, MSEDELIST, ...).
+    #[default]
+    Siz,
+    /// Flat file format 2.0 (STKMTHSECURITYDATA, STKDELISTS, ...), with renamed columns.
+    Ciz,
+}
+
+impl CrspFormat {
+    /// CRSP tables to download, as (WRDS table, canonical SIZ name). Files are saved
+    /// under the canonical name so the rest of the pipeline reads the same paths in
+    /// both formats.
+    pub fn tables(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            CrspFormat::Siz => &[
+                ("MSFHDR", "MSFHDR"),
+                ("MSF", "MSF"),             // Main dataset
+                ("MSEDELIST", "MSEDELIST"), // delisting returns
+                ("MSEEXCHDATES", "MSEEXCHDATES"),
+                ("CCMXPF_LNKHIST", "CCMXPF_LNKHIST"),
+                ("STOCKNAMES", "STOCKNAMES"),
+            ],
+            // CIZ has no equivalent of the exchange date history, whose codes were
+            // replaced by new classifications, so it still comes from the legacy table
+            CrspFormat::Ciz => &[
+                ("STKSECURITYINFOHIST", "MSFHDR"),
+                ("STKMTHSECURITYDATA", "MSF"),
+                ("STKDELISTS", "MSEDELIST"),
+                ("MSEEXCHDATES", "MSEEXCHDATES"),
+                ("CCMXPF_LNKHIST", "CCMXPF_LNKHIST"),
+                ("STOCKNAMES", "STOCKNAMES"),
+            ],
+        }
+    }
+
+    /// Columns of the monthly stock file, as (column in this format, canonical name).
+    /// A missing source column means the canonical column has no equivalent in this
+    /// format and is downloaded as nulls.
+    pub fn monthly_columns(&self) -> &'static [(Option<&'static str>, &'static str)] {
+        match self {
+            CrspFormat::Siz => &[
+                (Some("permno"), "permno"),
+                (Some("date"), "date"),
+                (Some("ret"), "ret"),
+                (Some("retx"), "retx"),
+                (Some("prc"), "prc"),
+                (Some("vol"), "vol"),
+                (Some("shrout"), "shrout"),
+                (Some("cfacpr"), "cfacpr"),
+                (Some("cfacshr"), "cfacshr"),
+                (Some("bid"), "bid"),
+                (Some("ask"), "ask"),
+                (Some("bidlo"), "bidlo"),
+                (Some("askhi"), "askhi"),
+                (Some("spread"), "spread"),
+            ],
+            CrspFormat::Ciz => &[
+                (Some("permno"), "permno"),
+                (Some("mthcaldt"), "date"),
+                (Some("mthret"), "ret"),
+                (Some("mthretx"), "retx"),
+                (Some("mthprc"), "prc"),
+                (Some("mthvol"), "vol"),
+                (Some("shrout"), "shrout"),
+                (Some("mthcumfacpr"), "cfacpr"),
+                (Some("mthcumfacshr"), "cfacshr"),
+                (Some("mthbid"), "bid"),
+                (Some("mthask"), "ask"),
+                (None, "bidlo"),
+                (None, "askhi"),
+                (None, "spread"),
+            ],
+        }
+    }
+
+    /// Canonical name of a column of the monthly stock file; unknown columns keep their
+    /// name.
+    pub fn canonical_name<'a>(&self, column: &'a str) -> &'a str {
+        self.monthly_columns()
+            .iter()
+            .find(|(source, _)| *source == Some(column))
+            .map_or(column, |(_, canonical)| canonical)
+    }
+
+    /// Columns of a CIZ table renamed to the columns of its SIZ counterpart, as (SQL
+    /// expression, canonical name), or `None` for the tables downloaded as they are.
+    /// The `dlstcd` of CIZ delistings is derived from their action type through
+    /// [`CIZ_DELISTING_CODES`].
+    pub(crate) fn columns(
+        &self,
+        memname: &str,
+    ) -> Option<&'static [(Option<&'static str>, &'static str)]> {
+        match (self, memname) {
+            (CrspFormat::Siz, _) => None,
+            (CrspFormat::Ciz, "STKMTHSECURITYDATA") => Some(self.monthly_columns()),
+            (CrspFormat::Ciz, "STKDELISTS") => Some(&[
+                (Some("permno"), "permno"),
+                (Some("delistingdt"), "dlstdt"),
+                (Some("delret"), "dlret"),
+                (Some("delactiontype"), "dlstcd"),
+            ]),
+            // The share and exchange codes were replaced by new classifications
+            (CrspFormat::Ciz, "STKSECURITYINFOHIST") => Some(&[
+                (Some("permno"), "permno"),
+                (Some("permco"), "permco"),
+                (Some("secinfostartdt"), "begdat"),
+                (Some("secinfoenddt"), "enddat"),
+                (Some("siccd"), "hsiccd"),
+                (Some("issuernm"), "hcomnam"),
+                (Some("ticker"), "htick"),
+                (None, "hshrcd"),
+                (None, "hexcd"),
+            ]),
+            (CrspFormat::Ciz, _) => None,
+        }
+    }
+
+    /// Query selecting a table with its columns renamed to the canonical names, for the
+    /// tables that need it in this format.
+    fn query(&self, libname: &str, memname: &str) -> Option<String> {
+        let columns: Vec<String> = self
+            .columns(memname)?
+            .iter()
+            .map(|(source, canonical)| match source {
+                Some(source) if *canonical == "dlstcd" => {
+                    let codes: Vec<String> = CIZ_DELISTING_CODES
+                        .iter()
+                        .map(|(action, code)| format!("WHEN '{}' THEN {}", action, code))
+                        .collect();
+                    format!("CASE {} {} END AS dlstcd", source, codes.join(" "))
+                }
+                Some(source) => format!("{} AS {}", source, canonical),
+                None => format!("NULL::float8 AS {}", canonical),
+            })
+            .collect();
+        Some(format!(
+            "SELECT {} FROM {}.{}",
+            columns.join(", "),
+            libname,
+            memname
+        ))
+    }
+}
+
+pub async fn get_crsp_data(
+    client: &Client,
+    dir_path: &str,
+    output_format: &str,
+    crsp_format: CrspFormat,
+) -> Result<()> {
+    fs::create_dir_all(dir_path)?;
 
     // Specify output directory and format
-    for (libname, memname) in &tables {
-        get_wrds_table(&client, libname, memname, dir_path, None, output_format)
-            .await
-            .unwrap();
+    for (memname, canonical) in crsp_format.tables() {
+        let output_file = format!(
+            "{}/crsp_{}.{}",
+            dir_path,
+            canonical.to_lowercase(),
+            output_format
+        );
+        let query = crsp_format.query("CRSP", memname);
+        let options = WrdsTableOptions {
+            custom_query: query.as_deref(),
+            ..Default::default()
+        };
+        save_wrds_table(
+            client,
+            "CRSP",
+            memname,
+            &output_file,
+            &options,
+            output_format,
+        )
+        .await?;
+        info!("Saved table CRSP.{} to {}", memname, output_file);
+    }
+    Ok(())
+}
+
+/// Standard filter of the Compustat annual fundamentals: industrial format, standardized
+/// data, domestic population and consolidated statements, which leaves one record per
+/// firm and fiscal year.
+pub const COMP_FUNDA_FILTER: &str =
+    "indfmt = 'INDL' AND datafmt = 'STD' AND popsrc = 'D' AND consol = 'C'";
+
+/// Query of the Compustat annual fundamentals, filtered with [`COMP_FUNDA_FILTER`].
+fn comp_funda_query() -> String {
+    format!("SELECT * FROM COMP.FUNDA WHERE {}", COMP_FUNDA_FILTER)
+}
+
+/// Downloads the Compustat annual fundamentals (`COMP.FUNDA`, with [`COMP_FUNDA_FILTER`])
+/// and the company header file (`COMP.COMPANY`) into `dir_path` (e.g. `data/compustat`),
+/// as `comp_funda.<output_format>` and `comp_company.<output_format>`, like
+/// [`get_crsp_data`] does for CRSP.
+pub async fn get_comp_data(client: &Client, dir_path: &str, output_format: &str) -> Result<()> {
+    fs::create_dir_all(dir_path)?;
+
+    let funda_query = comp_funda_query();
+    for (memname, query) in [("FUNDA", Some(funda_query.as_str())), ("COMPANY", None)] {
+        let output_file = format!(
+            "{}/comp_{}.{}",
+            dir_path,
+            memname.to_lowercase(),
+            output_format
+        );
+        let options = WrdsTableOptions {
+            custom_query: query,
+            ..Default::default()
+        };
+        save_wrds_table(
+            client,
+            "COMP",
+            memname,
+            &output_file,
+            &options,
+            output_format,
+        )
+        .await?;
+        info!("Saved table COMP.{} to {}", memname, output_file);
     }
     Ok(())
 }
 
+/// Calendar date of a timestamp in UTC, whatever the timezone it is expressed in.
+fn utc_date<Tz: TimeZone>(ts: &DateTime<Tz>) -> NaiveDate {
+    ts.with_timezone(&Utc).date_naive()
+}
+
+/// Minimum number of rows processed by each rayon task in the numeric conversion.
+const NUMERIC_CHUNK_ROWS: usize = 4096;
+
 /// Converts a PostgreSQL `numeric` column into a `Vec<Option<f64>>` for compatibility with Polars.
-fn numeric_column_to_f64(rows: &[Row], column_idx: usize) -> Vec<Option<f64>> {
-    rows.iter()
-        .map(|row| {
-            // Attempt to retrieve the value as a `Decimal`
-            let decimal: Option<Decimal> = row.get(column_idx);
-
-            // Convert `Decimal` to `f64`
-            decimal.and_then(|d| d.to_f64())
-        })
-        .collect()
+/// If `parallel` is set, rows are converted in parallel chunks of at least
+/// [`NUMERIC_CHUNK_ROWS`]; both ways give the same values in the same order.
+fn numeric_column_to_f64<R: RowValues + Sync>(
+    rows: &[R],
+    column_idx: usize,
+    parallel: bool,
+) -> Vec<Option<f64>> {
+    let convert = |row: &R| {
+        // Attempt to retrieve the value as a `Decimal`
+        let decimal: Option<Decimal> = row.value(column_idx);
+
+        // Convert `Decimal` to `f64`
+        decimal.and_then(|d| d.to_f64())
+    };
+    if parallel {
+        rows.par_iter()
+            .with_min_len(NUMERIC_CHUNK_ROWS)
+            .map(convert)
+            .collect()
+    } else {
+        rows.iter().map(convert).collect()
+    }
+}
+
+/// Reads a PostgreSQL `numeric` column as exact decimals, keeping the precision and
+/// scale stored in the database. If `parallel` is set, rows are converted in parallel
+/// chunks of at least [`NUMERIC_CHUNK_ROWS`], keeping their order.
+pub fn numeric_column_to_decimal<R: RowValues + Sync>(
+    rows: &[R],
+    column_idx: usize,
+    parallel: bool,
+) -> Vec<Option<Decimal>> {
+    if parallel {
+        rows.par_iter()
+            .with_min_len(NUMERIC_CHUNK_ROWS)
+            .map(|row| row.value(column_idx))
+            .collect()
+    } else {
+        rows.iter().map(|row| row.value(column_idx)).collect()
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_wrds_config_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wrds.pgpass");
+        fs::write(&path, "# WRDS profile\n*:*:wrds:jdoe:pa\\:ss\\\\word\n").unwrap();
+
+        let config = WrdsConfig::from_file(&path).unwrap();
+        // WRDS_* variables set on this machine take precedence over the file
+        let expected = |key: &str, default: &str| env::var(key).unwrap_or(default.to_string());
+        assert_eq!(config.password, expected("WRDS_PASSWORD", "pa:ss\\word"));
+        assert_eq!(
+            config.connection_string(),
+            format!(
+                "host={} port={} user={} password={} dbname={}",
+                expected("WRDS_HOST", "wrds-pgdata.wharton.upenn.edu"),
+                expected("WRDS_PORT", "9737"),
+                expected("WRDS_USER", "jdoe"),
+                config.password,
+                expected("WRDS_DBNAME", "wrds"),
+            )
+        );
+        assert!(WrdsConfig::from_file(&dir.path().join("missing.pgpass")).is_err());
+
+        // The environment takes precedence over the file
+        let env_var = |key: &str| (key == "WRDS_USER").then(|| "other".to_string());
+        let config =
+            WrdsConfig::from_pgpass("wrds.example.org:5432:wrds:jdoe:secret", env_var).unwrap();
+        assert_eq!(
+            (config.user.as_str(), config.host.as_str(), config.port),
+            ("other", "wrds.example.org", 5432)
+        );
+        assert!(WrdsConfig::from_pgpass("wrds:jdoe:secret", |_| None).is_err());
+    }
+
+    #[test]
+    fn test_wrds_config_debug_masks_password() {
+        let config =
+            WrdsConfig::from_pgpass("wrds.example.org:5432:wrds:jdoe:s3cr3t", |_| None).unwrap();
+        let debug = format!("{:?}", config);
+        assert!(debug.contains("***"));
+        assert!(debug.contains("jdoe"));
+        assert!(!debug.contains("s3cr3t"));
+    }
+
     #[tokio::test]
     async fn test_get_wrds_table() {
         let config = WrdsConfig::from_env();
@@ -261,9 +1033,16 @@ mod test {
         let dir_path = "data/crsp";
         let output_format = "parquet"; // or "csv"
         for (libname, memname) in &tables {
-            get_wrds_table(&client, libname, memname, dir_path, None, output_format)
-                .await
-                .unwrap();
+            get_wrds_table(
+                &client,
+                libname,
+                memname,
+                dir_path,
+                &WrdsTableOptions::default(),
+                output_format,
+            )
+            .await
+            .unwrap();
 
             // Read the parquet file
             let output_file = format!(
@@ -279,6 +1058,346 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_utc_date_near_midnight_keeps_month() {
+        // Midnight UTC on February 1st, expressed in New York time on January 31st
+        let ts = DateTime::parse_from_rfc3339("2000-01-31T19:00:00-05:00").unwrap();
+        let late = DateTime::parse_from_rfc3339("2000-01-31T23:59:59+00:00").unwrap();
+        let dates = Series::new("date".into(), vec![utc_date(&ts), utc_date(&late)]);
+
+        let yyyymm = dates
+            .into_frame()
+            .lazy()
+            .select([col("date").dt().to_string("%Y%m").cast(DataType::Int32)])
+            .collect()
+            .unwrap();
+
+        let encoded: Vec<i32> = yyyymm
+            .column("date")
+            .unwrap()
+            .i32()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(encoded, vec![200002, 200001]);
+    }
+
+    #[test]
+    fn test_write_dataframe_to_in_memory_parquet() {
+        let mut df = df![
+            "permno" => [10001, 10002],
+            "ret" => [Some(0.01), None],
+        ]
+        .unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+
+        write_dataframe(&mut df, &mut buffer, "parquet").unwrap();
+
+        let read_df = ParquetReader::new(std::io::Cursor::new(buffer))
+            .finish()
+            .unwrap();
+        assert!(read_df.equals_missing(&df));
+        assert!(write_dataframe(&mut df, Vec::new(), "xlsx").is_err());
+    }
+
+    #[test]
+    fn test_parquet_keeps_timestamp_dates_as_date() {
+        let date = |m: u32| NaiveDate::from_ymd_opt(2000, m, 28).unwrap();
+        let datetimes: Vec<_> = [1, 2].map(|m| date(m).and_hms_opt(0, 0, 0).unwrap()).into();
+        let as_datetime =
+            DatetimeChunked::from_naive_datetime("date".into(), datetimes, TimeUnit::Microseconds);
+        let as_yyyymm = Column::new("date".into(), [200001, 200002]);
+        let as_string = Column::new("date".into(), ["2000-01-28", "2000-02-28"]);
+
+        for (date_column, dtype) in [
+            (as_datetime.into_column(), DataType::Date),
+            (as_yyyymm, DataType::Int32),
+            (as_string, DataType::String),
+        ] {
+            let mut df = DataFrame::new(vec![
+                Column::new("permno".into(), [10001, 10001]),
+                date_column,
+            ])
+            .unwrap();
+            let mut buffer: Vec<u8> = Vec::new();
+            write_dataframe(&mut df, &mut buffer, "parquet").unwrap();
+
+            let read_df = ParquetReader::new(std::io::Cursor::new(buffer))
+                .finish()
+                .unwrap();
+            assert_eq!(read_df.column("date").unwrap().dtype(), &dtype);
+        }
+    }
+
+    #[test]
+    fn test_ciz_columns_map_to_canonical_names() {
+        let ciz = CrspFormat::Ciz;
+
+        assert_eq!(ciz.canonical_name("mthret"), "ret");
+        assert_eq!(ciz.canonical_name("mthprc"), "prc");
+        assert_eq!(ciz.canonical_name("shrout"), "shrout");
+        assert_eq!(ciz.canonical_name("mthcaldt"), "date");
+        assert_eq!(CrspFormat::Siz.canonical_name("ret"), "ret");
+        // Both formats produce the same canonical columns
+        let canonical = |format: CrspFormat| -> Vec<&str> {
+            format.monthly_columns().iter().map(|(_, c)| *c).collect()
+        };
+        assert_eq!(canonical(CrspFormat::Ciz), canonical(CrspFormat::Siz));
+
+        let query = ciz.query("CRSP", "STKMTHSECURITYDATA").unwrap();
+        assert!(query.starts_with("SELECT permno AS permno, mthcaldt AS date, mthret AS ret"));
+        assert!(query.contains("NULL::float8 AS spread"));
+        assert!(query.ends_with("FROM CRSP.STKMTHSECURITYDATA"));
+        let query = ciz.query("CRSP", "STKDELISTS").unwrap();
+        assert!(query.contains("delistingdt AS dlstdt, delret AS dlret, CASE delactiontype"));
+        assert!(query.contains("END AS dlstcd FROM CRSP.STKDELISTS"));
+        let query = ciz.query("CRSP", "STKSECURITYINFOHIST").unwrap();
+        assert!(query.contains("secinfostartdt AS begdat"));
+        assert!(query.contains("NULL::float8 AS hexcd"));
+        assert!(ciz.query("CRSP", "MSEEXCHDATES").is_none());
+        assert!(CrspFormat::Siz.query("CRSP", "MSF").is_none());
+    }
+
+    /// Row of values in the PostgreSQL binary format, standing in for query results.
+    struct RawRow {
+        types: Vec<Type>,
+        values: Vec<Option<Vec<u8>>>,
+    }
+
+    impl RowValues for RawRow {
+        fn value<'a, T: FromSql<'a>>(&'a self, idx: usize) -> T {
+            T::from_sql_nullable(&self.types[idx], self.values[idx].as_deref()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_int8_and_timestamp_columns_keep_their_types() {
+        let timestamp = NaiveDate::from_ymd_opt(2001, 2, 3)
+            .unwrap()
+            .and_hms_micro_opt(4, 5, 6, 7)
+            .unwrap();
+        let postgres_epoch = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        // Binary formats: big-endian i64, and microseconds since 2000-01-01 for timestamps
+        let micros = (timestamp - postgres_epoch).num_microseconds().unwrap();
+        let row = |gvkey: Option<i64>, ts: Option<i64>| RawRow {
+            types: vec![Type::INT8, Type::TIMESTAMP],
+            values: vec![
+                gvkey.map(|v| v.to_be_bytes().to_vec()),
+                ts.map(|v| v.to_be_bytes().to_vec()),
+            ],
+        };
+        let rows = vec![row(Some(12_345_678_901), Some(micros)), row(None, None)];
+
+        let gvkey = rows_to_column(&rows, 0, "gvkey", &Type::INT8, NumericMode::Float, true);
+        let updated = rows_to_column(
+            &rows,
+            1,
+            "updated",
+            &Type::TIMESTAMP,
+            NumericMode::Float,
+            true,
+        );
+
+        assert_eq!(gvkey.dtype(), &DataType::Int64);
+        assert_eq!(
+            gvkey.i64().unwrap().to_vec(),
+            vec![Some(12_345_678_901), None]
+        );
+        assert_eq!(
+            updated.dtype(),
+            &DataType::Datetime(TimeUnit::Microseconds, None)
+        );
+        assert_eq!(
+            updated.get(0).unwrap(),
+            AnyValue::Datetime(
+                timestamp.and_utc().timestamp_micros(),
+                TimeUnit::Microseconds,
+                None
+            )
+        );
+        assert_eq!(updated.null_count(), 1);
+    }
+
+    #[test]
+    fn test_exact_numeric_round_trips_without_drift() {
+        // Binary numeric: ndigits, weight, sign, dscale, then base-10000 digits
+        let digits: [i16; 6] = [12, 3456, 7890, 1234, 5678, 9000];
+        let mut bytes = Vec::new();
+        for header in [6i16, 2, 0, 9].into_iter().chain(digits) {
+            bytes.extend_from_slice(&header.to_be_bytes());
+        }
+        let rows = vec![
+            RawRow {
+                types: vec![Type::NUMERIC],
+                values: vec![Some(bytes)],
+            },
+            RawRow {
+                types: vec![Type::NUMERIC],
+                values: vec![None],
+            },
+        ];
+        let expected = "1234567890.123456789";
+
+        let decimals = numeric_column_to_decimal(&rows, 0, false);
+        assert_eq!(decimals, vec![Some(expected.parse().unwrap()), None]);
+
+        let exact = rows_to_column(&rows, 0, "at", &Type::NUMERIC, NumericMode::Exact, true);
+        assert_eq!(exact.str().unwrap().get(0), Some(expected));
+        assert_eq!(exact.null_count(), 1);
+
+        let float = rows_to_column(&rows, 0, "at", &Type::NUMERIC, NumericMode::Float, true);
+        let value = float.f64().unwrap().get(0).unwrap();
+        assert_ne!(value.to_string(), expected);
+    }
+
+    #[test]
+    fn test_parquet_batches_append_row_groups() {
+        let mut buffer = Vec::new();
+        let mut batches = ParquetBatches::new(&mut buffer);
+        for start in [0, 3] {
+            let df = df![
+                "permno" => [start, start + 1, start + 2],
+                "ret" => [Some(0.01), None, Some(0.03)],
+            ]
+            .unwrap();
+            batches.append(&df).unwrap();
+        }
+        assert_eq!(batches.n_rows, 6);
+        batches.finish().unwrap();
+
+        let df = ParquetReader::new(std::io::Cursor::new(buffer))
+            .finish()
+            .unwrap();
+        assert_eq!(df.shape(), (6, 2));
+        let permno: Vec<Option<i32>> = df.column("permno").unwrap().i32().unwrap().to_vec();
+        assert_eq!(permno, (0..6).map(Some).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_select_columns_query_quotes_names() {
+        let query = select_columns_query("CRSP", "MSF", &["permno", "date", "ret", "prc"]).unwrap();
+        assert_eq!(
+            query,
+            r#"SELECT "permno", "date", "ret", "prc" FROM CRSP.MSF"#
+        );
+
+        let injected = select_columns_query("CRSP", "MSF", &[r#"ret" FROM x; --"#]).unwrap();
+        assert_eq!(injected, r#"SELECT "ret"" FROM x; --" FROM CRSP.MSF"#);
+        assert!(select_columns_query("CRSP", "MSF", &[]).is_err());
+        assert!(select_columns_query("CRSP", "MSF", &["permno", ""]).is_err());
+    }
+
+    #[test]
+    fn test_comp_funda_query_filters() {
+        let query = comp_funda_query();
+
+        assert!(query.starts_with("SELECT * FROM COMP.FUNDA WHERE "));
+        for filter in [
+            "indfmt = 'INDL'",
+            "datafmt = 'STD'",
+            "popsrc = 'D'",
+            "consol = 'C'",
+        ] {
+            assert!(
+                query.contains(filter),
+                "{} is missing from {}",
+                filter,
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_wrds_table_options_query() {
+        let columns = ["permno", "ret"];
+        let subset = WrdsTableOptions {
+            columns: Some(&columns),
+            ..Default::default()
+        };
+        assert_eq!(
+            subset.query("CRSP", "MSF").unwrap(),
+            r#"SELECT "permno", "ret" FROM CRSP.MSF"#
+        );
+        assert_eq!(
+            WrdsTableOptions::default().query("CRSP", "MSF").unwrap(),
+            "SELECT * FROM CRSP.MSF"
+        );
+
+        let both = WrdsTableOptions {
+            custom_query: Some("SELECT permno FROM CRSP.MSF"),
+            ..subset
+        };
+        assert!(both.query("CRSP", "MSF").is_err());
+    }
+
+    #[test]
+    fn test_backoff_delay_doubles_up_to_cap() {
+        let delays: Vec<u64> = (1..=9)
+            .map(|a| backoff_delay(a).as_millis() as u64)
+            .collect();
+        assert_eq!(
+            delays,
+            vec![500, 1000, 2000, 4000, 8000, 16000, 30000, 30000, 30000]
+        );
+        assert_eq!(backoff_delay(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up_on_unreachable_host() {
+        let config = WrdsConfig {
+            user: "user".to_string(),
+            password: "password".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 1,
+            dbname: "wrds".to_string(),
+        };
+
+        let start = std::time::Instant::now();
+        let result = establish_connection_with_retry(&config, 2).await;
+
+        assert!(result.is_err_and(|e| is_transient(&e)));
+        assert!(start.elapsed() >= INITIAL_BACKOFF);
+    }
+
+    #[test]
+    fn test_parallel_numeric_conversion_matches_serial() {
+        // Binary numeric of +/-(units + fraction / 10000) with four decimals
+        let numeric = |units: i16, fraction: i16, negative: bool| {
+            let sign = if negative { 0x4000 } else { 0 };
+            [2i16, 0, sign, 4, units, fraction]
+                .iter()
+                .flat_map(|v| v.to_be_bytes())
+                .collect::<Vec<u8>>()
+        };
+        let rows: Vec<RawRow> = (0..200_000i32)
+            .map(|i| RawRow {
+                types: vec![Type::NUMERIC],
+                values: vec![(i % 97 != 0).then(|| {
+                    numeric(
+                        (i % 10_000) as i16,
+                        ((i * 7919) % 10_000) as i16,
+                        i % 2 == 1,
+                    )
+                })],
+            })
+            .collect();
+
+        let serial = numeric_column_to_f64(&rows, 0, false);
+        let parallel = numeric_column_to_f64(&rows, 0, true);
+
+        assert_eq!(parallel, serial);
+        assert_eq!(parallel.len(), rows.len());
+        assert_eq!(parallel[1], Some(-1.7919));
+        assert_eq!(parallel[97], None);
+        assert_eq!(
+            numeric_column_to_decimal(&rows, 0, true),
+            numeric_column_to_decimal(&rows, 0, false)
+        );
+    }
+
     #[tokio::test]
     async fn test_get_crsp_data() {
         let config = WrdsConfig::from_env();
@@ -287,7 +1406,7 @@ mod test {
         // Specify output directory and format
         let dir_path = "data/crsp";
         let output_format = "parquet"; // or "csv"
-        get_crsp_data(&client, dir_path, output_format)
+        get_crsp_data(&client, dir_path, output_format, CrspFormat::Siz)
             .await
             .unwrap();
     }
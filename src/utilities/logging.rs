@@ -0,0 +1,61 @@
+use anyhow::Result;
+use env_logger::Builder;
+use log::LevelFilter;
+
+/// Installs a logger so that the `info!` and `warn!` messages of the crate are printed to
+/// stderr. Call it once at the start of a binary, e.g. `init_logging(LevelFilter::Info)`.
+///
+/// Messages up to `level` are shown, unless the `RUST_LOG` environment variable is set,
+/// in which case its directives (e.g. `RUST_LOG=assayinganomalies=debug`) take precedence.
+/// Returns an error if a logger was already installed.
+pub fn init_logging(level: LevelFilter) -> Result<()> {
+    logger_builder(level).try_init()?;
+    Ok(())
+}
+
+/// Logger at `level`, overridden by `RUST_LOG` when set.
+fn logger_builder(level: LevelFilter) -> Builder {
+    let mut builder = Builder::new();
+    builder.filter_level(level).parse_default_env();
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::info;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer appending to a buffer shared with the test.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_info_is_captured_after_init() {
+        let buffer = SharedBuffer::default();
+        logger_builder(LevelFilter::Info)
+            // Ignore any RUST_LOG of the environment running the tests
+            .filter_level(LevelFilter::Info)
+            .target(env_logger::Target::Pipe(Box::new(buffer.clone())))
+            .try_init()
+            .unwrap();
+
+        info!("logging is initialized");
+
+        let captured = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(captured.contains("logging is initialized"));
+        // The global logger can only be installed once
+        assert!(init_logging(LevelFilter::Info).is_err());
+    }
+}
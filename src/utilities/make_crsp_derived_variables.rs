@@ -1,76 +1,304 @@
-use super::make_crsp_monthly_data::{load_parquet, Params};
-use anyhow::Result;
-use ndarray::Array2;
+use super::make_crsp_monthly_data::{load_parquet, Frequency, Params};
+use super::matrix_io::{load_array, save_matrix_in_layout, MatrixCategory};
+use anyhow::{anyhow, Result};
+use log::info;
+use ndarray::{Array2, Zip};
 use polars::lazy::dsl::*;
 use polars::prelude::*;
-use serde::de::DeserializeOwned;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::collections::HashMap;
 use std::path::Path;
 
+/// Replacement return for performance-related delistings without a delisting return on
+/// NYSE and AMEX (Shumway, 1997).
+pub const PERFORMANCE_DELIST_RETURN: f64 = -0.30;
+
+/// Replacement return for performance-related delistings without a delisting return on
+/// NASDAQ (Shumway and Warther, 1999).
+pub const NASDAQ_PERFORMANCE_DELIST_RETURN: f64 = -0.35;
+
+/// CRSP exchange code of NASDAQ.
+const NASDAQ_EXCHCD: i16 = 3;
+
+/// How the delisting events were applied to the permno x date grid.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DelistCoverage {
+    /// Number of delisting events considered.
+    pub n_events: usize,
+    /// Events whose permno and month are on the grid.
+    pub matched: usize,
+    /// Events whose permno or month is outside the grid.
+    pub out_of_sample: usize,
+    /// Matched events without a delisting return that received the replacement return.
+    pub imputed: usize,
+    /// Matched events without a delisting return that were left unadjusted because the
+    /// delisting was not performance-related.
+    pub missing_return: usize,
+}
+
 pub fn make_crsp_derived_variables(params: &Params) -> Result<()> {
     let crsp_dir_path = Path::new(&params.directory).join("data/crsp");
 
     // Load data
-    let mut ret_x_dl: Array2<f64> = load_array(&crsp_dir_path, "ret_x_dl.json")?;
+    let ret_x_dl: Array2<f64> = load_array(&crsp_dir_path, "ret_x_dl.json")?;
     let permno: Array2<i32> = load_array(&crsp_dir_path, "permno.json")?;
     let date: Array2<i32> = load_array(&crsp_dir_path, "dates.json")?;
+    let exchcd: Array2<i16> = load_array(&crsp_dir_path, "exchcd.json")?;
+    let vol_x_adj: Array2<f64> = load_array(&crsp_dir_path, "vol_x_adj.json")?;
+    let prc: Array2<f64> = load_array(&crsp_dir_path, "prc.json")?;
+    let shrout: Array2<f64> = load_array(&crsp_dir_path, "shrout.json")?;
 
     // Read the CRSP delist returns file
-    let mut crsp_msedelist: LazyFrame =
-        load_parquet(&crsp_dir_path.join("crsp_msedelist.parquet"))?;
+    let crsp_msedelist: LazyFrame = load_parquet(&crsp_dir_path.join("crsp_msedelist.parquet"))?;
 
     // Filter delisting data
-    let crsp_msedelist = filter_delisting_data(crsp_msedelist, &permno, &date)?;
-    dbg!(crsp_msedelist);
+    let crsp_msedelist = filter_delisting_data(crsp_msedelist, params.frequency)?;
+
+    // Apply the delisting returns and save the adjusted returns separately
+    let (ret, coverage) =
+        apply_delisting_returns(&ret_x_dl, &exchcd, &permno, &date, &crsp_msedelist)?;
+    info!("Delisting coverage: {:?}", coverage);
+    save_matrix_in_layout(
+        ret,
+        &crsp_dir_path,
+        "ret",
+        MatrixCategory::Derived,
+        params.output_layout,
+        params.matrix_format,
+    )?;
+
+    // Market equity in $millions
+    save_matrix_in_layout(
+        make_market_equity(&prc, &shrout),
+        &crsp_dir_path,
+        "me",
+        MatrixCategory::Derived,
+        params.output_layout,
+        params.matrix_format,
+    )?;
+
+    // Adjust the NASDAQ volume for double counting
+    save_matrix_in_layout(
+        adjust_nasdaq_volume(&vol_x_adj, &exchcd, &date),
+        &crsp_dir_path,
+        "vol",
+        MatrixCategory::Derived,
+        params.output_layout,
+        params.matrix_format,
+    )?;
     Ok(())
 }
 
-fn filter_delisting_data(
-    crsp_msedelist: LazyFrame,
-    permno: &Array2<i32>,
-    date: &Array2<i32>,
-) -> Result<DataFrame> {
-    // Convert permno to a Vec for filtering
-    let permno_vec: Vec<i32> = permno.iter().copied().collect();
+/// Computes market equity in $millions as `abs(prc) * shrout / 1000`.
+///
+/// The absolute price is used because CRSP reports the bid/ask midpoint as a negative
+/// price when there is no closing price, and `shrout` is in thousands of shares. Cells
+/// where either input is zero or missing are NaN, so that they do not enter portfolio
+/// sorts as tiny stocks.
+///
+/// # Arguments
+///
+/// * `prc` - Prices (nMonths x nStocks).
+/// * `shrout` - Shares outstanding in thousands (nMonths x nStocks).
+pub fn make_market_equity(prc: &Array2<f64>, shrout: &Array2<f64>) -> Array2<f64> {
+    assert_eq!(
+        prc.dim(),
+        shrout.dim(),
+        "Prices and shares outstanding must have the same dimensions"
+    );
+    Zip::from(prc).and(shrout).map_collect(|&p, &s| {
+        let me = p.abs() * s / 1000.0;
+        if me.is_finite() && me != 0.0 {
+            me
+        } else {
+            f64::NAN
+        }
+    })
+}
+
+/// Divisor of the NASDAQ volume in month `date` (`YYYYMM`) following Gao and Ritter
+/// (2010): 2.0 before February 2001, 1.8 for the rest of 2001, 1.6 in 2002 and 2003,
+/// and 1.0 from 2004 on.
+fn nasdaq_volume_divisor(date: i32) -> f64 {
+    match date {
+        ..=200101 => 2.0,
+        200102..=200112 => 1.8,
+        200201..=200312 => 1.6,
+        _ => 1.0,
+    }
+}
+
+/// Adjusts the NASDAQ trading volume for the double counting of interdealer trades,
+/// dividing it by [`nasdaq_volume_divisor`] of the month (Gao and Ritter, 2010). Stocks
+/// are treated as NASDAQ in the months where their exchange code is 3; the volume of
+/// NYSE, AMEX and other stocks is left untouched.
+///
+/// # Arguments
+///
+/// * `vol` - Trading volume without adjustment (nMonths x nStocks).
+/// * `exchcd` - Exchange codes (nMonths x nStocks).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1).
+pub fn adjust_nasdaq_volume(
+    vol: &Array2<f64>,
+    exchcd: &Array2<i16>,
+    dates: &Array2<i32>,
+) -> Array2<f64> {
+    assert_eq!(
+        vol.dim(),
+        exchcd.dim(),
+        "Volume and exchange codes must have the same dimensions"
+    );
+    assert_eq!(
+        vol.nrows(),
+        dates.len(),
+        "Volume must have one row per date"
+    );
+
+    let mut adjusted = vol.clone();
+    for (t, &date) in dates.iter().enumerate() {
+        let divisor = nasdaq_volume_divisor(date);
+        for j in 0..vol.ncols() {
+            if exchcd[[t, j]] == NASDAQ_EXCHCD {
+                adjusted[[t, j]] /= divisor;
+            }
+        }
+    }
+    adjusted
+}
 
-    // Clone the Series to avoid "moved value" error
-    let permno_series = Series::new("permno".into(), permno_vec);
+/// Drops the placeholder rows of still-active permnos (dated at the last `dlstdt` of the
+/// file) and adds the delisting period as a `date` column in `YYYYMM` format: the month
+/// of the delisting, or the last month of its quarter for [`Frequency::Quarterly`], so
+/// that the delisting return is compounded with the return of the quarter.
+fn filter_delisting_data(crsp_msedelist: LazyFrame, frequency: Frequency) -> Result<DataFrame> {
+    let date = match frequency {
+        Frequency::Monthly => col("dlstdt").dt().to_string("%Y%m").cast(DataType::Int32),
+        Frequency::Quarterly => {
+            col("dlstdt").dt().year() * lit(100)
+                + col("dlstdt").dt().quarter().cast(DataType::Int32) * lit(3)
+        }
+    };
     // Apply filtering to LazyFrame
     let filtered = crsp_msedelist
-        .lazy()
-        .clone()
-        .filter(
-            cols(["permno"])
-                .is_in(lit(permno_series))
-                .and(col("dlstdt").neq(col("dlstdt").max())),
-        )
-        .with_columns([col("dlstdt")
-            .dt()
-            .to_string("%Y%m")
-            .cast(DataType::Int32)
-            .alias("date")])
-        .filter(cols(["date"]).lt(lit(date.iter().cloned().max().unwrap())))
+        .filter(col("dlstdt").neq(col("dlstdt").max()))
+        .with_columns([date.alias("date")])
         .collect()?;
 
     Ok(filtered)
 }
 
-fn load_array<T>(crsp_path: &Path, file_name: &str) -> Result<Array2<T>>
-where
-    T: DeserializeOwned + std::fmt::Debug,
-{
-    let mut file = File::open(crsp_path.join(file_name))?;
-    let mut json = String::new();
-    file.read_to_string(&mut json)?;
-    // Deserialize JSON to Array2<T>
-    let data: Array2<T> = serde_json::from_str(&json)?;
-    Ok(data)
+/// Whether a CRSP delisting code is performance-related (500 and 520-584).
+fn is_performance_delisting(dlstcd: i32) -> bool {
+    dlstcd == 500 || (520..=584).contains(&dlstcd)
+}
+
+/// Combines returns without delisting adjustment with the delisting returns.
+///
+/// Each event is located on the grid by its permno and delisting month. In that month
+/// the return becomes `(1 + ret) * (1 + dlret) - 1`, or `dlret` alone when the regular
+/// return is missing. Performance-related delistings without a delisting return get
+/// [`NASDAQ_PERFORMANCE_DELIST_RETURN`] if the stock last traded on NASDAQ and
+/// [`PERFORMANCE_DELIST_RETURN`] otherwise; other events without one are left unadjusted.
+/// Events outside the grid, including delistings after the end of the sample, are skipped,
+/// and permnos that never delist pass through unchanged. The adjusted matrix is validated
+/// to have no return below -100%.
+///
+/// # Arguments
+///
+/// * `ret_x_dl` - Returns without delisting adjustment (nMonths x nStocks).
+/// * `exchcd` - Exchange codes (nMonths x nStocks), 0 where missing.
+/// * `permno` - Permno vector (nStocks x 1).
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1).
+/// * `delist` - Delisting events with `permno`, `date` (`YYYYMM`), `dlret` and `dlstcd`.
+pub fn apply_delisting_returns(
+    ret_x_dl: &Array2<f64>,
+    exchcd: &Array2<i16>,
+    permno: &Array2<i32>,
+    dates: &Array2<i32>,
+    delist: &DataFrame,
+) -> Result<(Array2<f64>, DelistCoverage)> {
+    if ret_x_dl.dim() != (dates.len(), permno.len()) {
+        return Err(anyhow!(
+            "Returns {:?} do not match {} dates and {} permnos",
+            ret_x_dl.dim(),
+            dates.len(),
+            permno.len()
+        ));
+    }
+    if exchcd.dim() != ret_x_dl.dim() {
+        return Err(anyhow!(
+            "Exchange codes {:?} do not match returns {:?}",
+            exchcd.dim(),
+            ret_x_dl.dim()
+        ));
+    }
+    let column_of: HashMap<i32, usize> = permno.iter().enumerate().map(|(j, &p)| (p, j)).collect();
+    let row_of: HashMap<i32, usize> = dates.iter().enumerate().map(|(t, &d)| (d, t)).collect();
+
+    let events = delist
+        .clone()
+        .lazy()
+        .select([
+            col("permno").cast(DataType::Int32),
+            col("date").cast(DataType::Int32),
+            col("dlret").cast(DataType::Float64),
+            col("dlstcd").cast(DataType::Int32),
+        ])
+        .collect()?;
+    let event_permno = events.column("permno")?.i32()?;
+    let event_date = events.column("date")?.i32()?;
+    let event_dlret = events.column("dlret")?.f64()?;
+    let event_dlstcd = events.column("dlstcd")?.i32()?;
+
+    let mut ret = ret_x_dl.clone();
+    let mut coverage = DelistCoverage {
+        n_events: events.height(),
+        ..Default::default()
+    };
+    for i in 0..events.height() {
+        let cell = event_permno
+            .get(i)
+            .and_then(|p| column_of.get(&p))
+            .zip(event_date.get(i).and_then(|d| row_of.get(&d)));
+        let Some((&j, &t)) = cell else {
+            coverage.out_of_sample += 1;
+            continue;
+        };
+        coverage.matched += 1;
+        let dlret = match event_dlret.get(i).filter(|r| r.is_finite()) {
+            Some(dlret) => dlret,
+            None if event_dlstcd.get(i).is_some_and(is_performance_delisting) => {
+                coverage.imputed += 1;
+                // Exchange of the last month with a known exchange code
+                let last_exchcd = (0..=t).rev().map(|s| exchcd[[s, j]]).find(|&e| e != 0);
+                if last_exchcd == Some(NASDAQ_EXCHCD) {
+                    NASDAQ_PERFORMANCE_DELIST_RETURN
+                } else {
+                    PERFORMANCE_DELIST_RETURN
+                }
+            }
+            None => {
+                coverage.missing_return += 1;
+                continue;
+            }
+        };
+        let r = ret[[t, j]];
+        ret[[t, j]] = if r.is_finite() {
+            (1.0 + r) * (1.0 + dlret) - 1.0
+        } else {
+            dlret
+        };
+    }
+
+    if let Some(r) = ret.iter().find(|&&r| r < -1.0) {
+        return Err(anyhow!("Delisting-adjusted return {} is below -100%", r));
+    }
+    Ok((ret, coverage))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::utilities::get_crsp_data::{CrspFormat, CIZ_DELISTING_CODES};
     use chrono::NaiveDate;
 
     #[test]
@@ -79,7 +307,7 @@ mod test {
             directory: ".".to_string(),
             sample_start: NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
             sample_end: NaiveDate::from_ymd_opt(2001, 12, 31).unwrap(),
-            dom_com_eq_flag: true,
+            ..Default::default()
         };
         let crsp_dir_path = Path::new(&params.directory).join("data/crsp");
 
@@ -88,13 +316,192 @@ mod test {
         dbg!(ret_x_dl);
     }
 
+    #[test]
+    fn test_apply_delisting_returns_coverage() {
+        let ret_x_dl = ndarray::array![
+            [0.01, 0.02, f64::NAN],
+            [0.10, 0.03, 0.05],
+            [f64::NAN, f64::NAN, 0.04]
+        ];
+        let permno = ndarray::array![[10001], [10002], [10003]];
+        let dates = ndarray::array![[200001], [200002], [200003]];
+        let delist = df![
+            "permno" => [10001, 10002, 10003, 10004, 10003],
+            "date" => [200002, 200003, 200001, 200002, 200012],
+            "dlret" => [Some(-0.5), None, None, Some(-0.1), Some(0.0)],
+            "dlstcd" => [500, 552, 331, 500, 233],
+        ]
+        .unwrap();
+
+        let exchcd = Array2::from_elem(ret_x_dl.dim(), 1i16);
+
+        let (ret, coverage) =
+            apply_delisting_returns(&ret_x_dl, &exchcd, &permno, &dates, &delist).unwrap();
+
+        assert_eq!(
+            coverage,
+            DelistCoverage {
+                n_events: 5,
+                matched: 3,
+                out_of_sample: 2,
+                imputed: 1,
+                missing_return: 1,
+            }
+        );
+        assert!((ret[[1, 0]] + 0.45).abs() < 1e-12);
+        assert_eq!(ret[[2, 1]], PERFORMANCE_DELIST_RETURN);
+        assert!(ret[[0, 2]].is_nan());
+        assert_eq!(ret[[1, 1]], 0.03);
+    }
+
+    #[test]
+    fn test_apply_delisting_returns_by_exchange() {
+        let ret_x_dl = ndarray::array![
+            [0.01, 0.02, 0.03, 0.04],
+            [0.05, f64::NAN, 0.06, 0.07],
+            [f64::NAN, f64::NAN, 0.08, 0.09]
+        ];
+        // The NASDAQ stock has no exchange code in its delisting month
+        let exchcd = ndarray::array![[1i16, 3, 3, 2], [2, 0, 3, 2], [0, 0, 3, 2]];
+        let permno = ndarray::array![[10001], [10002], [10003], [10004]];
+        let dates = ndarray::array![[200001], [200002], [200003]];
+        let delist = df![
+            "permno" => [10001, 10002, 10003],
+            "date" => [200002, 200002, 200104],
+            "dlret" => [None::<f64>, None, None],
+            "dlstcd" => [552, 552, 560],
+        ]
+        .unwrap();
+
+        let (ret, coverage) =
+            apply_delisting_returns(&ret_x_dl, &exchcd, &permno, &dates, &delist).unwrap();
+
+        assert_eq!(coverage.imputed, 2);
+        assert!((ret[[1, 0]] - (1.05 * (1.0 + PERFORMANCE_DELIST_RETURN) - 1.0)).abs() < 1e-12);
+        assert_eq!(ret[[1, 1]], NASDAQ_PERFORMANCE_DELIST_RETURN);
+        // Delisted after the end of the sample
+        assert_eq!(coverage.out_of_sample, 1);
+        assert_eq!(ret.column(2), ret_x_dl.column(2));
+        // Never delisted
+        assert_eq!(ret.column(3), ret_x_dl.column(3));
+        assert_eq!(ret.dim(), ret_x_dl.dim());
+    }
+
+    #[test]
+    fn test_delisting_returns_from_ciz_table() {
+        let date = |y: i32, m: u32, d: u32| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let mut delist = df![
+            "permno" => [10001, 10002, 10003],
+            "delistingdt" => [date(2000, 2, 15), date(2000, 3, 31), date(2000, 12, 31)],
+            "delret" => [Some(-0.2), None, None],
+            "delactiontype" => ["MER", "GDR", "GDR"],
+        ]
+        .unwrap();
+        // Same renaming as the download query of the CIZ delisting table
+        for (source, canonical) in CrspFormat::Ciz.columns("STKDELISTS").unwrap() {
+            let source = source.unwrap();
+            if *canonical == "dlstcd" {
+                let codes: Vec<Option<i32>> = delist
+                    .column(source)
+                    .unwrap()
+                    .str()
+                    .unwrap()
+                    .into_iter()
+                    .map(|action| {
+                        CIZ_DELISTING_CODES
+                            .iter()
+                            .find(|(a, _)| Some(*a) == action)
+                            .map(|(_, code)| *code)
+                    })
+                    .collect();
+                delist
+                    .with_column(Series::new("dlstcd".into(), codes))
+                    .unwrap();
+            } else {
+                delist.rename(source, (*canonical).into()).unwrap();
+            }
+        }
+        let ret_x_dl = ndarray::array![[0.01, 0.02], [0.05, 0.03], [0.02, f64::NAN]];
+        let exchcd = Array2::from_elem(ret_x_dl.dim(), 1i16);
+        let permno = ndarray::array![[10001], [10002]];
+        let dates = ndarray::array![[200001], [200002], [200003]];
+
+        let delist = filter_delisting_data(delist.lazy(), Frequency::Monthly).unwrap();
+        let (ret, coverage) =
+            apply_delisting_returns(&ret_x_dl, &exchcd, &permno, &dates, &delist).unwrap();
+
+        // The last delisting date is the placeholder of active securities
+        assert_eq!((coverage.n_events, coverage.matched), (2, 2));
+        assert!((ret[[1, 0]] - (1.05 * 0.8 - 1.0)).abs() < 1e-12);
+        assert_eq!(ret[[2, 1]], PERFORMANCE_DELIST_RETURN);
+    }
+
+    #[test]
+    fn test_quarterly_delisting_returns() {
+        let date = |y: i32, m: u32, d: u32| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let delist = df![
+            "permno" => [10001, 10002, 10003],
+            "dlstdt" => [date(2000, 2, 15), date(2000, 4, 28), date(2000, 12, 29)],
+            "dlret" => [Some(-0.5), Some(0.1), None],
+            "dlstcd" => [500, 233, 100],
+        ]
+        .unwrap();
+        let ret_x_dl = ndarray::array![[0.2, 0.05], [f64::NAN, 0.1]];
+        let exchcd = Array2::from_elem(ret_x_dl.dim(), 1i16);
+        let permno = ndarray::array![[10001], [10002]];
+        let dates = ndarray::array![[200003], [200006]];
+
+        let delist = filter_delisting_data(delist.lazy(), Frequency::Quarterly).unwrap();
+        let (ret, coverage) =
+            apply_delisting_returns(&ret_x_dl, &exchcd, &permno, &dates, &delist).unwrap();
+
+        // Delistings in February and April fall in the quarters ending in March and June
+        assert_eq!(
+            delist.column("date").unwrap().i32().unwrap().to_vec(),
+            vec![Some(200003), Some(200006)]
+        );
+        assert_eq!(coverage.matched, 2);
+        assert!((ret[[0, 0]] - (1.2 * 0.5 - 1.0)).abs() < 1e-12);
+        assert!((ret[[1, 1]] - (1.1 * 1.1 - 1.0)).abs() < 1e-12);
+        assert_eq!(ret[[0, 1]], 0.05);
+    }
+
+    #[test]
+    fn test_make_market_equity() {
+        let prc = ndarray::array![[10.0, -20.0], [0.0, 5.0], [f64::NAN, 2.5]];
+        let shrout = ndarray::array![[1000.0, 500.0], [300.0, 0.0], [100.0, 4000.0]];
+
+        let me = make_market_equity(&prc, &shrout);
+
+        assert_eq!(me[[0, 0]], 10.0);
+        // Bid/ask midpoints are stored as negative prices
+        assert_eq!(me[[0, 1]], 10.0);
+        assert!(me[[1, 0]].is_nan());
+        assert!(me[[1, 1]].is_nan());
+        assert!(me[[2, 0]].is_nan());
+        assert_eq!(me[[2, 1]], 10.0);
+    }
+
+    #[test]
+    fn test_adjust_nasdaq_volume() {
+        let vol = Array2::from_elem((5, 2), 100.0);
+        let exchcd = ndarray::array![[3i16, 1], [3, 1], [3, 2], [3, 1], [3, 1]];
+        let dates = ndarray::array![[200012], [200101], [200102], [200212], [200401]];
+
+        let adjusted = adjust_nasdaq_volume(&vol, &exchcd, &dates);
+
+        let nasdaq: Vec<f64> = adjusted.column(0).to_vec();
+        assert_eq!(nasdaq, vec![50.0, 50.0, 100.0 / 1.8, 62.5, 100.0]);
+        assert_eq!(adjusted.column(1), vol.column(1));
+    }
+
     #[test]
     fn test_make_crsp_derived_variables() {
         let params = Params {
             directory: ".".to_string(),
             sample_start: NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
             sample_end: NaiveDate::from_ymd_opt(2001, 12, 31).unwrap(),
-            dom_com_eq_flag: true,
+            ..Default::default()
         };
         make_crsp_derived_variables(&params).unwrap();
     }
@@ -1,16 +1,19 @@
-use anyhow::{Context, Result};
+use super::get_crsp_data::CrspFormat;
+use super::matrix_io::{
+    load_array, save_ndarray_as_json, MatrixCategory, MatrixEnum, MatrixFormat, OutputLayout,
+};
+use super::matrix_ops::df_to_array_f64;
+use super::timings::Timings;
+use anyhow::{anyhow, Context, Result};
 use chrono::NaiveDate;
+use log::{info, warn};
+use ndarray::Array2;
 use pivot::pivot;
+use std::collections::HashMap;
 // Use chrono for date handling
 use polars::prelude::*;
-use std::fs;
-use std::ops::BitAnd; // Required for custom logical AND
-                      // ndarrays
-use ndarray::{Array2, Data};
-use ndarray::{ArrayBase, Ix2}; // Import dimensionality types
-use std::fs::File;
-use std::io::{Read, Write};
 use std::path::Path;
+use std::time::Instant;
 
 /// Struct representing the configuration parameters
 #[derive(Debug)]
@@ -18,10 +21,130 @@ pub struct Params {
     pub directory: String,
     pub sample_start: NaiveDate,
     pub sample_end: NaiveDate,
-    pub dom_com_eq_flag: bool,
+    /// Share codes to keep, e.g. [`DOMESTIC_COMMON_EQUITY`] (the default) or with 18 to
+    /// include REITs; all share codes are kept when `None`.
+    pub share_codes: Option<Vec<i32>>,
+    /// Exchange codes to keep (e.g. 1, 2 and 3 for NYSE, AMEX and NASDAQ); all exchanges
+    /// are kept when `None`, the default.
+    pub exchange_codes: Option<Vec<i32>>,
+    pub output_layout: OutputLayout,
+    /// File format of the saved matrices. The `permno`, `dates` and link index files
+    /// stay in JSON either way.
+    pub matrix_format: MatrixFormat,
+    /// Reindex the variables onto the `permno.json`/`dates.json` already in the output
+    /// directory instead of deriving and saving a fresh index, so that the new matrices
+    /// align column for column with a prior build. Observations outside that index are
+    /// dropped.
+    pub reuse_index: bool,
+    /// Alternative names of the input columns, keyed by the name the pipeline expects.
+    /// When an expected column is missing from an input file, the first alias present
+    /// is used in its place, which absorbs renamings across CRSP vintages (e.g. `mthret`
+    /// for `ret` in the CIZ format).
+    pub column_aliases: HashMap<String, Vec<String>>,
+    /// Restricts the universe to these permnos before pivoting (e.g. index
+    /// constituents), so that the matrices only have columns for them.
+    pub permno_filter: Option<Vec<i32>>,
+    /// Warm-start an existing build: only the months after the last date of the
+    /// existing `dates.json` are processed and appended as new rows to the existing
+    /// matrices. Permnos that first appear in the new months are appended as new
+    /// columns, filled with NaN in the earlier months for float variables and with 0 for
+    /// integer codes, as in a full build. Only the new months need to be present in the
+    /// input files, so a download can be limited to them with a custom query in
+    /// `get_wrds_table`.
+    pub update_mode: bool,
+    /// Log a warning for each variable whose share of missing (permno, month) cells,
+    /// before integer codes are zero-filled, exceeds this fraction (e.g. 0.5), so that a
+    /// mostly empty variable like `spread` is not sorted on unknowingly. The share of
+    /// each variable is reported at the info level either way.
+    pub missing_warning: Option<f64>,
+    /// Frequency of the matrices and of the date vector, see [`Frequency`].
+    pub frequency: Frequency,
 }
 
-pub fn make_crsp_monthly_data(params: &Params) -> Result<()> {
+/// Frequency of the CRSP matrices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Frequency {
+    /// One row per month of the MSF.
+    #[default]
+    Monthly,
+    /// One row per calendar quarter, dated at the end of the quarter. Returns are
+    /// compounded over the months of the quarter, volume is summed, `bidlo` and `askhi`
+    /// are the low and high of the quarter, and the other variables (prices, shares,
+    /// codes) are sampled at the last month the stock trades in the quarter.
+    Quarterly,
+}
+
+/// Share codes of domestic common equity, the usual sample of anomaly studies.
+pub const DOMESTIC_COMMON_EQUITY: [i32; 2] = [10, 11];
+
+/// Column aliases of the CRSP CIZ monthly files: the CIZ names of
+/// [`CrspFormat::monthly_columns`], and `mret` for `ret` in some vintages.
+pub fn default_column_aliases() -> HashMap<String, Vec<String>> {
+    let mut aliases: HashMap<String, Vec<String>> = CrspFormat::Ciz
+        .monthly_columns()
+        .iter()
+        .filter_map(|(source, canonical)| {
+            source
+                .filter(|source| source != canonical)
+                .map(|source| (canonical.to_string(), vec![source.to_string()]))
+        })
+        .collect();
+    aliases
+        .entry("ret".to_string())
+        .or_default()
+        .push("mret".to_string());
+    aliases
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            directory: ".".to_string(),
+            sample_start: NaiveDate::from_ymd_opt(1925, 12, 31).unwrap(),
+            sample_end: chrono::Local::now().date_naive(),
+            share_codes: Some(DOMESTIC_COMMON_EQUITY.to_vec()),
+            exchange_codes: None,
+            output_layout: OutputLayout::Flat,
+            matrix_format: MatrixFormat::Json,
+            reuse_index: false,
+            column_aliases: default_column_aliases(),
+            permno_filter: None,
+            update_mode: false,
+            missing_warning: None,
+            frequency: Frequency::Monthly,
+        }
+    }
+}
+
+/// CRSP variables extracted as monthly matrices.
+const VAR_NAMES: [&str; 15] = [
+    "shrcd",
+    "exchcd",
+    "siccd",
+    "prc",
+    "bid",
+    "ask",
+    "bidlo",
+    "askhi",
+    "vol_x_adj",
+    "ret_x_dl",
+    "shrout",
+    "cfacpr",
+    "cfacshr",
+    "spread",
+    "retx",
+];
+
+/// Keys of the index vectors and link file in the output of
+/// [`make_monthly_matrices_from_df`], besides the variables.
+const PERMNO_KEY: &str = "permno";
+const DATES_KEY: &str = "dates";
+const LINK_KEY: &str = "crsp_link";
+
+/// Builds the CRSP monthly matrices and returns the time spent in each stage.
+pub fn make_crsp_monthly_data(params: &Params) -> Result<Timings> {
+    let mut timings = Timings::new();
+
     // Store the CRSP directory path
     let crsp_dir_path = Path::new(&params.directory).join("data/crsp");
 
@@ -29,6 +152,81 @@ pub fn make_crsp_monthly_data(params: &Params) -> Result<()> {
     let crsp_msf_lazy = load_parquet(&crsp_dir_path.join("crsp_msf.parquet"))?;
     let crsp_mseexchdates_lazy = load_parquet(&crsp_dir_path.join("crsp_mseexchdates.parquet"))?;
 
+    let mut matrices =
+        build_monthly_matrices(crsp_msf_lazy, crsp_mseexchdates_lazy, params, &mut timings)?;
+
+    let since = Instant::now();
+    if params.update_mode {
+        append_to_existing(&mut matrices, &crsp_dir_path)?;
+    }
+
+    // Save permno and dates as JSON unless they come from an existing index, and the
+    // link file for the COMPUSTAT matrices creation
+    let mut flat_keys = vec![LINK_KEY];
+    if !params.reuse_index {
+        flat_keys.extend([PERMNO_KEY, DATES_KEY]);
+    }
+    for key in flat_keys {
+        if let Some(MatrixEnum::I32(m)) = matrices.remove(key) {
+            save_ndarray_as_json(m, &crsp_dir_path, &format!("{}.json", key), false)?;
+        }
+    }
+    for var_name in VAR_NAMES {
+        if let Some(matrix) = matrices.remove(var_name) {
+            matrix.save(
+                &crsp_dir_path,
+                var_name,
+                MatrixCategory::Raw,
+                params.output_layout,
+                params.matrix_format,
+            )?;
+        }
+    }
+    timings.record_stage("save", since);
+
+    Ok(timings)
+}
+
+/// Builds the CRSP monthly matrices from DataFrames already in memory, without reading
+/// parquet files or writing anything to disk.
+///
+/// The returned map holds one nMonths x nPermno matrix per CRSP variable (`prc`,
+/// `ret_x_dl`, ...), along with the index vectors `permno` (nPermno x 1) and `dates`
+/// (nMonths x 1, `YYYYMM`) and the `crsp_link` (permno, YYYYMM) pairs. With
+/// `params.reuse_index` the index is read from the output directory as in
+/// [`make_crsp_monthly_data`]. With `params.update_mode` the matrices only hold the new
+/// months, `dates` lists those months and `permno` the updated universe.
+///
+/// # Arguments
+///
+/// * `msf` - CRSP monthly stock file.
+/// * `exchdates` - CRSP exchange dates file with share, exchange and SIC codes.
+/// * `params` - Sample and filter parameters.
+pub fn make_monthly_matrices_from_df(
+    msf: DataFrame,
+    exchdates: DataFrame,
+    params: &Params,
+) -> Result<HashMap<String, MatrixEnum>> {
+    build_monthly_matrices(msf.lazy(), exchdates.lazy(), params, &mut Timings::new())
+}
+
+fn build_monthly_matrices(
+    crsp_msf_lazy: LazyFrame,
+    crsp_mseexchdates_lazy: LazyFrame,
+    params: &Params,
+    timings: &mut Timings,
+) -> Result<HashMap<String, MatrixEnum>> {
+    let mut since = Instant::now();
+    if params.reuse_index && params.update_mode {
+        return Err(anyhow!(
+            "reuse_index and update_mode cannot be combined; update_mode extends the index"
+        ));
+    }
+
+    // Rename aliased columns to the names used below
+    let crsp_msf_lazy = resolve_aliases(crsp_msf_lazy, &params.column_aliases)?;
+    let crsp_mseexchdates_lazy = resolve_aliases(crsp_mseexchdates_lazy, &params.column_aliases)?;
+
     // Perform the join as LazyFrame
     let mut result = crsp_msf_lazy
         .join(
@@ -50,29 +248,91 @@ pub fn make_crsp_monthly_data(params: &Params) -> Result<()> {
         .collect()
         .context("Failed to join and filter the CRSP data.")?;
 
-    // Check to see if we should only keep share codes 10 and 11 (domestic common equity)
-    if params.dom_com_eq_flag {
-        // Filter the DataFrame to only keep share codes 10 and 11
+    // Keep the requested share and exchange codes
+    for (column, codes) in [
+        ("shrcd", &params.share_codes),
+        ("exchcd", &params.exchange_codes),
+    ] {
+        if let Some(codes) = codes {
+            let codes = Series::new(column.into(), codes.as_slice());
+            result = result
+                .lazy()
+                .filter(col(column).cast(DataType::Int32).is_in(lit(codes)))
+                .collect()
+                .with_context(|| format!("Failed to filter the {} codes.", column))?;
+            println!("Filtered the {} codes.", column);
+        }
+    }
+
+    // Restrict the universe to the requested permnos
+    if let Some(permnos) = &params.permno_filter {
+        let permnos = Series::new("permno".into(), permnos.as_slice());
         result = result
-            .clone()
             .lazy()
-            .filter(
-                col("shrcd").eq(lit(10)).or(col("shrcd").eq(lit(11))), // The logic ensures that only rows with share codes 10 and 11 are retained.
-            )
+            .filter(col("permno").cast(DataType::Int32).is_in(lit(permnos)))
             .collect()
-            .context("Failed to filter out non-domestic common equity.")?;
+            .context("Failed to filter the permno subset.")?;
+    }
 
-        println!("Filtered out non-domestic common equity.");
+    if params.frequency == Frequency::Quarterly {
+        result = aggregate_to_quarters(result).context("Failed to aggregate to quarters.")?;
     }
 
     println!("Schema of the filtered DataFrame:\n{:?}", result.schema());
+    timings.record_stage("load and filter", since);
 
-    // Save permno and dates as JSON
-    save_unique_column(&result, "permno", &crsp_dir_path, "permno.json")?;
-    save_unique_dates(&result, "date", &crsp_dir_path, "dates.json")?;
+    // Load the existing index, or derive permno and dates from the data
+    since = Instant::now();
+    let crsp_dir_path = Path::new(&params.directory).join("data/crsp");
+    let (permno, dates) = if params.reuse_index {
+        (
+            load_array::<i32>(&crsp_dir_path, "permno.json")?,
+            load_array::<i32>(&crsp_dir_path, "dates.json")?,
+        )
+    } else if params.update_mode {
+        // Keep the months after the existing build, and extend its permno universe
+        let existing_dates = load_array::<i32>(&crsp_dir_path, "dates.json")?;
+        let last_date = existing_dates
+            .iter()
+            .copied()
+            .max()
+            .ok_or_else(|| anyhow!("The existing dates.json is empty"))?;
+        result = result
+            .lazy()
+            .filter(
+                col("date")
+                    .dt()
+                    .to_string("%Y%m")
+                    .cast(DataType::Int32)
+                    .gt(lit(last_date)),
+            )
+            .collect()?;
+        if result.height() == 0 {
+            return Err(anyhow!("No new months after {}", last_date));
+        }
+        let mut permno = load_array::<i32>(&crsp_dir_path, "permno.json")?
+            .into_iter()
+            .collect::<Vec<i32>>();
+        for p in unique_column(&result, "permno")? {
+            if !permno.contains(&p) {
+                permno.push(p);
+            }
+        }
+        (
+            Array2::from_shape_vec((permno.len(), 1), permno)?,
+            unique_dates(&result, "date")?,
+        )
+    } else {
+        (
+            unique_column(&result, "permno")?,
+            unique_dates(&result, "date")?,
+        )
+    };
 
-    // Save the link file for the COMPUSTAT matrices creation
-    save_link_file(&result, &crsp_dir_path)?;
+    let mut matrices = HashMap::new();
+    matrices.insert(LINK_KEY.to_string(), MatrixEnum::I32(link_matrix(&result)?));
+    timings.record_stage("index vectors and link file", since);
+    since = Instant::now();
 
     // Rename returns to indicate they are without delisting adjustment
     // Rename volume to indicate it is without adjustment for NASDAQ
@@ -87,41 +347,152 @@ pub fn make_crsp_monthly_data(params: &Params) -> Result<()> {
         .collect()
         .unwrap();
 
-    // List of variables to extract
-    let var_names = vec![
-        "shrcd",
-        "exchcd",
-        "siccd",
-        "prc",
-        "bid",
-        "ask",
-        "bidlo",
-        "askhi",
-        "vol_x_adj",
-        "ret_x_dl",
-        "shrout",
-        "cfacpr",
-        "cfacshr",
-        "spread",
-        "retx",
-    ];
-
     // Iterate through the variable names
-    for (i, var_name) in var_names.iter().enumerate() {
+    for (i, var_name) in VAR_NAMES.iter().enumerate() {
         println!(
             "Now working on variable {} ({} out of {}).",
             var_name,
             i + 1,
-            var_names.len()
+            VAR_NAMES.len()
         );
 
-        process_variable(&result, var_name, Path::new(&crsp_dir_path))?;
+        let variable_since = Instant::now();
+        let (matrix, missing) = process_variable(&result, var_name, &permno, &dates)?;
+        warn_if_mostly_missing(var_name, missing, params.missing_warning);
+        matrices.insert(var_name.to_string(), matrix);
+        timings.record_variable(var_name, variable_since);
     }
+    timings.record_stage("variables", since);
+
+    matrices.insert(PERMNO_KEY.to_string(), MatrixEnum::I32(permno));
+    matrices.insert(DATES_KEY.to_string(), MatrixEnum::I32(dates));
+    Ok(matrices)
+}
 
+/// Appends the matrices of the new months to those of the existing build in `dir`. The
+/// existing matrices are padded with zero columns for the permnos added to the index.
+fn append_to_existing(matrices: &mut HashMap<String, MatrixEnum>, dir: &Path) -> Result<()> {
+    let keys = [DATES_KEY, LINK_KEY].into_iter().chain(VAR_NAMES);
+    for key in keys {
+        let Some(new) = matrices.remove(key) else {
+            continue;
+        };
+        let file_name = format!("{}.json", key);
+        let appended = match new {
+            MatrixEnum::I16(m) => MatrixEnum::I16(append_rows(load_array(dir, &file_name)?, m, 0)?),
+            MatrixEnum::I32(m) => MatrixEnum::I32(append_rows(load_array(dir, &file_name)?, m, 0)?),
+            MatrixEnum::I64(m) => MatrixEnum::I64(append_rows(load_array(dir, &file_name)?, m, 0)?),
+            MatrixEnum::F64(m) => {
+                MatrixEnum::F64(append_rows(load_array(dir, &file_name)?, m, f64::NAN)?)
+            }
+        };
+        matrices.insert(key.to_string(), appended);
+    }
     Ok(())
 }
 
-fn save_link_file(dataframe: &DataFrame, path: &Path) -> Result<()> {
+/// Stacks the rows of `new` below `existing`, filling the earlier months of the permnos
+/// that only appear in `new` with `missing`.
+fn append_rows<T: Clone>(existing: Array2<T>, new: Array2<T>, missing: T) -> Result<Array2<T>> {
+    if existing.ncols() > new.ncols() {
+        return Err(anyhow!(
+            "Existing matrix has {} columns, more than the {} of the update",
+            existing.ncols(),
+            new.ncols()
+        ));
+    }
+    let mut appended = Array2::from_elem((existing.nrows() + new.nrows(), new.ncols()), missing);
+    appended
+        .slice_mut(ndarray::s![..existing.nrows(), ..existing.ncols()])
+        .assign(&existing);
+    appended
+        .slice_mut(ndarray::s![existing.nrows().., ..])
+        .assign(&new);
+    Ok(appended)
+}
+
+/// Renames the first alias present of every expected column missing from the frame.
+fn resolve_aliases(
+    mut lazy_df: LazyFrame,
+    aliases: &HashMap<String, Vec<String>>,
+) -> Result<LazyFrame> {
+    let schema = lazy_df.collect_schema()?;
+    let (existing, new): (Vec<&str>, Vec<&str>) = aliases
+        .iter()
+        .filter(|(name, _)| !schema.contains(name))
+        .filter_map(|(name, alternatives)| {
+            alternatives
+                .iter()
+                .find(|alias| schema.contains(alias))
+                .map(|alias| (alias.as_str(), name.as_str()))
+        })
+        .unzip();
+    Ok(lazy_df.rename(existing, new, true))
+}
+
+/// Returns compounded over the months of a quarter.
+const COMPOUNDED_RETURNS: [&str; 2] = ["ret", "retx"];
+
+/// Collapses the monthly observations into one row per permno and calendar quarter, see
+/// [`Frequency::Quarterly`]. The `date` of each row is the last day of its quarter.
+fn aggregate_to_quarters(df: DataFrame) -> Result<DataFrame> {
+    let quarter_end = (col("date").dt().year() * lit(100)
+        + col("date").dt().quarter().cast(DataType::Int32) * lit(3))
+    .alias("quarter_end");
+    // Keep quarters where the variable is missing every month as null
+    let if_observed = |name: &str, aggregate: Expr| {
+        when(col(name).count().gt(lit(0)))
+            .then(aggregate)
+            .otherwise(lit(NULL))
+            .alias(name)
+    };
+    let aggregations = df
+        .get_column_names()
+        .into_iter()
+        .filter(|name| !["permno", "date"].contains(&name.as_str()))
+        .map(|name| match name.as_str() {
+            name if COMPOUNDED_RETURNS.contains(&name) => {
+                if_observed(name, (lit(1.0) + col(name)).product() - lit(1.0))
+            }
+            "vol" => if_observed("vol", col("vol").sum()),
+            "bidlo" => col("bidlo").min(),
+            "askhi" => col("askhi").max(),
+            name => col(name).last(),
+        })
+        .collect::<Vec<_>>();
+    let quarterly = df
+        .lazy()
+        .sort(["permno", "date"], Default::default())
+        .group_by_stable([col("permno"), quarter_end])
+        .agg(aggregations)
+        .sort(["quarter_end", "permno"], Default::default())
+        .collect()?;
+
+    let dates = quarterly
+        .column("quarter_end")?
+        .i32()?
+        .into_iter()
+        .map(|yyyymm| yyyymm.map(last_day_of_month).transpose())
+        .collect::<Result<Vec<_>>>()?;
+    let mut quarterly = quarterly.drop("quarter_end")?;
+    quarterly.with_column(DateChunked::from_naive_date_options("date".into(), dates))?;
+    Ok(quarterly)
+}
+
+/// Last calendar day of the month `YYYYMM`.
+fn last_day_of_month(yyyymm: i32) -> Result<NaiveDate> {
+    let (year, month) = (yyyymm / 100, (yyyymm % 100) as u32);
+    let next_month = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)
+    };
+    next_month
+        .and_then(|date| date.pred_opt())
+        .ok_or_else(|| anyhow!("Invalid month {}", yyyymm))
+}
+
+fn link_matrix(dataframe: &DataFrame) -> Result<Array2<i32>> {
     let link = dataframe
         .clone()
         .lazy()
@@ -131,8 +502,7 @@ fn save_link_file(dataframe: &DataFrame, path: &Path) -> Result<()> {
         ])
         .collect()?;
 
-    let link_array = link.to_ndarray::<Int32Type>(Default::default())?;
-    save_ndarray_as_json(link_array, path, "crsp_link.json")
+    Ok(link.to_ndarray::<Int32Type>(Default::default())?)
 }
 
 pub fn load_parquet(path: &Path) -> Result<LazyFrame> {
@@ -140,40 +510,53 @@ pub fn load_parquet(path: &Path) -> Result<LazyFrame> {
         .with_context(|| format!("Failed to load parquet file: {:?}", path))
 }
 
-fn save_unique_column(df: &DataFrame, column: &str, dir: &Path, filename: &str) -> Result<()> {
-    let unique_values = df
+fn unique_column(df: &DataFrame, column: &str) -> Result<Array2<i32>> {
+    Ok(df
         .clone()
         .lazy()
         .select([col(column).unique_stable()])
         .collect()?
-        .to_ndarray::<Int32Type>(Default::default())?;
-    save_ndarray_as_json(unique_values, dir, filename)
+        .to_ndarray::<Int32Type>(Default::default())?)
 }
 
-fn save_unique_dates(df: &DataFrame, column: &str, dir: &Path, filename: &str) -> Result<()> {
+fn unique_dates(df: &DataFrame, column: &str) -> Result<Array2<i32>> {
     let dates_col = df
         .clone()
         .lazy()
         .select([col(column).dt().to_string("%Y%m").unique_stable()])
         .collect()?;
-    let dates = dates_col
+    Ok(dates_col
         .lazy()
         .select([col(column).cast(DataType::Int32)])
         .collect()?
-        .to_ndarray::<Int32Type>(Default::default())?;
-    save_ndarray_as_json(dates, dir, filename)
+        .to_ndarray::<Int32Type>(Default::default())?)
 }
 
-fn process_variable(df: &DataFrame, var_name: &str, dir: &Path) -> Result<()> {
-    // to dimension nMonths x nPermno
+/// Pivots one variable into the canonical matrix orientation used throughout the crate:
+/// one row per month of `dates` and one column per stock of `permno`, i.e.
+/// `nMonths x nPermno`. [`make_crsp_derived_variables`](super::make_crsp_derived_variables)
+/// and the portfolio code index the matrices as `[[month, stock]]`.
+///
+/// Also returns the fraction of the matrix cells without an observation.
+fn process_variable(
+    df: &DataFrame,
+    var_name: &str,
+    permno: &Array2<i32>,
+    dates: &Array2<i32>,
+) -> Result<(MatrixEnum, f64)> {
     let temp_df = df
         .clone()
         .lazy()
-        .select([col("permno"), col("date"), col(var_name)])
+        .select([
+            col("permno"),
+            col("date").dt().to_string("%Y%m").cast(DataType::Int32),
+            col(var_name),
+        ])
         .collect()?;
 
     let column_type = temp_df.schema().get_field(var_name).unwrap();
 
+    // Columns `on` permno, rows indexed by date
     let mut pivoted_df = pivot(
         &temp_df,
         ["permno"],
@@ -182,47 +565,105 @@ fn process_variable(df: &DataFrame, var_name: &str, dir: &Path) -> Result<()> {
         false,
         None,
         None,
-    )?
-    .fill_null(FillNullStrategy::Zero)?;
+    )?;
+    pivoted_df = align_to_index(&pivoted_df, &column_type.dtype, permno, dates)?;
+    let n_cells = pivoted_df.height() * pivoted_df.width();
+    let n_missing: usize = pivoted_df
+        .get_columns()
+        .iter()
+        .map(|column| column.null_count())
+        .sum();
+    let missing = if n_cells > 0 {
+        n_missing as f64 / n_cells as f64
+    } else {
+        0.0
+    };
+    // Missing integer codes become zero, missing floats stay null and become NaN below,
+    // so that a missing price or return is not mistaken for a real zero
+    if column_type.dtype.is_integer() {
+        pivoted_df = pivoted_df.fill_null(FillNullStrategy::Zero)?;
+    }
 
-    pivoted_df.drop_in_place("date")?;
+    // Pivoting and filling nulls can widen integer columns to Float64, so cast the
+    // columns back to the type of the variable before dispatching on it
+    let columns = pivoted_df
+        .get_columns()
+        .iter()
+        .map(|column| {
+            if column.dtype() == &column_type.dtype {
+                Ok(column.clone())
+            } else {
+                column.strict_cast(&column_type.dtype)
+            }
+        })
+        .collect::<PolarsResult<Vec<Column>>>()
+        .with_context(|| format!("Failed to cast {} back to {}", var_name, column_type.dtype))?;
+    pivoted_df = DataFrame::new(columns)?;
 
-    match column_type.dtype {
-        DataType::Int16 => save_ndarray::<Int16Type>(&pivoted_df, dir, var_name),
-        DataType::Int32 => save_ndarray::<Int32Type>(&pivoted_df, dir, var_name),
-        DataType::Int64 => save_ndarray::<Int64Type>(&pivoted_df, dir, var_name),
-        DataType::Float32 => save_ndarray::<Float32Type>(&pivoted_df, dir, var_name),
-        DataType::Float64 => save_ndarray::<Float64Type>(&pivoted_df, dir, var_name),
-        _ => Err(anyhow::anyhow!("Unsupported data type for {}", var_name)),
-    }
+    let matrix = match column_type.dtype {
+        DataType::Int16 => MatrixEnum::I16(pivoted_df.to_ndarray::<Int16Type>(Default::default())?),
+        DataType::Int32 => MatrixEnum::I32(pivoted_df.to_ndarray::<Int32Type>(Default::default())?),
+        DataType::Int64 => MatrixEnum::I64(pivoted_df.to_ndarray::<Int64Type>(Default::default())?),
+        DataType::Float32 | DataType::Float64 => MatrixEnum::F64(df_to_array_f64(&pivoted_df)?),
+        _ => return Err(anyhow::anyhow!("Unsupported data type for {}", var_name)),
+    };
+    Ok((matrix, missing))
 }
 
-fn save_ndarray<T: PolarsNumericType>(df: &DataFrame, dir: &Path, var_name: &str) -> Result<()>
-where
-    T: PolarsNumericType,
-    T::Native: serde::Serialize,
-{
-    let ndarray = df.to_ndarray::<T>(Default::default())?;
-    save_ndarray_as_json(ndarray, dir, &format!("{}.json", var_name))
+/// Reports the missing fraction of a variable, with a warning when it exceeds
+/// `threshold`. Returns whether the warning was emitted.
+fn warn_if_mostly_missing(var_name: &str, missing: f64, threshold: Option<f64>) -> bool {
+    info!("{} is {:.1}% missing", var_name, 100.0 * missing);
+    match threshold {
+        Some(threshold) if missing > threshold => {
+            warn!(
+                "{} is {:.1}% missing, above the {:.1}% threshold",
+                var_name,
+                100.0 * missing,
+                100.0 * threshold
+            );
+            true
+        }
+        _ => false,
+    }
 }
 
-fn save_ndarray_as_json<T: serde::Serialize>(
-    ndarray: Array2<T>,
-    dir: &Path,
-    filename: &str,
-) -> Result<()> {
-    let json = serde_json::to_string(&ndarray)?;
-    let file_path = dir.join(filename);
-    File::create(&file_path)
-        .and_then(|mut file| file.write_all(json.as_bytes()))
-        .with_context(|| format!("Failed to write ndarray to file: {:?}", file_path))?;
-    println!("Saved matrix for {}.", filename);
-    Ok(())
+/// Reorders the pivoted rows and columns to follow `dates` and `permno`. Months or permnos
+/// of the index without observations come out as nulls, and observations outside the index
+/// are dropped.
+fn align_to_index(
+    pivoted_df: &DataFrame,
+    dtype: &DataType,
+    permno: &Array2<i32>,
+    dates: &Array2<i32>,
+) -> Result<DataFrame> {
+    let pivoted_dates = pivoted_df.column("date")?.i32()?;
+    let row_of: HashMap<i32, IdxSize> = pivoted_dates
+        .into_iter()
+        .enumerate()
+        .filter_map(|(row, date)| date.map(|d| (d, row as IdxSize)))
+        .collect();
+    let rows: IdxCa = dates.iter().map(|date| row_of.get(date).copied()).collect();
+    let rows = pivoted_df.take(&rows)?;
+
+    let columns = permno
+        .iter()
+        .map(|p| {
+            let name = p.to_string();
+            match rows.column(&name) {
+                Ok(column) => column.clone(),
+                Err(_) => Column::full_null(name.into(), rows.height(), dtype),
+            }
+        })
+        .collect();
+    Ok(DataFrame::new(columns)?)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::File;
+    use std::io::{Read, Write};
 
     #[test]
     fn test_rename_column() {
@@ -257,7 +698,7 @@ mod tests {
             directory: ".".to_string(),
             sample_start: NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
             sample_end: NaiveDate::from_ymd_opt(2001, 12, 31).unwrap(),
-            dom_com_eq_flag: true,
+            ..Default::default()
         };
 
         make_crsp_monthly_data(&params).unwrap();
@@ -335,4 +776,445 @@ mod tests {
 
         println!("{:?}", df_numeric)
     }
+
+    /// Small synthetic CRSP MSF and MSEEXCHDATES frames. Three permnos over three months
+    /// of 2000: 10001 (NYSE) and 10002 (NASDAQ) trade every month, 10003 (AMEX) skips
+    /// February.
+    pub(crate) fn synthetic_crsp_frames() -> (DataFrame, DataFrame) {
+        let date = |m: u32, d: u32| NaiveDate::from_ymd_opt(2000, m, d).unwrap();
+
+        let msf = df![
+            "permno" => [10001, 10001, 10001, 10002, 10002, 10002, 10003, 10003],
+            "date" => [
+                date(1, 31), date(2, 29), date(3, 31),
+                date(1, 31), date(2, 29), date(3, 31),
+                date(1, 31), date(3, 31),
+            ],
+            "ret" => [0.01, 0.02, -0.01, 0.05, -0.02, 0.03, 0.10, -0.05],
+            "retx" => [0.01, 0.015, -0.01, 0.05, -0.02, 0.025, 0.10, -0.05],
+            "prc" => [10.0, 10.2, 10.1, -5.0, 4.9, 5.05, 2.0, 1.9],
+            "vol" => [100.0, 110.0, 120.0, 200.0, 210.0, 220.0, 30.0, 35.0],
+            "shrout" => [1000.0, 1000.0, 1000.0, 500.0, 500.0, 510.0, 50.0, 50.0],
+            "cfacpr" => [1.0; 8],
+            "cfacshr" => [1.0; 8],
+            "bid" => [9.9, 10.1, 10.0, 4.9, 4.8, 5.0, 1.9, 1.8],
+            "ask" => [10.1, 10.3, 10.2, 5.1, 5.0, 5.1, 2.1, 2.0],
+            "bidlo" => [9.5, 9.8, 9.7, 4.5, 4.6, 4.8, 1.7, 1.7],
+            "askhi" => [10.5, 10.6, 10.4, 5.5, 5.2, 5.3, 2.3, 2.2],
+            "spread" => [0.2, 0.2, 0.2, 0.2, 0.2, 0.1, 0.2, 0.2],
+        ]
+        .unwrap();
+        let exchdates = df![
+            "permno" => [10001, 10002, 10003],
+            "namedt" => [date(1, 1) - chrono::Days::new(3650); 3],
+            "nameendt" => [date(12, 31); 3],
+            "shrcd" => [10i16, 11, 11],
+            "exchcd" => [1i16, 3, 2],
+            "siccd" => [3571i16, 7372, 6020],
+        ]
+        .unwrap();
+        (msf, exchdates)
+    }
+
+    /// Parameters covering the sample of [`synthetic_crsp_frames`], writing to `dir`.
+    fn synthetic_params(dir: &Path) -> Params {
+        Params {
+            directory: dir.to_str().unwrap().to_string(),
+            sample_start: NaiveDate::from_ymd_opt(2000, 1, 1).unwrap(),
+            sample_end: NaiveDate::from_ymd_opt(2000, 12, 31).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    /// Writes the [`synthetic_crsp_frames`] into `<dir>/data/crsp` and returns parameters
+    /// covering their sample.
+    pub(crate) fn write_synthetic_crsp(dir: &Path) -> Params {
+        let crsp_dir = dir.join("data/crsp");
+        std::fs::create_dir_all(&crsp_dir).unwrap();
+        let (mut msf, mut exchdates) = synthetic_crsp_frames();
+
+        let mut file = File::create(crsp_dir.join("crsp_msf.parquet")).unwrap();
+        ParquetWriter::new(&mut file).finish(&mut msf).unwrap();
+        let mut file = File::create(crsp_dir.join("crsp_mseexchdates.parquet")).unwrap();
+        ParquetWriter::new(&mut file)
+            .finish(&mut exchdates)
+            .unwrap();
+
+        synthetic_params(dir)
+    }
+
+    #[test]
+    fn test_make_monthly_matrices_from_df() {
+        let dir = tempfile::tempdir().unwrap();
+        let (msf, exchdates) = synthetic_crsp_frames();
+
+        let matrices =
+            make_monthly_matrices_from_df(msf, exchdates, &synthetic_params(dir.path())).unwrap();
+
+        assert_eq!(matrices.len(), VAR_NAMES.len() + 3);
+        assert_eq!(
+            matrices["permno"],
+            MatrixEnum::I32(ndarray::array![[10001], [10002], [10003]])
+        );
+        assert_eq!(
+            matrices["dates"],
+            MatrixEnum::I32(ndarray::array![[200001], [200002], [200003]])
+        );
+        let MatrixEnum::F64(prc) = &matrices["prc"] else {
+            panic!("prc should be a float matrix");
+        };
+        assert_eq!(prc[[0, 2]], 2.0);
+        assert!(prc[[1, 2]].is_nan());
+        assert_eq!(prc[[2, 2]], 1.9);
+        assert_eq!(matrices["exchcd"].dim(), (3, 3));
+        assert_eq!(matrices["crsp_link"].dim(), (8, 2));
+        // Nothing is written to disk
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_nullable_integer_column_keeps_its_type() {
+        let date = |m: u32| NaiveDate::from_ymd_opt(2000, m, 28).unwrap();
+        let df = df![
+            "permno" => [10001, 10001, 10002],
+            "date" => [date(1), date(2), date(1)],
+            "siccd" => [Some(3571i16), None, Some(7372)],
+        ]
+        .unwrap();
+        let permno = ndarray::array![[10001], [10002]];
+        let dates = ndarray::array![[200001], [200002]];
+
+        let (matrix, _) = process_variable(&df, "siccd", &permno, &dates).unwrap();
+
+        assert_eq!(
+            matrix,
+            MatrixEnum::I16(ndarray::array![[3571, 7372], [0, 0]])
+        );
+    }
+
+    #[test]
+    fn test_update_mode_appends_new_month() {
+        // 10003 is only listed from March, so the update appends it as a new permno
+        let write_crsp = |dir: &Path| {
+            let params = write_synthetic_crsp(dir);
+            let (msf, _) = synthetic_crsp_frames();
+            let mut msf = msf
+                .lazy()
+                .filter(
+                    col("permno")
+                        .neq(lit(10003))
+                        .or(col("date").dt().month().eq(lit(3))),
+                )
+                .collect()
+                .unwrap();
+            let mut file = File::create(dir.join("data/crsp/crsp_msf.parquet")).unwrap();
+            ParquetWriter::new(&mut file).finish(&mut msf).unwrap();
+            params
+        };
+        let full_dir = tempfile::tempdir().unwrap();
+        make_crsp_monthly_data(&write_crsp(full_dir.path())).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let params = write_crsp(dir.path());
+        make_crsp_monthly_data(&Params {
+            sample_end: NaiveDate::from_ymd_opt(2000, 2, 29).unwrap(),
+            ..synthetic_params(dir.path())
+        })
+        .unwrap();
+
+        make_crsp_monthly_data(&Params {
+            update_mode: true,
+            ..params
+        })
+        .unwrap();
+
+        let crsp_dir = dir.path().join("data/crsp");
+        let full_crsp_dir = full_dir.path().join("data/crsp");
+        let dates: Array2<i32> = load_array(&crsp_dir, "dates.json").unwrap();
+        assert_eq!(dates, ndarray::array![[200001], [200002], [200003]]);
+        for file_name in ["prc.json", "ret_x_dl.json"] {
+            let updated: Array2<f64> = load_array(&crsp_dir, file_name).unwrap();
+            let full: Array2<f64> = load_array(&full_crsp_dir, file_name).unwrap();
+            assert_eq!(updated.dim(), full.dim());
+            assert!(updated
+                .iter()
+                .zip(full.iter())
+                .all(|(a, b)| a == b || (a.is_nan() && b.is_nan())));
+            // The earlier months of the new permno are missing, not zero
+            assert!(updated.column(2).iter().take(2).all(|v| v.is_nan()));
+        }
+        let exchcd: Array2<i16> = load_array(&crsp_dir, "exchcd.json").unwrap();
+        assert_eq!(exchcd.column(2).to_vec(), vec![0, 0, 2]);
+        assert_eq!(exchcd.row(2).to_vec(), vec![1, 3, 2]);
+        let link: Array2<i32> = load_array(&crsp_dir, "crsp_link.json").unwrap();
+        assert_eq!(link.nrows(), 7);
+
+        // A second update without new months is an error
+        let params = Params {
+            update_mode: true,
+            ..synthetic_params(dir.path())
+        };
+        assert!(make_crsp_monthly_data(&params).is_err());
+    }
+
+    #[test]
+    fn test_append_rows_pads_new_permnos() {
+        let existing = ndarray::array![[1.0, 2.0]];
+        let new = ndarray::array![[3.0, 4.0, 5.0]];
+
+        let appended = append_rows(existing, new, -1.0).unwrap();
+
+        assert_eq!(appended, ndarray::array![[1.0, 2.0, -1.0], [3.0, 4.0, 5.0]]);
+    }
+
+    #[test]
+    fn test_permno_filter_restricts_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let (msf, exchdates) = synthetic_crsp_frames();
+        let params = Params {
+            permno_filter: Some(vec![10001, 10003]),
+            ..synthetic_params(dir.path())
+        };
+
+        let matrices = make_monthly_matrices_from_df(msf, exchdates, &params).unwrap();
+
+        assert_eq!(
+            matrices["permno"],
+            MatrixEnum::I32(ndarray::array![[10001], [10003]])
+        );
+        for var_name in VAR_NAMES {
+            assert_eq!(matrices[var_name].dim(), (3, 2));
+        }
+    }
+
+    #[test]
+    fn test_column_aliases_resolve_to_expected_variables() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut msf, exchdates) = synthetic_crsp_frames();
+        msf.rename("retx", "mthretx".into()).unwrap();
+        msf.rename("spread", "baspread".into()).unwrap();
+        let mut params = synthetic_params(dir.path());
+        params
+            .column_aliases
+            .insert("spread".to_string(), vec!["baspread".to_string()]);
+
+        let matrices = make_monthly_matrices_from_df(msf, exchdates, &params).unwrap();
+
+        let MatrixEnum::F64(retx) = &matrices["retx"] else {
+            panic!("retx should be a float matrix");
+        };
+        assert_eq!(retx.column(0).to_vec(), vec![0.01, 0.015, -0.01]);
+        let MatrixEnum::F64(spread) = &matrices["spread"] else {
+            panic!("spread should be a float matrix");
+        };
+        assert_eq!(spread.column(1).to_vec(), vec![0.2, 0.2, 0.1]);
+    }
+
+    #[test]
+    fn test_make_crsp_monthly_data_categorized_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = Params {
+            output_layout: OutputLayout::Categorized,
+            ..write_synthetic_crsp(dir.path())
+        };
+
+        let timings = make_crsp_monthly_data(&params).unwrap();
+
+        let stages: Vec<&str> = timings.stages().iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(
+            stages,
+            [
+                "load and filter",
+                "index vectors and link file",
+                "variables",
+                "save"
+            ]
+        );
+        assert_eq!(timings.variables().len(), 15);
+        assert!(timings.stage_total() <= timings.total());
+
+        let crsp_dir = dir.path().join("data/crsp");
+        assert!(crsp_dir.join("raw/prc.json").exists());
+        assert!(!crsp_dir.join("prc.json").exists());
+        let shrout: Array2<f64> =
+            crate::utilities::matrix_io::load_array(&crsp_dir, "shrout.json").unwrap();
+        assert_eq!(shrout.dim(), (3, 3));
+    }
+
+    #[test]
+    fn test_absent_permno_month_is_nan_in_prc() {
+        let dir = tempfile::tempdir().unwrap();
+        make_crsp_monthly_data(&write_synthetic_crsp(dir.path())).unwrap();
+
+        let crsp_dir = dir.path().join("data/crsp");
+        let prc: Array2<f64> = load_array(&crsp_dir, "prc.json").unwrap();
+        let shrcd: Array2<i16> = load_array(&crsp_dir, "shrcd.json").unwrap();
+        // 10003 does not trade in February 2000
+        assert!(prc[[1, 2]].is_nan());
+        assert_eq!(prc[[2, 2]], 1.9);
+        assert_eq!(shrcd[[1, 2]], 0);
+    }
+
+    #[test]
+    fn test_make_crsp_monthly_data_reuses_existing_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let params = Params {
+            reuse_index: true,
+            ..write_synthetic_crsp(dir.path())
+        };
+        // Index from a prior build: different permno order, an extra permno and month
+        let crsp_dir = dir.path().join("data/crsp");
+        let permno = ndarray::array![[10003], [10001], [99999], [10002]];
+        let dates = ndarray::array![[199912], [200001], [200002], [200003]];
+        save_ndarray_as_json(permno.clone(), &crsp_dir, "permno.json", false).unwrap();
+        save_ndarray_as_json(dates.clone(), &crsp_dir, "dates.json", false).unwrap();
+
+        make_crsp_monthly_data(&params).unwrap();
+
+        let prc: Array2<f64> = load_array(&crsp_dir, "prc.json").unwrap();
+        assert_eq!(prc.dim(), (4, 4));
+        assert!(prc.row(0).iter().all(|p| p.is_nan()));
+        assert!(prc.column(2).iter().all(|p| p.is_nan()));
+        assert_eq!(
+            prc.slice(ndarray::s![1.., 1]).to_vec(),
+            vec![10.0, 10.2, 10.1]
+        );
+        assert_eq!(
+            prc.slice(ndarray::s![1.., 3]).to_vec(),
+            vec![-5.0, 4.9, 5.05]
+        );
+        // 10003 has no observation in February
+        assert_eq!(prc[[1, 0]], 2.0);
+        assert!(prc[[2, 0]].is_nan());
+        assert_eq!(prc[[3, 0]], 1.9);
+        let exchcd: Array2<i16> = load_array(&crsp_dir, "exchcd.json").unwrap();
+        assert_eq!(exchcd.row(1).to_vec(), vec![2, 1, 0, 3]);
+        // The existing index is left untouched
+        assert_eq!(load_array::<i32>(&crsp_dir, "permno.json").unwrap(), permno);
+        assert_eq!(load_array::<i32>(&crsp_dir, "dates.json").unwrap(), dates);
+    }
+
+    #[test]
+    fn test_saved_matrices_are_months_by_permnos() {
+        let dir = tempfile::tempdir().unwrap();
+        // Two permnos over three months, so that a transposed matrix would be caught
+        let params = Params {
+            permno_filter: Some(vec![10001, 10003]),
+            ..write_synthetic_crsp(dir.path())
+        };
+        make_crsp_monthly_data(&params).unwrap();
+
+        let crsp_dir = dir.path().join("data/crsp");
+        let permno: Array2<i32> = load_array(&crsp_dir, "permno.json").unwrap();
+        let dates: Array2<i32> = load_array(&crsp_dir, "dates.json").unwrap();
+        assert_eq!((dates.len(), permno.len()), (3, 2));
+        for var_name in VAR_NAMES {
+            let matrix: Array2<f64> = load_array(&crsp_dir, &format!("{}.json", var_name)).unwrap();
+            assert_eq!(matrix.dim(), (dates.len(), permno.len()), "{}", var_name);
+        }
+    }
+
+    #[test]
+    fn test_mostly_missing_variable_triggers_warning() {
+        let date = |m: u32| NaiveDate::from_ymd_opt(2000, m, 28).unwrap();
+        // One spread observation over ten permno-months
+        let df = df![
+            "permno" => [10001, 10001, 10001, 10001, 10001, 10002, 10002, 10002, 10002, 10002],
+            "date" => [date(1), date(2), date(3), date(4), date(5), date(1), date(2), date(3), date(4), date(5)],
+            "spread" => [Some(0.01), None, None, None, None, None, None, None, None, None],
+        ]
+        .unwrap();
+        let permno = ndarray::array![[10001], [10002]];
+        let dates = ndarray::array![[200001], [200002], [200003], [200004], [200005]];
+
+        let (_, missing) = process_variable(&df, "spread", &permno, &dates).unwrap();
+
+        assert!((missing - 0.9).abs() < 1e-12);
+        assert!(warn_if_mostly_missing("spread", missing, Some(0.5)));
+        assert!(!warn_if_mostly_missing("spread", missing, Some(0.95)));
+        assert!(!warn_if_mostly_missing("spread", missing, None));
+    }
+
+    #[test]
+    fn test_quarterly_frequency_compounds_returns() {
+        let dir = tempfile::tempdir().unwrap();
+        let (msf, exchdates) = synthetic_crsp_frames();
+        // 10001 also trades in April, the first month of the second quarter
+        let april = msf
+            .clone()
+            .lazy()
+            .filter(col("permno").eq(lit(10001)))
+            .tail(1)
+            .with_columns([
+                lit(NaiveDate::from_ymd_opt(2000, 4, 28).unwrap())
+                    .cast(DataType::Date)
+                    .alias("date"),
+                lit(0.04).alias("ret"),
+            ])
+            .collect()
+            .unwrap();
+        let msf = msf.vstack(&april).unwrap();
+        let params = Params {
+            frequency: Frequency::Quarterly,
+            ..synthetic_params(dir.path())
+        };
+
+        let matrices = make_monthly_matrices_from_df(msf, exchdates, &params).unwrap();
+
+        let Some(MatrixEnum::I32(dates)) = matrices.get(DATES_KEY) else {
+            panic!("dates is not an I32 matrix");
+        };
+        assert_eq!(dates, &ndarray::array![[200003], [200006]]);
+        let Some(MatrixEnum::F64(ret)) = matrices.get("ret_x_dl") else {
+            panic!("ret_x_dl is not an F64 matrix");
+        };
+        assert_eq!(ret.dim(), (2, 3));
+        assert!((ret[[0, 0]] - (1.01 * 1.02 * 0.99 - 1.0)).abs() < 1e-12);
+        assert!((ret[[0, 2]] - (1.10 * 0.95 - 1.0)).abs() < 1e-12);
+        assert!((ret[[1, 0]] - 0.04).abs() < 1e-12);
+        assert!(ret[[1, 1]].is_nan());
+        let Some(MatrixEnum::F64(prc)) = matrices.get("prc") else {
+            panic!("prc is not an F64 matrix");
+        };
+        assert_eq!(prc[[0, 0]], 10.1);
+        let Some(MatrixEnum::F64(vol)) = matrices.get("vol_x_adj") else {
+            panic!("vol_x_adj is not an F64 matrix");
+        };
+        assert_eq!(vol[[0, 1]], 630.0);
+    }
+
+    #[test]
+    fn test_share_and_exchange_code_filters() {
+        let dir = tempfile::tempdir().unwrap();
+        let (msf, mut exchdates) = synthetic_crsp_frames();
+        // 10003 is a REIT
+        exchdates
+            .replace("shrcd", Series::new("shrcd".into(), [10i16, 11, 18]))
+            .unwrap();
+        let permnos = |params: &Params| {
+            let matrices =
+                make_monthly_matrices_from_df(msf.clone(), exchdates.clone(), params).unwrap();
+            let MatrixEnum::I32(permno) = &matrices["permno"] else {
+                panic!("permno should be an integer matrix");
+            };
+            permno.iter().copied().collect::<Vec<i32>>()
+        };
+        let params = synthetic_params(dir.path());
+
+        assert_eq!(permnos(&params), vec![10001, 10002]);
+        let with_reits = Params {
+            share_codes: Some(vec![10, 11, 18]),
+            ..synthetic_params(dir.path())
+        };
+        assert_eq!(permnos(&with_reits), vec![10001, 10002, 10003]);
+        let all_codes = Params {
+            share_codes: None,
+            ..synthetic_params(dir.path())
+        };
+        assert_eq!(permnos(&all_codes), vec![10001, 10002, 10003]);
+        let nyse_amex_reits = Params {
+            exchange_codes: Some(vec![1, 2]),
+            ..with_reits
+        };
+        assert_eq!(permnos(&nyse_amex_reits), vec![10001, 10003]);
+    }
 }
@@ -0,0 +1,673 @@
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use memmap2::Mmap;
+use ndarray::{Array2, ArrayView2};
+use polars::prelude::*;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Name of the index file mapping variable names to their relative paths.
+pub const INDEX_FILE: &str = "index.json";
+
+/// Extension appended to the gzipped JSON files.
+const GZIP_EXTENSION: &str = "gz";
+
+/// Extension of the matrices saved as parquet.
+const PARQUET_EXTENSION: &str = "parquet";
+
+/// Extension of the matrices saved in the binary format of [`save_matrix_bin`].
+pub const BINARY_EXTENSION: &str = "bin";
+
+/// Size in bytes of the header of the binary matrices: the number of rows and of
+/// columns as little-endian `u64`.
+const BINARY_HEADER_LEN: usize = 16;
+
+/// File format of the saved matrices.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatrixFormat {
+    /// JSON serialization of the ndarray, readable by any tool but large and slow.
+    #[default]
+    Json,
+    /// Parquet file with one column per matrix column, named by its position (`"0"`,
+    /// `"1"`, ...), and one row per matrix row.
+    Parquet,
+}
+
+impl MatrixFormat {
+    /// Extension of the files in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MatrixFormat::Json => "json",
+            MatrixFormat::Parquet => PARQUET_EXTENSION,
+        }
+    }
+}
+
+/// How matrix outputs are laid out on disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputLayout {
+    /// Every matrix is written directly into the output directory.
+    #[default]
+    Flat,
+    /// Matrices are grouped into one subdirectory per [`MatrixCategory`], and a top-level
+    /// `index.json` maps each variable name to its relative path.
+    Categorized,
+}
+
+/// Category of a matrix, used as its subdirectory in the categorized layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixCategory {
+    /// Variables read directly from the CRSP files (prc, shrout, ...).
+    Raw,
+    /// Variables derived from the raw ones (ret, me, ...).
+    Derived,
+    /// Anomaly signals.
+    Signals,
+}
+
+impl MatrixCategory {
+    pub fn subdirectory(&self) -> &'static str {
+        match self {
+            MatrixCategory::Raw => "raw",
+            MatrixCategory::Derived => "derived",
+            MatrixCategory::Signals => "signals",
+        }
+    }
+}
+
+/// A monthly matrix of any of the element types produced from the CRSP files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatrixEnum {
+    I16(Array2<i16>),
+    I32(Array2<i32>),
+    I64(Array2<i64>),
+    F64(Array2<f64>),
+}
+
+impl MatrixEnum {
+    pub fn dim(&self) -> (usize, usize) {
+        match self {
+            MatrixEnum::I16(m) => m.dim(),
+            MatrixEnum::I32(m) => m.dim(),
+            MatrixEnum::I64(m) => m.dim(),
+            MatrixEnum::F64(m) => m.dim(),
+        }
+    }
+
+    /// Saves the matrix with [`save_matrix_json`].
+    pub fn save_json(
+        self,
+        dir: &Path,
+        var_name: &str,
+        category: MatrixCategory,
+        layout: OutputLayout,
+    ) -> Result<()> {
+        self.save(dir, var_name, category, layout, MatrixFormat::Json)
+    }
+
+    /// Saves the matrix with [`save_matrix_in_layout`].
+    pub fn save(
+        self,
+        dir: &Path,
+        var_name: &str,
+        category: MatrixCategory,
+        layout: OutputLayout,
+        format: MatrixFormat,
+    ) -> Result<()> {
+        match self {
+            MatrixEnum::I16(m) => save_matrix_in_layout(m, dir, var_name, category, layout, format),
+            MatrixEnum::I32(m) => save_matrix_in_layout(m, dir, var_name, category, layout, format),
+            MatrixEnum::I64(m) => save_matrix_in_layout(m, dir, var_name, category, layout, format),
+            MatrixEnum::F64(m) => save_matrix_in_layout(m, dir, var_name, category, layout, format),
+        }
+    }
+}
+
+/// Saves a matrix as `<var_name>.json` following the given layout. In the categorized
+/// layout the file goes into the category's subdirectory and is registered in the index.
+pub fn save_matrix_json<T: serde::Serialize + ParquetElement>(
+    ndarray: Array2<T>,
+    dir: &Path,
+    var_name: &str,
+    category: MatrixCategory,
+    layout: OutputLayout,
+) -> Result<()> {
+    save_matrix_in_layout(ndarray, dir, var_name, category, layout, MatrixFormat::Json)
+}
+
+/// Saves a matrix as `<var_name>.<extension of format>` following the given layout, see
+/// [`save_matrix_json`].
+pub fn save_matrix_in_layout<T: serde::Serialize + ParquetElement>(
+    ndarray: Array2<T>,
+    dir: &Path,
+    var_name: &str,
+    category: MatrixCategory,
+    layout: OutputLayout,
+    format: MatrixFormat,
+) -> Result<()> {
+    match layout {
+        OutputLayout::Flat => save_matrix(ndarray, dir, var_name, format),
+        OutputLayout::Categorized => {
+            let subdirectory = dir.join(category.subdirectory());
+            fs::create_dir_all(&subdirectory)?;
+            save_matrix(ndarray, &subdirectory, var_name, format)?;
+            register_in_index(
+                dir,
+                var_name,
+                &format!(
+                    "{}/{}.{}",
+                    category.subdirectory(),
+                    var_name,
+                    format.extension()
+                ),
+            )
+        }
+    }
+}
+
+/// Saves a matrix as `dir/<name>.json` or `dir/<name>.parquet` depending on `format`.
+/// [`load_array`] reads both.
+pub fn save_matrix<T: serde::Serialize + ParquetElement>(
+    ndarray: Array2<T>,
+    dir: &Path,
+    name: &str,
+    format: MatrixFormat,
+) -> Result<()> {
+    let filename = format!("{}.{}", name, format.extension());
+    match format {
+        MatrixFormat::Json => save_ndarray_as_json(ndarray, dir, &filename, false),
+        MatrixFormat::Parquet => {
+            let file_path = dir.join(&filename);
+            let columns = ndarray
+                .columns()
+                .into_iter()
+                .enumerate()
+                .map(|(j, column)| T::into_series(j.to_string().into(), column.to_vec()).into())
+                .collect();
+            let mut df = DataFrame::new(columns)?;
+            let file = File::create(&file_path)
+                .with_context(|| format!("Failed to create matrix file: {:?}", file_path))?;
+            ParquetWriter::new(BufWriter::new(file)).finish(&mut df)?;
+            println!("Saved matrix for {}.", filename);
+            Ok(())
+        }
+    }
+}
+
+/// Saves a matrix as JSON in `dir/filename`. With `compress`, the JSON is gzipped and
+/// written to `dir/<filename>.gz` instead; [`load_array`] reads both.
+pub fn save_ndarray_as_json<T: serde::Serialize>(
+    ndarray: Array2<T>,
+    dir: &Path,
+    filename: &str,
+    compress: bool,
+) -> Result<()> {
+    let json = serde_json::to_string(&ndarray)?;
+    let filename = if compress {
+        format!("{}.{}", filename, GZIP_EXTENSION)
+    } else {
+        filename.to_string()
+    };
+    let file_path = dir.join(&filename);
+    let write = || -> std::io::Result<()> {
+        let file = File::create(&file_path)?;
+        if compress {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            encoder.write_all(json.as_bytes())?;
+            encoder.finish()?;
+            Ok(())
+        } else {
+            BufWriter::new(file).write_all(json.as_bytes())
+        }
+    };
+    write().with_context(|| format!("Failed to write ndarray to file: {:?}", file_path))?;
+    println!("Saved matrix for {}.", filename);
+    Ok(())
+}
+
+/// Saves a float matrix as CSV, one line per row and without a header, writing each
+/// value with `float_precision` decimal places. Rows are streamed to the file so the
+/// text of the whole matrix is never held in memory. Missing values are left empty.
+pub fn save_matrix_csv(
+    ndarray: &Array2<f64>,
+    dir: &Path,
+    filename: &str,
+    float_precision: usize,
+) -> Result<()> {
+    let file_path = dir.join(filename);
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&file_path)?);
+        for row in ndarray.rows() {
+            for (j, x) in row.iter().enumerate() {
+                if j > 0 {
+                    writer.write_all(b",")?;
+                }
+                if x.is_finite() {
+                    write!(writer, "{:.*}", float_precision, x)?;
+                }
+            }
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    };
+    write().with_context(|| format!("Failed to write matrix to file: {:?}", file_path))?;
+    println!("Saved matrix for {}.", filename);
+    Ok(())
+}
+
+/// Loads a matrix saved as JSON or parquet. If `dir` holds an `index.json` listing the
+/// variable (the file name without extension), the indexed path is used; otherwise the
+/// file is read directly from `dir`. The format follows the extension: files with a `.gz`
+/// extension are decompressed JSON, and `.parquet` files are read as saved by
+/// [`save_matrix`]. When the file does not exist, a gzipped copy `<file_name>.gz` or a
+/// parquet file of the same variable is used instead.
+pub fn load_array<T>(dir: &Path, file_name: &str) -> Result<Array2<T>>
+where
+    T: JsonElement + ParquetElement + std::fmt::Debug,
+{
+    let plain_name = file_name
+        .strip_suffix(&format!(".{}", GZIP_EXTENSION))
+        .unwrap_or(file_name);
+    let var_name = plain_name.strip_suffix(".json").unwrap_or(plain_name);
+    let var_name = var_name
+        .strip_suffix(&format!(".{}", PARQUET_EXTENSION))
+        .unwrap_or(var_name);
+    let mut file_path = match read_index(dir)?.get(var_name) {
+        Some(relative_path) => dir.join(relative_path),
+        None => dir.join(file_name),
+    };
+    if !file_path.exists() {
+        let mut gzipped = file_path.clone().into_os_string();
+        gzipped.push(format!(".{}", GZIP_EXTENSION));
+        let gzipped = PathBuf::from(gzipped);
+        let parquet = file_path.with_file_name(format!("{}.{}", var_name, PARQUET_EXTENSION));
+        if gzipped.exists() {
+            file_path = gzipped;
+        } else if parquet.exists() {
+            file_path = parquet;
+        }
+    }
+    if file_path
+        .extension()
+        .is_some_and(|ext| ext == PARQUET_EXTENSION)
+    {
+        return load_parquet_matrix(&file_path);
+    }
+    let file = File::open(&file_path)
+        .with_context(|| format!("Failed to open matrix file: {:?}", file_path))?;
+    let mut json = String::new();
+    if file_path
+        .extension()
+        .is_some_and(|ext| ext == GZIP_EXTENSION)
+    {
+        GzDecoder::new(file).read_to_string(&mut json)?;
+    } else {
+        BufReader::new(file).read_to_string(&mut json)?;
+    }
+    // Deserialize JSON to Array2<T>, where missing values are stored as null
+    let data: Array2<Option<T>> = serde_json::from_str(&json)?;
+    let dim = data.dim();
+    data.into_iter()
+        .map(|x| x.or_else(T::missing))
+        .collect::<Option<Vec<T>>>()
+        .and_then(|values| Array2::from_shape_vec(dim, values).ok())
+        .with_context(|| format!("Matrix file {:?} has null entries", file_path))
+}
+
+/// Reads a matrix saved as parquet by [`save_matrix`].
+fn load_parquet_matrix<T: ParquetElement>(file_path: &Path) -> Result<Array2<T>> {
+    let file = File::open(file_path)
+        .with_context(|| format!("Failed to open matrix file: {:?}", file_path))?;
+    let df = ParquetReader::new(file).finish()?;
+    let (n_rows, n_cols) = (df.height(), df.width());
+    let mut values = Vec::with_capacity(n_rows * n_cols);
+    for column in df.get_columns() {
+        values.extend(
+            T::from_column(column)
+                .with_context(|| format!("Invalid matrix file {:?}", file_path))?,
+        );
+    }
+    // Values are stored column by column
+    Ok(Array2::from_shape_vec((n_cols, n_rows), values)?.reversed_axes())
+}
+
+/// Element type of a matrix stored as parquet.
+pub trait ParquetElement: Clone + Sized {
+    /// Column of values.
+    fn into_series(name: PlSmallStr, values: Vec<Self>) -> Series;
+    /// Values of a column, an error if it has another type or has nulls.
+    fn from_column(column: &Column) -> Result<Vec<Self>>;
+}
+
+macro_rules! impl_parquet_element {
+    ($($t:ty => $accessor:ident),*) => {
+        $(impl ParquetElement for $t {
+            fn into_series(name: PlSmallStr, values: Vec<Self>) -> Series {
+                Series::new(name, values)
+            }
+
+            fn from_column(column: &Column) -> Result<Vec<Self>> {
+                column
+                    .$accessor()?
+                    .into_iter()
+                    .map(|x| x.with_context(|| format!("Column {} has nulls", column.name())))
+                    .collect()
+            }
+        })*
+    };
+}
+
+impl_parquet_element!(i16 => i16, i32 => i32, i64 => i64, f64 => f64);
+
+/// Element type of a matrix stored as JSON.
+///
+/// JSON has no NaN, so serde writes NaN floats as `null`; this maps them back when
+/// loading. Integer matrices have no missing value and cannot contain nulls.
+pub trait JsonElement: DeserializeOwned {
+    /// Value of a `null` entry, if the type has one.
+    fn missing() -> Option<Self>;
+}
+
+impl JsonElement for f64 {
+    fn missing() -> Option<Self> {
+        Some(f64::NAN)
+    }
+}
+
+macro_rules! impl_json_element_without_missing {
+    ($($t:ty),*) => {
+        $(impl JsonElement for $t {
+            fn missing() -> Option<Self> {
+                None
+            }
+        })*
+    };
+}
+
+impl_json_element_without_missing!(i16, i32, i64);
+
+/// Saves a float matrix in a binary format that [`load_array_mmap`] maps without reading
+/// it: a header with the number of rows and columns as little-endian `u64`, followed by
+/// the values in row-major order as little-endian `f64`.
+pub fn save_matrix_bin(ndarray: &Array2<f64>, file_path: &Path) -> Result<()> {
+    let file = File::create(file_path)
+        .with_context(|| format!("Failed to create matrix file: {:?}", file_path))?;
+    let mut writer = BufWriter::new(file);
+    let (n_rows, n_cols) = ndarray.dim();
+    writer.write_all(&(n_rows as u64).to_le_bytes())?;
+    writer.write_all(&(n_cols as u64).to_le_bytes())?;
+    for value in ndarray.iter() {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A binary matrix mapped in memory by [`load_array_mmap`]. Pages are read from disk as
+/// the view touches them, so computations going over the rows never hold the whole
+/// matrix in memory.
+pub struct MappedMatrix {
+    mmap: Mmap,
+    shape: (usize, usize),
+}
+
+impl MappedMatrix {
+    /// The nRows x nCols view of the values.
+    pub fn view(&self) -> ArrayView2<'_, f64> {
+        // SAFETY: the length and alignment of the values were checked when mapping
+        let (_, values, _) = unsafe { self.mmap[BINARY_HEADER_LEN..].align_to::<f64>() };
+        ArrayView2::from_shape(self.shape, values).expect("shape checked when mapping")
+    }
+}
+
+/// Maps a matrix saved by [`save_matrix_bin`] into memory.
+pub fn load_array_mmap(file_path: &Path) -> Result<MappedMatrix> {
+    if cfg!(target_endian = "big") {
+        return Err(anyhow!(
+            "Binary matrices can only be mapped on little-endian targets"
+        ));
+    }
+    let file = File::open(file_path)
+        .with_context(|| format!("Failed to open matrix file: {:?}", file_path))?;
+    // SAFETY: the matrix files are written once and not modified while mapped
+    let mmap = unsafe { Mmap::map(&file) }
+        .with_context(|| format!("Failed to map matrix file: {:?}", file_path))?;
+    if mmap.len() < BINARY_HEADER_LEN {
+        return Err(anyhow!("{:?} is too short for a matrix header", file_path));
+    }
+    let dimension = |offset: usize| {
+        let bytes: [u8; 8] = mmap[offset..offset + 8].try_into().unwrap();
+        u64::from_le_bytes(bytes) as usize
+    };
+    let shape = (dimension(0), dimension(8));
+    let expected_len = shape
+        .0
+        .checked_mul(shape.1)
+        .and_then(|n| n.checked_mul(size_of::<f64>()))
+        .and_then(|n| n.checked_add(BINARY_HEADER_LEN));
+    if expected_len != Some(mmap.len()) {
+        return Err(anyhow!(
+            "{:?} has {} bytes, which does not match a {} x {} matrix",
+            file_path,
+            mmap.len(),
+            shape.0,
+            shape.1
+        ));
+    }
+    // SAFETY: any bit pattern is a valid f64
+    let (prefix, _, _) = unsafe { mmap[BINARY_HEADER_LEN..].align_to::<f64>() };
+    if !prefix.is_empty() {
+        return Err(anyhow!("The values of {:?} are not aligned", file_path));
+    }
+    Ok(MappedMatrix { mmap, shape })
+}
+
+/// Converts the float matrix `<var_name>` of `dir`, saved as JSON or in any format read
+/// by [`load_array`], to `dir/<var_name>.bin` for [`load_array_mmap`], and returns the
+/// path of the binary file. The matrix is read in full once.
+pub fn convert_json_matrix_to_bin(dir: &Path, var_name: &str) -> Result<PathBuf> {
+    let matrix: Array2<f64> = load_array(dir, &format!("{}.json", var_name))?;
+    let file_path = dir.join(format!("{}.{}", var_name, BINARY_EXTENSION));
+    save_matrix_bin(&matrix, &file_path)?;
+    Ok(file_path)
+}
+
+/// Reads the index of `dir`, empty if there is none.
+pub fn read_index(dir: &Path) -> Result<BTreeMap<String, String>> {
+    let index_path = dir.join(INDEX_FILE);
+    if !index_path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let json = fs::read_to_string(&index_path)
+        .with_context(|| format!("Failed to read index file: {:?}", index_path))?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+fn register_in_index(dir: &Path, var_name: &str, relative_path: &str) -> Result<()> {
+    let mut index = read_index(dir)?;
+    index.insert(var_name.to_string(), relative_path.to_string());
+    let index_path = dir.join(INDEX_FILE);
+    fs::write(&index_path, serde_json::to_string_pretty(&index)?)
+        .with_context(|| format!("Failed to write index file: {:?}", index_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_categorized_layout_loads_through_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let prc = array![[10.0, 20.5], [11.0, 12.0]];
+        let me = array![[1.0, 2.0]];
+
+        save_matrix_json(
+            prc.clone(),
+            dir.path(),
+            "prc",
+            MatrixCategory::Raw,
+            OutputLayout::Categorized,
+        )
+        .unwrap();
+        save_matrix_json(
+            me,
+            dir.path(),
+            "me",
+            MatrixCategory::Derived,
+            OutputLayout::Categorized,
+        )
+        .unwrap();
+
+        let index = read_index(dir.path()).unwrap();
+        assert_eq!(index["prc"], "raw/prc.json");
+        assert_eq!(index["me"], "derived/me.json");
+        assert!(!dir.path().join("prc.json").exists());
+
+        let loaded: Array2<f64> = load_array(dir.path(), "prc.json").unwrap();
+        assert_eq!(loaded, prc);
+    }
+
+    #[test]
+    fn test_flat_layout_has_no_index() {
+        let dir = tempfile::tempdir().unwrap();
+
+        save_matrix_json(
+            array![[1i16, 2]],
+            dir.path(),
+            "shrcd",
+            MatrixCategory::Raw,
+            OutputLayout::Flat,
+        )
+        .unwrap();
+
+        assert!(!dir.path().join(INDEX_FILE).exists());
+        let loaded: Array2<i16> = load_array(dir.path(), "shrcd.json").unwrap();
+        assert_eq!(loaded, array![[1i16, 2]]);
+    }
+
+    #[test]
+    fn test_nan_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let prc = array![[10.0, f64::NAN], [f64::NAN, 5.0]];
+
+        save_ndarray_as_json(prc, dir.path(), "prc.json", false).unwrap();
+        let loaded: Array2<f64> = load_array(dir.path(), "prc.json").unwrap();
+        assert_eq!(loaded[[0, 0]], 10.0);
+        assert!(loaded[[0, 1]].is_nan());
+        assert!(loaded[[1, 0]].is_nan());
+        assert_eq!(loaded[[1, 1]], 5.0);
+
+        fs::write(
+            dir.path().join("exchcd.json"),
+            r#"{"v":1,"dim":[1,2],"data":[1,null]}"#,
+        )
+        .unwrap();
+        assert!(load_array::<i16>(dir.path(), "exchcd.json").is_err());
+    }
+
+    #[test]
+    fn test_gzip_json_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let ret = Array2::from_shape_fn((120, 50), |(t, j)| ((t * j) % 7) as f64 / 100.0);
+
+        save_ndarray_as_json(ret.clone(), dir.path(), "ret.json", false).unwrap();
+        save_ndarray_as_json(ret.clone(), dir.path(), "ret_gz.json", true).unwrap();
+
+        let plain_size = fs::metadata(dir.path().join("ret.json")).unwrap().len();
+        let gzip_size = fs::metadata(dir.path().join("ret_gz.json.gz"))
+            .unwrap()
+            .len();
+        assert!(gzip_size < plain_size);
+        assert!(!dir.path().join("ret_gz.json").exists());
+
+        let explicit: Array2<f64> = load_array(dir.path(), "ret_gz.json.gz").unwrap();
+        let implicit: Array2<f64> = load_array(dir.path(), "ret_gz.json").unwrap();
+        assert_eq!(explicit, ret);
+        assert_eq!(implicit, ret);
+    }
+
+    #[test]
+    fn test_binary_matrix_is_mapped() {
+        let dir = tempfile::tempdir().unwrap();
+        let ret = array![[0.01, f64::NAN, -0.02], [0.03, 0.04, 0.05]];
+        save_ndarray_as_json(ret.clone(), dir.path(), "ret.json", false).unwrap();
+
+        let path = convert_json_matrix_to_bin(dir.path(), "ret").unwrap();
+        assert_eq!(path, dir.path().join("ret.bin"));
+        let mapped = load_array_mmap(&path).unwrap();
+        let view = mapped.view();
+        assert_eq!(view.dim(), (2, 3));
+        assert!(view[[0, 1]].is_nan());
+        assert_eq!(view.row(1), ret.row(1));
+
+        // A file cut short does not map
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 8]).unwrap();
+        assert!(load_array_mmap(&path).is_err());
+    }
+
+    #[test]
+    fn test_parquet_matrix_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let ret = array![[0.01, f64::NAN, -0.02], [0.03, 0.04, 0.05]];
+        let exchcd = array![[1i16, 3], [2, 1], [3, 3]];
+
+        save_matrix(ret.clone(), dir.path(), "ret", MatrixFormat::Parquet).unwrap();
+        save_matrix(exchcd.clone(), dir.path(), "exchcd", MatrixFormat::Parquet).unwrap();
+        save_matrix(exchcd.clone(), dir.path(), "shrcd", MatrixFormat::Json).unwrap();
+
+        assert!(dir.path().join("ret.parquet").exists());
+        let loaded: Array2<f64> = load_array(dir.path(), "ret.parquet").unwrap();
+        assert_eq!(loaded.dim(), (2, 3));
+        assert!(loaded[[0, 1]].is_nan());
+        assert_eq!(loaded[[1, 2]], 0.05);
+        // The JSON name falls back to the parquet file of the same variable
+        let by_json_name: Array2<i16> = load_array(dir.path(), "exchcd.json").unwrap();
+        assert_eq!(by_json_name, exchcd);
+        let json: Array2<i16> = load_array(dir.path(), "shrcd.json").unwrap();
+        assert_eq!(json, exchcd);
+
+        save_matrix_in_layout(
+            ret.clone(),
+            dir.path(),
+            "me",
+            MatrixCategory::Derived,
+            OutputLayout::Categorized,
+            MatrixFormat::Parquet,
+        )
+        .unwrap();
+        assert_eq!(read_index(dir.path()).unwrap()["me"], "derived/me.parquet");
+        let me: Array2<f64> = load_array(dir.path(), "me.json").unwrap();
+        assert_eq!(me.dim(), (2, 3));
+    }
+
+    #[test]
+    fn test_save_matrix_csv_respects_precision() {
+        let dir = tempfile::tempdir().unwrap();
+        let ret = array![[0.0123456789, -0.5], [f64::NAN, 1.0 / 3.0]];
+
+        save_matrix_csv(&ret, dir.path(), "ret.csv", 4).unwrap();
+
+        let csv = fs::read_to_string(dir.path().join("ret.csv")).unwrap();
+        assert_eq!(csv, "0.0123,-0.5000\n,0.3333\n");
+        let parsed: Vec<Vec<f64>> = csv
+            .lines()
+            .map(|line| {
+                line.split(',')
+                    .map(|v| v.parse().unwrap_or(f64::NAN))
+                    .collect()
+            })
+            .collect();
+        for (row, original) in parsed.iter().zip(ret.rows()) {
+            for (x, y) in row.iter().zip(original) {
+                assert!((x.is_nan() && y.is_nan()) || (x - y).abs() <= 0.5e-4);
+            }
+        }
+    }
+}
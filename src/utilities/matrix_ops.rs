@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Result};
+use ndarray::{s, Array2, Zip};
+use polars::prelude::*;
+use std::collections::HashMap;
+
+/// Converts simple returns to continuously-compounded (log) returns, `ln(1 + ret)`.
+///
+/// Returns of -100% or below have no log counterpart and are set to NaN, as are
+/// missing returns.
+pub fn to_log_returns(ret: &Array2<f64>) -> Array2<f64> {
+    ret.mapv(|r| if r > -1.0 { r.ln_1p() } else { f64::NAN })
+}
+
+/// Converts log returns back to simple returns, `exp(log_ret) - 1`.
+pub fn from_log_returns(log_ret: &Array2<f64>) -> Array2<f64> {
+    log_ret.mapv(f64::exp_m1)
+}
+
+/// Lags a matrix by `k` months: every column is shifted down by `k` rows, so that row t
+/// holds the values of month t-k, and the first `k` rows are NaN. Columns keep their
+/// permno.
+pub fn lag_matrix(m: &Array2<f64>, k: usize) -> Array2<f64> {
+    let mut lagged = Array2::from_elem(m.dim(), f64::NAN);
+    if k < m.nrows() {
+        lagged
+            .slice_mut(s![k.., ..])
+            .assign(&m.slice(s![..m.nrows() - k, ..]));
+    }
+    lagged
+}
+
+/// Combines a primary and a fallback source of the same variable (e.g. the CRSP return
+/// and a return reconstructed from prices): each cell takes the primary value unless it
+/// is NaN, and the fallback value otherwise.
+pub fn coalesce_matrices(primary: &Array2<f64>, fallback: &Array2<f64>) -> Array2<f64> {
+    assert_eq!(
+        primary.dim(),
+        fallback.dim(),
+        "Primary and fallback matrices must have the same dimensions"
+    );
+    Zip::from(primary)
+        .and(fallback)
+        .map_collect(|&p, &f| if p.is_nan() { f } else { p })
+}
+
+/// Converts a DataFrame of numeric columns into an `f64` matrix, with nulls as NaN.
+///
+/// Unlike `DataFrame::to_ndarray`, nulls are converted explicitly whatever the column
+/// dtype, and a non-numeric column yields an error naming the offending column instead
+/// of a Polars cast error.
+pub fn df_to_array_f64(df: &DataFrame) -> Result<Array2<f64>> {
+    let mut array = Array2::from_elem((df.height(), df.width()), f64::NAN);
+    for (j, column) in df.get_columns().iter().enumerate() {
+        if !column.dtype().is_numeric() {
+            return Err(anyhow!(
+                "Column {} has non-numeric type {} and cannot be converted to f64",
+                column.name(),
+                column.dtype()
+            ));
+        }
+        let values = column.cast(&DataType::Float64)?;
+        for (i, value) in values.f64()?.into_iter().enumerate() {
+            if let Some(value) = value {
+                array[[i, j]] = value;
+            }
+        }
+    }
+    Ok(array)
+}
+
+/// Shifts a `YYYYMM` date by `k` months (negative `k` goes back in time).
+pub fn add_months(yyyymm: i32, k: i32) -> i32 {
+    let index = (yyyymm / 100) * 12 + (yyyymm % 100 - 1) + k;
+    (index.div_euclid(12)) * 100 + index.rem_euclid(12) + 1
+}
+
+/// Returns every `YYYYMM` month between `start` and `end`, both included.
+pub fn month_range(start: i32, end: i32) -> Vec<i32> {
+    let mut months = Vec::new();
+    let mut month = start;
+    while month <= end {
+        months.push(month);
+        month = add_months(month, 1);
+    }
+    months
+}
+
+/// Returns the months between `start` and `end` that are absent from `dates`.
+/// An empty result means the dates form a contiguous monthly grid over the range.
+pub fn missing_months(dates: &Array2<i32>, start: i32, end: i32) -> Vec<i32> {
+    month_range(start, end)
+        .into_iter()
+        .filter(|month| !dates.iter().any(|d| d == month))
+        .collect()
+}
+
+/// Reindexes a matrix onto the full monthly calendar between `start` and `end`.
+///
+/// Rows are matched to months through `dates` (`YYYYMM`, one per row of `m`). Months
+/// absent from `dates` are inserted as all-NaN rows and rows outside the range are
+/// dropped, so the output is sorted and contiguous, which lag/lead operations rely on.
+///
+/// # Returns
+///
+/// * `(Array2<f64>, Array2<i32>)` - The reindexed matrix and its dates vector (nMonths x 1).
+pub fn reindex_to_full_calendar(
+    m: &Array2<f64>,
+    dates: &Array2<i32>,
+    start: i32,
+    end: i32,
+) -> (Array2<f64>, Array2<i32>) {
+    assert_eq!(
+        m.nrows(),
+        dates.len(),
+        "The matrix must have one row per date"
+    );
+    let calendar = month_range(start, end);
+    let position: HashMap<i32, usize> = calendar
+        .iter()
+        .enumerate()
+        .map(|(i, &month)| (month, i))
+        .collect();
+
+    let mut reindexed = Array2::from_elem((calendar.len(), m.ncols()), f64::NAN);
+    for (row, date) in dates.iter().enumerate() {
+        if let Some(&i) = position.get(date) {
+            reindexed.row_mut(i).assign(&m.row(row));
+        }
+    }
+    let calendar_dates = Array2::from_shape_vec((calendar.len(), 1), calendar)
+        .expect("Calendar length matches its shape");
+    (reindexed, calendar_dates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_lag_matrix_shifts_rows_down() {
+        let m = array![[1.0, 2.0], [3.0, 4.0], [5.0, 6.0]];
+
+        let lagged = lag_matrix(&m, 1);
+
+        assert!(lagged.row(0).iter().all(|x| x.is_nan()));
+        for t in 1..3 {
+            assert_eq!(lagged.row(t), m.row(t - 1));
+        }
+        assert_eq!(lag_matrix(&m, 0), m);
+        assert!(lag_matrix(&m, 5).iter().all(|x| x.is_nan()));
+    }
+
+    #[test]
+    fn test_coalesce_matrices_fills_missing_primary() {
+        let nan = f64::NAN;
+        let primary = array![[0.01, nan], [nan, 0.04]];
+        let fallback = array![[0.02, 0.03], [nan, 0.05]];
+
+        let merged = coalesce_matrices(&primary, &fallback);
+
+        assert_eq!(merged[[0, 0]], 0.01);
+        assert_eq!(merged[[0, 1]], 0.03);
+        assert!(merged[[1, 0]].is_nan());
+        assert_eq!(merged[[1, 1]], 0.04);
+    }
+
+    #[test]
+    fn test_log_returns_round_trip() {
+        let ret = array![[0.05, -0.5, 0.0], [1.2, f64::NAN, -0.999]];
+
+        let recovered = from_log_returns(&to_log_returns(&ret));
+
+        for (r, back) in ret.iter().zip(recovered.iter()) {
+            if r.is_nan() {
+                assert!(back.is_nan());
+            } else {
+                assert!((r - back).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_log_returns_total_loss_is_nan() {
+        let ret = array![[-1.0, -1.5, 0.1]];
+
+        let log_ret = to_log_returns(&ret);
+
+        assert!(log_ret[[0, 0]].is_nan());
+        assert!(log_ret[[0, 1]].is_nan());
+        assert!((log_ret[[0, 2]] - 1.1f64.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_add_months_crosses_years() {
+        assert_eq!(add_months(200012, 1), 200101);
+        assert_eq!(add_months(200101, -1), 200012);
+        assert_eq!(add_months(200006, 18), 200112);
+        assert_eq!(
+            month_range(200011, 200102),
+            vec![200011, 200012, 200101, 200102]
+        );
+    }
+
+    #[test]
+    fn test_reindex_inserts_missing_interior_month() {
+        let m = array![[1.0, 2.0], [5.0, 6.0]];
+        let dates = array![[200011], [200101]];
+        assert_eq!(missing_months(&dates, 200011, 200101), vec![200012]);
+
+        let (full, full_dates) = reindex_to_full_calendar(&m, &dates, 200011, 200101);
+
+        assert_eq!(full_dates, array![[200011], [200012], [200101]]);
+        assert_eq!(full.row(0), m.row(0));
+        assert!(full.row(1).iter().all(|x| x.is_nan()));
+        assert_eq!(full.row(2), m.row(1));
+        assert!(missing_months(&full_dates, 200011, 200101).is_empty());
+    }
+
+    #[test]
+    fn test_df_to_array_f64_nulls_become_nan() {
+        let df = df![
+            "10001" => [Some(1.5), None, Some(3.0)],
+            "10002" => [Some(2i32), Some(4), None],
+        ]
+        .unwrap();
+
+        let array = df_to_array_f64(&df).unwrap();
+
+        assert_eq!(array.dim(), (3, 2));
+        assert_eq!(array[[0, 0]], 1.5);
+        assert!(array[[1, 0]].is_nan());
+        assert_eq!(array[[1, 1]], 4.0);
+        assert!(array[[2, 1]].is_nan());
+    }
+
+    #[test]
+    fn test_df_to_array_f64_rejects_non_numeric() {
+        let df = df!["10001" => [1.0, 2.0], "ticker" => ["IBM", "AAPL"]].unwrap();
+
+        let err = df_to_array_f64(&df).unwrap_err();
+
+        assert!(err.to_string().contains("ticker"));
+    }
+}
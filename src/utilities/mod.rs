@@ -1,3 +1,13 @@
+pub mod ccm_link;
+pub mod crsp_matrices;
+pub mod data_quality;
 pub mod get_crsp_data;
+pub mod logging;
 pub mod make_crsp_derived_variables;
 pub mod make_crsp_monthly_data;
+pub mod matrix_io;
+pub mod matrix_ops;
+pub mod point_in_time;
+pub mod risk_free;
+pub mod sqlite_db;
+pub mod timings;
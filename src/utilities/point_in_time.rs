@@ -0,0 +1,107 @@
+use super::matrix_ops::add_months;
+use chrono::{Datelike, NaiveDate};
+use ndarray::Array2;
+
+/// Builds point-in-time fundamentals: at each formation date, the latest value that had
+/// been reported by then.
+///
+/// A value for the fiscal period ending in month `datadate` is treated as reported at the
+/// end of month `datadate + reporting_lag`, and never before, so a formation at the end of
+/// month t only sees periods with `datadate + reporting_lag <= t`. Values are carried
+/// forward until a later period becomes available.
+///
+/// With `as_of`, the data is frozen at that date: formations after it only see what had
+/// been reported by `as_of`, which keeps later restatements and late filings out of a
+/// backtest run on a snapshot. A month counts as reported by `as_of` once it has ended.
+///
+/// # Arguments
+///
+/// * `values` - Fundamentals (nMonths x nStocks), on the row of their `datadate` month and
+///   NaN elsewhere.
+/// * `dates` - Dates vector in `YYYYMM` format (nMonths x 1), sorted.
+/// * `reporting_lag` - Months between the end of the fiscal period and the report.
+/// * `as_of` - Optional date at which the available information is frozen.
+pub fn point_in_time_fundamentals(
+    values: &Array2<f64>,
+    dates: &Array2<i32>,
+    reporting_lag: u32,
+    as_of: Option<NaiveDate>,
+) -> Array2<f64> {
+    assert_eq!(
+        values.nrows(),
+        dates.len(),
+        "Fundamentals must have one row per date"
+    );
+    let dates: Vec<i32> = dates.iter().copied().collect();
+    let as_of_month = as_of.map(last_complete_month);
+    let mut pit = Array2::from_elem(values.dim(), f64::NAN);
+    for j in 0..values.ncols() {
+        // Dates are sorted, so periods become available in row order
+        let mut next_report = 0;
+        let mut latest = f64::NAN;
+        for (t, &date) in dates.iter().enumerate() {
+            let cutoff = as_of_month.map_or(date, |m| m.min(date));
+            while next_report < dates.len()
+                && add_months(dates[next_report], reporting_lag as i32) <= cutoff
+            {
+                let value = values[[next_report, j]];
+                if value.is_finite() {
+                    latest = value;
+                }
+                next_report += 1;
+            }
+            pit[[t, j]] = latest;
+        }
+    }
+    pit
+}
+
+/// Last month, in `YYYYMM` format, that has fully ended by `date`.
+fn last_complete_month(date: NaiveDate) -> i32 {
+    let month = date.year() * 100 + date.month() as i32;
+    let next_day = date.succ_opt().expect("Date is within the supported range");
+    if next_day.month() != date.month() {
+        month
+    } else {
+        add_months(month, -1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::matrix_ops::month_range;
+
+    #[test]
+    fn test_late_report_excluded_from_earlier_formation() {
+        let months = month_range(199912, 200112);
+        let row = |d: i32| months.iter().position(|&x| x == d).unwrap();
+        let dates = Array2::from_shape_vec((months.len(), 1), months.clone()).unwrap();
+        let mut be = Array2::from_elem((months.len(), 1), f64::NAN);
+        be[[row(199912), 0]] = 10.0;
+        be[[row(200012), 0]] = 20.0;
+
+        let pit = point_in_time_fundamentals(&be, &dates, 6, None);
+
+        assert!(pit[[row(200005), 0]].is_nan());
+        assert_eq!(pit[[row(200006), 0]], 10.0);
+        // The fiscal year 2000 report only arrives at the end of June 2001
+        assert_eq!(pit[[row(200105), 0]], 10.0);
+        assert_eq!(pit[[row(200106), 0]], 20.0);
+
+        // Frozen in March 2001, the fiscal year 2000 report is never seen
+        let as_of = NaiveDate::from_ymd_opt(2001, 3, 15);
+        let frozen = point_in_time_fundamentals(&be, &dates, 6, as_of);
+        assert!(frozen[[row(200005), 0]].is_nan());
+        assert_eq!(frozen[[row(200106), 0]], 10.0);
+        assert_eq!(frozen[[row(200112), 0]], 10.0);
+    }
+
+    #[test]
+    fn test_last_complete_month() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        assert_eq!(last_complete_month(date(2001, 6, 30)), 200106);
+        assert_eq!(last_complete_month(date(2001, 6, 29)), 200105);
+        assert_eq!(last_complete_month(date(2001, 1, 1)), 200012);
+    }
+}
@@ -0,0 +1,150 @@
+use super::make_crsp_monthly_data::load_parquet;
+use anyhow::{anyhow, Context, Result};
+use ndarray::{Array1, Array2};
+use polars::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Source of the monthly risk-free rate.
+///
+/// Every source is normalized to monthly decimal returns keyed by `YYYYMM` and aligned
+/// to the dates vector of the matrices through [`RiskFree::series`].
+#[derive(Debug, Clone)]
+pub enum RiskFree {
+    /// The `RF` column of a Fama-French factors CSV (e.g. `F-F_Research_Data_Factors.CSV`),
+    /// reported in percent.
+    FamaFrench(PathBuf),
+    /// A user-supplied series of monthly rates in decimals.
+    UserSupplied { dates: Vec<i32>, rates: Vec<f64> },
+    /// The 30-day T-bill return (`t30ret`) from the CRSP treasury file `crsp_mcti.parquet`,
+    /// dated by `caldt`.
+    CrspTreasury(PathBuf),
+}
+
+impl RiskFree {
+    /// A constant monthly rate over the given dates.
+    pub fn constant(dates: &Array2<i32>, rate: f64) -> Self {
+        RiskFree::UserSupplied {
+            dates: dates.iter().copied().collect(),
+            rates: vec![rate; dates.len()],
+        }
+    }
+
+    /// Returns the risk-free rate for each date in `dates` (`YYYYMM`), NaN for months the
+    /// source does not cover.
+    pub fn series(&self, dates: &Array2<i32>) -> Result<Array1<f64>> {
+        let rates = self.load()?;
+        Ok(dates
+            .iter()
+            .map(|d| rates.get(d).copied().unwrap_or(f64::NAN))
+            .collect())
+    }
+
+    fn load(&self) -> Result<HashMap<i32, f64>> {
+        match self {
+            RiskFree::FamaFrench(path) => {
+                let content = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read Fama-French file: {:?}", path))?;
+                parse_ff_risk_free(&content)
+            }
+            RiskFree::UserSupplied { dates, rates } => {
+                if dates.len() != rates.len() {
+                    return Err(anyhow!(
+                        "Risk-free series has {} dates but {} rates",
+                        dates.len(),
+                        rates.len()
+                    ));
+                }
+                Ok(dates.iter().copied().zip(rates.iter().copied()).collect())
+            }
+            RiskFree::CrspTreasury(path) => {
+                let df = load_parquet(path)?
+                    .select([
+                        col("caldt")
+                            .dt()
+                            .to_string("%Y%m")
+                            .cast(DataType::Int32)
+                            .alias("date"),
+                        col("t30ret").cast(DataType::Float64),
+                    ])
+                    .collect()
+                    .context("Failed to read the CRSP treasury file.")?;
+                let dates = df.column("date")?.i32()?;
+                let rates = df.column("t30ret")?.f64()?;
+                Ok(dates
+                    .into_iter()
+                    .zip(rates)
+                    .filter_map(|(d, r)| Some((d?, r?)))
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Parses the monthly block of a Fama-French factors CSV and returns its `RF` column in
+/// decimals. The monthly block starts at the first header line containing `RF` and ends
+/// at the first line that does not start with a `YYYYMM` date (the annual block follows).
+fn parse_ff_risk_free(content: &str) -> Result<HashMap<i32, f64>> {
+    let mut lines = content.lines();
+    let rf_idx = lines
+        .by_ref()
+        .find_map(|line| line.split(',').position(|field| field.trim() == "RF"))
+        .ok_or_else(|| anyhow!("No RF column found in the Fama-French file"))?;
+
+    let mut rates = HashMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let date = match fields[0].parse::<i32>() {
+            Ok(date) if fields[0].len() == 6 => date,
+            _ => break,
+        };
+        let rf: f64 = fields
+            .get(rf_idx)
+            .ok_or_else(|| anyhow!("Missing RF value for {}", date))?
+            .parse()
+            .with_context(|| format!("Invalid RF value for {}", date))?;
+        rates.insert(date, rf / 100.0);
+    }
+    Ok(rates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_parse_ff_risk_free() {
+        let content = "This file was created using the 202412 CRSP database.\n\
+                       \n\
+                       ,Mkt-RF,SMB,HML,RF\n\
+                       192607,    2.89,   -2.55,   -2.39,    0.22\n\
+                       192608,    2.64,   -1.14,    3.81,    0.25\n\
+                       \n\
+                       Annual Factors: January-December\n\
+                       ,Mkt-RF,SMB,HML,RF\n\
+                       1927,   29.47,   -2.46,   -3.75,    3.12\n";
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("F-F_Research_Data_Factors.CSV");
+        fs::write(&path, content).unwrap();
+
+        let rf = RiskFree::FamaFrench(path)
+            .series(&array![[192608], [192607], [192609]])
+            .unwrap();
+
+        assert!((rf[0] - 0.0025).abs() < 1e-12);
+        assert!((rf[1] - 0.0022).abs() < 1e-12);
+        assert!(rf[2].is_nan());
+    }
+
+    #[test]
+    fn test_user_supplied_length_mismatch() {
+        let rf = RiskFree::UserSupplied {
+            dates: vec![200001, 200002],
+            rates: vec![0.001],
+        };
+
+        assert!(rf.series(&array![[200001]]).is_err());
+    }
+}
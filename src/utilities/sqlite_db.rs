@@ -0,0 +1,325 @@
+use anyhow::{anyhow, Context, Result};
+use ndarray::Array2;
+use polars::prelude::*;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, ToSql};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// SQLite database holding matrices as (date, permno, value) tables, so that a
+/// characteristic can be queried for a subset of permnos or months with SQL instead of
+/// deserializing the whole matrix.
+pub struct SqliteDB {
+    conn: Connection,
+}
+
+impl SqliteDB {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open SQLite database: {:?}", path))?;
+        Ok(SqliteDB { conn })
+    }
+
+    /// Opens a temporary database in memory.
+    pub fn open_in_memory() -> Result<Self> {
+        Ok(SqliteDB {
+            conn: Connection::open_in_memory()?,
+        })
+    }
+
+    /// Executes one or more SQL statements without parameters.
+    pub fn execute(&self, sql: &str) -> Result<()> {
+        self.conn.execute_batch(sql)?;
+        Ok(())
+    }
+
+    /// Executes a single SQL statement with positional parameters (`?1`, `?2`, ...) and
+    /// returns the number of rows it inserted, updated or deleted.
+    pub fn execute_with_params(&self, sql: &str, params: &[&dyn ToSql]) -> Result<usize> {
+        Ok(self.conn.execute(sql, params)?)
+    }
+
+    /// Runs a query and collects its rows into a DataFrame, with one Series per column:
+    /// INTEGER columns become `i64`, REAL columns `f64` and TEXT columns strings, with
+    /// SQL NULLs as nulls. A column mixing integers and reals is read as `f64`, and a
+    /// column that is NULL throughout takes the type of its declaration, `i64` if any.
+    pub fn query_df(&self, sql: &str, params: &[&dyn ToSql]) -> Result<DataFrame> {
+        let mut stmt = self.conn.prepare(sql)?;
+        let declared: Vec<(String, Option<String>)> = stmt
+            .columns()
+            .iter()
+            .map(|column| {
+                (
+                    column.name().to_string(),
+                    column.decl_type().map(str::to_uppercase),
+                )
+            })
+            .collect();
+        let mut values: Vec<Vec<Value>> = vec![Vec::new(); declared.len()];
+        let mut rows = stmt.query(params)?;
+        while let Some(row) = rows.next()? {
+            for (i, column) in values.iter_mut().enumerate() {
+                column.push(row.get::<_, Value>(i)?);
+            }
+        }
+        let columns = declared
+            .into_iter()
+            .zip(values)
+            .map(|((name, decl_type), values)| {
+                values_to_column(&name, decl_type.as_deref(), values)
+            })
+            .collect::<Result<Vec<Column>>>()?;
+        Ok(DataFrame::new(columns)?)
+    }
+
+    /// Saves a matrix into the table `name`, replacing any previous one, with one row per
+    /// non-NaN entry keyed by `(date, permno)`. The row and column indices are saved in
+    /// the tables `<name>_dates` and `<name>_permnos` so that [`SqliteDB::load_matrix`]
+    /// restores the full shape. Everything is inserted in a single transaction.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Table name, made of ASCII letters, digits and underscores.
+    /// * `m` - Matrix (nMonths x nStocks).
+    /// * `row_index` - Dates of the rows in `YYYYMM` format.
+    /// * `col_index` - Permnos of the columns.
+    pub fn save_matrix(
+        &self,
+        name: &str,
+        m: &Array2<f64>,
+        row_index: &[i32],
+        col_index: &[i32],
+    ) -> Result<()> {
+        check_table_name(name)?;
+        if m.dim() != (row_index.len(), col_index.len()) {
+            return Err(anyhow!(
+                "Matrix {} is {:?} but has {} dates and {} permnos",
+                name,
+                m.dim(),
+                row_index.len(),
+                col_index.len()
+            ));
+        }
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute_batch(&format!(
+            "DROP TABLE IF EXISTS {name};
+             DROP TABLE IF EXISTS {name}_dates;
+             DROP TABLE IF EXISTS {name}_permnos;
+             CREATE TABLE {name} (
+                 date INTEGER NOT NULL,
+                 permno INTEGER NOT NULL,
+                 value REAL NOT NULL,
+                 PRIMARY KEY (date, permno)
+             );
+             CREATE TABLE {name}_dates (position INTEGER PRIMARY KEY, date INTEGER NOT NULL);
+             CREATE TABLE {name}_permnos (position INTEGER PRIMARY KEY, permno INTEGER NOT NULL);"
+        ))?;
+        {
+            let mut insert_date = tx.prepare(&format!(
+                "INSERT INTO {name}_dates (position, date) VALUES (?1, ?2)"
+            ))?;
+            for (i, date) in row_index.iter().enumerate() {
+                insert_date.execute(params![i as i64, date])?;
+            }
+            let mut insert_permno = tx.prepare(&format!(
+                "INSERT INTO {name}_permnos (position, permno) VALUES (?1, ?2)"
+            ))?;
+            for (j, permno) in col_index.iter().enumerate() {
+                insert_permno.execute(params![j as i64, permno])?;
+            }
+            let mut insert_value = tx.prepare(&format!(
+                "INSERT INTO {name} (date, permno, value) VALUES (?1, ?2, ?3)"
+            ))?;
+            for ((t, j), &value) in m.indexed_iter() {
+                if !value.is_nan() {
+                    insert_value.execute(params![row_index[t], col_index[j], value])?;
+                }
+            }
+        }
+        tx.commit()
+            .with_context(|| format!("Failed to save matrix {}", name))?;
+        Ok(())
+    }
+
+    /// Loads a matrix saved by [`SqliteDB::save_matrix`], with NaN for the entries
+    /// without a row in the table.
+    pub fn load_matrix(&self, name: &str) -> Result<Array2<f64>> {
+        check_table_name(name)?;
+        let dates = self.index_positions(&format!("{name}_dates"), "date")?;
+        let permnos = self.index_positions(&format!("{name}_permnos"), "permno")?;
+        let mut m = Array2::from_elem((dates.len(), permnos.len()), f64::NAN);
+
+        let mut select = self
+            .conn
+            .prepare(&format!("SELECT date, permno, value FROM {name}"))?;
+        let rows = select.query_map([], |row| {
+            Ok((
+                row.get::<_, i32>(0)?,
+                row.get::<_, i32>(1)?,
+                row.get::<_, f64>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (date, permno, value) = row?;
+            if let (Some(&t), Some(&j)) = (dates.get(&date), permnos.get(&permno)) {
+                m[[t, j]] = value;
+            }
+        }
+        Ok(m)
+    }
+
+    /// Maps the values of an index table to their positions.
+    fn index_positions(&self, table: &str, column: &str) -> Result<HashMap<i32, usize>> {
+        let mut select = self
+            .conn
+            .prepare(&format!("SELECT position, {column} FROM {table}"))
+            .with_context(|| format!("No saved matrix index {}", table))?;
+        let positions = select
+            .query_map([], |row| {
+                Ok((row.get::<_, i32>(1)?, row.get::<_, i64>(0)? as usize))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(positions)
+    }
+}
+
+/// Builds a typed column from the SQLite values of a query column, see
+/// [`SqliteDB::query_df`].
+fn values_to_column(name: &str, decl_type: Option<&str>, values: Vec<Value>) -> Result<Column> {
+    let has = |pattern: fn(&Value) -> bool| values.iter().any(pattern);
+    let dtype = if has(|v| matches!(v, Value::Blob(_))) {
+        return Err(anyhow!(
+            "Column {} holds BLOBs, which are not supported",
+            name
+        ));
+    } else if has(|v| matches!(v, Value::Text(_))) {
+        DataType::String
+    } else if has(|v| matches!(v, Value::Real(_))) {
+        DataType::Float64
+    } else if has(|v| matches!(v, Value::Integer(_))) {
+        DataType::Int64
+    } else {
+        // Same affinity rules as SQLite for the declared type
+        match decl_type {
+            Some(t) if t.contains("CHAR") || t.contains("CLOB") || t.contains("TEXT") => {
+                DataType::String
+            }
+            Some(t) if t.contains("REAL") || t.contains("FLOA") || t.contains("DOUB") => {
+                DataType::Float64
+            }
+            _ => DataType::Int64,
+        }
+    };
+    let name = PlSmallStr::from(name);
+    let series = match dtype {
+        DataType::String => {
+            let values = values
+                .into_iter()
+                .map(|v| match v {
+                    Value::Null => Ok(None),
+                    Value::Text(text) => Ok(Some(text)),
+                    other => Err(anyhow!("Column {} mixes text and {:?}", name, other)),
+                })
+                .collect::<Result<Vec<Option<String>>>>()?;
+            Series::new(name, values)
+        }
+        DataType::Float64 => {
+            let values: Vec<Option<f64>> = values
+                .into_iter()
+                .map(|v| match v {
+                    Value::Integer(x) => Some(x as f64),
+                    Value::Real(x) => Some(x),
+                    _ => None,
+                })
+                .collect();
+            Series::new(name, values)
+        }
+        _ => {
+            let values: Vec<Option<i64>> = values
+                .into_iter()
+                .map(|v| match v {
+                    Value::Integer(x) => Some(x),
+                    _ => None,
+                })
+                .collect();
+            Series::new(name, values)
+        }
+    };
+    Ok(series.into())
+}
+
+/// Table names are interpolated into the SQL, so only plain identifiers are accepted.
+fn check_table_name(name: &str) -> Result<()> {
+    let valid = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid matrix table name: {:?}", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn test_matrix_round_trip_and_query() {
+        let db = SqliteDB::open_in_memory().unwrap();
+        let me = array![[1.0, f64::NAN, 3.0], [4.0, 5.0, f64::NAN]];
+
+        db.save_matrix("me", &me, &[200001, 200002], &[10001, 10002, 10003])
+            .unwrap();
+
+        let loaded = db.load_matrix("me").unwrap();
+        assert_eq!(loaded.dim(), (2, 3));
+        assert!(loaded[[0, 1]].is_nan() && loaded[[1, 2]].is_nan());
+        assert_eq!(loaded[[1, 1]], 5.0);
+
+        let total: f64 = db
+            .conn
+            .query_row(
+                "SELECT SUM(value) FROM me WHERE permno IN (10001, 10003)",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(total, 8.0);
+
+        assert_eq!(
+            db.execute_with_params(
+                "UPDATE me SET value = value * ?1 WHERE date = ?2",
+                &[&2.0, &200002],
+            )
+            .unwrap(),
+            2
+        );
+        assert_eq!(db.load_matrix("me").unwrap()[[1, 1]], 10.0);
+
+        let df = db
+            .query_df(
+                "SELECT permno, value, CAST(date AS TEXT) AS yyyymm, NULL AS note \
+                 FROM me WHERE permno = ?1 ORDER BY date",
+                &[&10001],
+            )
+            .unwrap();
+        assert_eq!(df.shape(), (2, 4));
+        assert_eq!(df.column("permno").unwrap().dtype(), &DataType::Int64);
+        assert_eq!(df.column("value").unwrap().f64().unwrap().get(1), Some(8.0));
+        assert_eq!(
+            df.column("yyyymm").unwrap().str().unwrap().get(0),
+            Some("200001")
+        );
+        assert_eq!(df.column("note").unwrap().null_count(), 2);
+
+        assert!(db
+            .save_matrix("me; DROP TABLE me", &me, &[1, 2], &[1, 2, 3])
+            .is_err());
+        assert!(db.save_matrix("bad_shape", &me, &[1], &[1, 2, 3]).is_err());
+    }
+}
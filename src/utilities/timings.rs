@@ -0,0 +1,122 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Lightweight collector of elapsed times for the pipeline stages and for each variable.
+///
+/// Stages are expected to be sequential and to cover the pipeline, so their durations
+/// add up to roughly [`Timings::total`]. Variable timings are a breakdown of the stage in
+/// which the variables are processed and are reported separately.
+#[derive(Debug, Clone)]
+pub struct Timings {
+    started: Instant,
+    stages: Vec<(String, Duration)>,
+    variables: Vec<(String, Duration)>,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timings {
+    /// Starts a new collector; the total is measured from this point.
+    pub fn new() -> Self {
+        Timings {
+            started: Instant::now(),
+            stages: Vec::new(),
+            variables: Vec::new(),
+        }
+    }
+
+    /// Records a stage that began at `since`.
+    pub fn record_stage(&mut self, label: &str, since: Instant) {
+        self.stages.push((label.to_string(), since.elapsed()));
+    }
+
+    /// Records the processing of a variable that began at `since`.
+    pub fn record_variable(&mut self, var_name: &str, since: Instant) {
+        self.variables.push((var_name.to_string(), since.elapsed()));
+    }
+
+    pub fn stages(&self) -> &[(String, Duration)] {
+        &self.stages
+    }
+
+    pub fn variables(&self) -> &[(String, Duration)] {
+        &self.variables
+    }
+
+    /// Sum of the recorded stage durations.
+    pub fn stage_total(&self) -> Duration {
+        self.stages.iter().map(|(_, d)| *d).sum()
+    }
+
+    /// Time elapsed since the collector was created.
+    pub fn total(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .stages
+            .iter()
+            .chain(self.variables.iter())
+            .map(|(label, _)| label.len() + 2)
+            .max()
+            .unwrap_or(0)
+            .max(5);
+        writeln!(f, "{:<width$} {:>10}", "Stage", "Seconds")?;
+        for (label, duration) in &self.stages {
+            writeln!(f, "{:<width$} {:>10.3}", label, duration.as_secs_f64())?;
+        }
+        for (label, duration) in &self.variables {
+            writeln!(
+                f,
+                "  {:<w$} {:>10.3}",
+                label,
+                duration.as_secs_f64(),
+                w = width - 2
+            )?;
+        }
+        write!(
+            f,
+            "{:<width$} {:>10.3}",
+            "Total",
+            self.total().as_secs_f64()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn test_stage_timings_sum_to_total() {
+        let mut timings = Timings::new();
+        for label in ["load", "pivot"] {
+            let since = Instant::now();
+            sleep(Duration::from_millis(20));
+            timings.record_stage(label, since);
+        }
+        let since = Instant::now();
+        sleep(Duration::from_millis(5));
+        timings.record_variable("prc", since);
+
+        let stage_total = timings.stage_total().as_secs_f64();
+        let total = timings.total().as_secs_f64();
+        assert_eq!(timings.stages().len(), 2);
+        assert!(stage_total >= 0.04);
+        assert!(stage_total <= total);
+        assert!(total - stage_total < 0.02);
+
+        let table = timings.to_string();
+        assert!(table.contains("pivot"));
+        assert!(table.contains("  prc"));
+        assert!(table.lines().last().unwrap().starts_with("Total"));
+    }
+}